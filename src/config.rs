@@ -1,13 +1,46 @@
 #![allow(dead_code)]
 
+use std::collections::HashMap;
+use std::net::IpAddr;
+
 use chrono::prelude::*;
 use serde_derive::{Serialize, Deserialize};
 
 use super::proxy::item::*;
+use super::proxy::canonical::*;
+use super::proxy::content_type;
+use super::proxy::upstream::*;
+use super::proxy::bypass::*;
+use super::proxy::auth::*;
+use super::proxy::condition::NetworkCondition;
+use super::proxy::header::*;
+use super::proxy::body::BodyRule;
+use super::proxy::cache::CacheConfig;
+use super::util::network::*;
 
 #[derive(Serialize, Deserialize, Debug)]
 pub struct LopxyConfig {
     timestamp: i64,
+    #[serde(default)]
+    sort_query_params: bool,
+    #[serde(default)]
+    https_interception: bool,
+    #[serde(default)]
+    socks_username: Option<String>,
+    #[serde(default)]
+    socks_password: Option<String>,
+    #[serde(default)]
+    allowed_clients: Vec<String>,
+    #[serde(default)]
+    users: Vec<ProxyUser>,
+    #[serde(default)]
+    content_type_extensions: HashMap<String, String>,
+    #[serde(default)]
+    upstream: UpstreamConfig,
+    #[serde(default)]
+    host_header_rules: Vec<HostHeaderRules>,
+    #[serde(default)]
+    cache: CacheConfig,
     proxy_items: Vec<ProxyItem>
 }
 
@@ -15,6 +48,16 @@ impl LopxyConfig {
     pub fn new() -> LopxyConfig {
         LopxyConfig {
             timestamp: 0,
+            sort_query_params: false,
+            https_interception: false,
+            socks_username: None,
+            socks_password: None,
+            allowed_clients: vec![],
+            users: vec![],
+            content_type_extensions: HashMap::new(),
+            upstream: UpstreamConfig::default(),
+            host_header_rules: vec![],
+            cache: CacheConfig::default(),
             proxy_items: vec![]
         }
     }
@@ -27,12 +70,36 @@ impl LopxyConfig {
             }
         };
 
-        match toml::from_str(&config) {
+        let mut config: LopxyConfig = match toml::from_str(&config) {
             Ok(c) => c,
             Err(_) => {
-                LopxyConfig::new()
+                return LopxyConfig::new();
             }
+        };
+
+        config.allowed_clients.iter().
+            filter(|network: &&String| IpNetwork::parse(network).is_none()).
+            for_each(|network: &String| eprintln!("invalid allowed client network : {}", network));
+
+        config.host_header_rules.iter().
+            filter(|host_rules: &&HostHeaderRules| !host_rules.verify()).
+            for_each(|host_rules: &HostHeaderRules| eprintln!("invalid header rules of hosts : {}", host_rules.hosts.join(", ")));
+
+        if !config.cache.verify() {
+            eprintln!("invalid cache urls : {}", config.cache.urls.join(", "));
         }
+
+        let sort_query_params = config.sort_query_params;
+        config.proxy_items.iter_mut().for_each(|item: &mut ProxyItem| {
+            let resource_url = LopxyConfig::canonical_resource_url(item.match_kind(), item.resource_url(), sort_query_params);
+            item.update_resource_url(&resource_url);
+
+            if !item.compile_matcher() {
+                eprintln!("invalid proxy item pattern : {}", item.resource_url());
+            }
+        });
+
+        config
     }
 
     pub fn save(&mut self, path: &str) -> std::io::Result<()> {
@@ -54,6 +121,166 @@ impl LopxyConfig {
         self.timestamp = Local::now().timestamp_millis();
     }
 
+    pub fn sort_query_params(&self) -> bool {
+        self.sort_query_params
+    }
+
+    ///
+    /// Get the user defined extension to content type map, used by `auto` content type
+    ///
+    pub fn content_type_extensions<'a>(&'a self) -> &'a HashMap<String, String> {
+        &self.content_type_extensions
+    }
+
+    ///
+    /// Whether https connections to hosts with matching proxy items are decrypted
+    ///
+    pub fn https_interception(&self) -> bool {
+        self.https_interception
+    }
+
+    ///
+    /// Get the username and password required by socks5 proxy, None if no authentication is required
+    ///
+    pub fn socks_credential(&self) -> Option<(String, String)> {
+        let username = self.socks_username.as_ref().filter(|username: &&String| !username.is_empty())?;
+        Some((username.clone(), self.socks_password.clone().unwrap_or_default()))
+    }
+
+    ///
+    /// Check whether the client at `address` may use the proxy servers and web manager
+    ///
+    /// # Notes
+    /// Loopback clients are always allowed, the others must be in one of `allowed_clients` networks
+    pub fn is_client_allowed(&self, address: &IpAddr) -> bool {
+        if canonical_ip(address).is_loopback() {
+            return true;
+        }
+
+        self.allowed_clients.iter().
+            filter_map(|network: &String| IpNetwork::parse(network)).
+            any(|network: IpNetwork| network.contains(address))
+    }
+
+    ///
+    /// Whether proxy clients must authenticate with `Proxy-Authorization`, it's required once any user is defined
+    ///
+    pub fn proxy_auth_required(&self) -> bool {
+        !self.users.is_empty()
+    }
+
+    ///
+    /// Check the credential of proxy user
+    ///
+    pub fn authenticate_proxy_user(&self, username: &str, password: &str) -> bool {
+        self.users.iter().any(|user: &ProxyUser| user.verify(username, password))
+    }
+
+    ///
+    /// Check whether the proxy item can be used by the requests of `username`
+    ///
+    /// # Notes
+    /// Rule sets are ignored if no user is defined, an anonymous request only uses the items without rule set
+    fn is_proxy_item_usable(&self, item: &ProxyItem, username: Option<&str>) -> bool {
+        if item.rule_set().is_none() || self.users.is_empty() {
+            return true;
+        }
+
+        let username = match username {
+            Some(username) => username,
+            None => {
+                return false;
+            }
+        };
+
+        self.users.iter().
+            filter(|user: &&ProxyUser| user.username.eq(username)).
+            any(|user: &ProxyUser| user.can_use(item))
+    }
+
+    ///
+    /// Get the upstream proxies of lopxy config, they take the place of the ones from environment variables if any is set
+    ///
+    pub fn upstream<'a>(&'a self) -> &'a UpstreamConfig {
+        &self.upstream
+    }
+
+    ///
+    /// Get the hosts which skip the upstream proxy, it's the `no_proxy` of `[upstream]`
+    ///
+    pub fn bypass_list<'a>(&'a self) -> &'a Vec<String> {
+        &self.upstream.no_proxy
+    }
+
+    ///
+    /// Replace the bypass list, fail if any entry is invalid
+    ///
+    pub fn set_bypass_list(&mut self, entries: Vec<String>) -> bool {
+        let entries: Vec<String> = entries.iter().map(|entry: &String| entry.trim().to_string()).filter(|entry: &String| !entry.is_empty()).collect();
+
+        if let Some(entry) = entries.iter().find(|entry: &&String| BypassEntry::parse(entry).is_none()) {
+            eprintln!("invalid bypass entry : {}", entry);
+            return false;
+        }
+
+        self.upstream.no_proxy = entries;
+        self.update_timestamp();
+
+        true
+    }
+
+    ///
+    /// Check whether any proxy item may match https urls of host
+    ///
+    pub fn has_https_proxy_item(&self, host: &str, port: u16) -> bool {
+        self.proxy_items.iter().any(|item: &ProxyItem| item.may_match_https_host(host, port))
+    }
+
+    ///
+    /// Check whether any host header rules match the host, the https connections to it are intercepted as well
+    ///
+    pub fn has_host_header_rules(&self, host: &str, port: u16) -> bool {
+        self.host_header_rules.iter().any(|host_rules: &HostHeaderRules| host_rules.matches(host, Some(port)))
+    }
+
+    ///
+    /// Check whether the requests to host may be handled by proxy items, host header rules or offline cache
+    ///
+    pub fn may_handle_host(&self, host: &str, port: u16) -> bool {
+        let item_hosts: Vec<String> = self.proxy_items.iter().filter_map(|item: &ProxyItem| item.host_pattern()).collect();
+        BypassList::from_entries(&item_hosts).matches(host, None) || self.has_host_header_rules(host, port) || self.cache.matches(host, Some(port))
+    }
+
+    ///
+    /// Get the offline cache config, it's the `[cache]` of lopxy config
+    ///
+    pub fn cache<'a>(&'a self) -> &'a CacheConfig {
+        &self.cache
+    }
+
+    ///
+    /// Collect the header rules of the request url and its proxy item
+    ///
+    /// # Notes
+    /// The rules of matching hosts go before the ones of proxy item, invalid rules are skipped
+    pub fn header_rules(&self, url: &str, item: Option<&ProxyItem>) -> HeaderRules {
+        let mut rules = HeaderRules::default();
+        let mut collect = |request_headers: &Vec<String>, response_headers: &Vec<String>| {
+            rules.request.extend(request_headers.iter().filter_map(|rule: &String| HeaderRule::parse(rule)));
+            rules.response.extend(response_headers.iter().filter_map(|rule: &String| HeaderRule::parse(rule)));
+        };
+
+        self.host_header_rules.iter().
+            filter(|host_rules: &&HostHeaderRules| host_rules.matches_url(url)).
+            for_each(|host_rules: &HostHeaderRules| collect(&host_rules.request_headers, &host_rules.response_headers));
+
+        if let Some(item) = item {
+            collect(item.request_headers(), item.response_headers());
+        }
+
+        rules
+    }
+
     pub fn proxy_item_list<'a>(&'a self) -> &'a Vec<ProxyItem> {
         &self.proxy_items
     }
@@ -62,40 +289,138 @@ impl LopxyConfig {
         self.proxy_items.len()
     }
 
-    pub fn proxy_redirect(&self, resource_url: &str) -> Option<ProxyItem> {
-        let mut result: Option<ProxyItem> = None;
+    ///
+    /// Find the proxy item for the request url
+    ///
+    /// # Notes
+    /// The precedence is : exact > prefix(the longest prefix wins) > glob > regex,
+    /// items of the same kind are tried in config order, the returned item's
+    /// `proxy_resource_url` already has the captured groups substituted. Items out of
+    /// the rule sets of `username` are skipped
+    pub fn proxy_redirect(&self, resource_url: &str, username: Option<&str>) -> Option<ProxyItem> {
+        let mut item = self.find_proxy_item(resource_url, username)?;
 
-        self.proxy_items.iter().any(|item: &ProxyItem| {
-            if item.resource_url().ne(resource_url) {
-                return false;
+        // `auto` content type of local file and ftp file is guessed from the extension first, the senders sniff the rest
+        let guessable = item.proxy_resource_url().starts_with("file:") || item.proxy_resource_url().starts_with("ftp:");
+        if content_type::is_auto_content_type(item.content_type()) && guessable {
+            let path = super::proxy::request::get_uri_path(item.proxy_resource_url()).unwrap_or_default();
+            if let Some(content_type) = content_type::guess_from_extension(&path, &self.content_type_extensions) {
+                item.update_resource_content_type(&content_type);
             }
+        }
 
-            result = Some(item.clone());
+        Some(item)
+    }
 
-            true
-        });
+    fn find_proxy_item(&self, resource_url: &str, username: Option<&str>) -> Option<ProxyItem> {
+        let exact_resource_url = canonicalize_url_or_keep(resource_url, self.sort_query_params);
+        let resource_url = &canonicalize_url_or_keep(resource_url, false);
 
-        result
-    }
+        let find_by_kind = |match_kind: MatchKind, resource_url: &str| -> Option<ProxyItem> {
+            self.proxy_items.iter().
+                filter(|item: &&ProxyItem| item.match_kind() == match_kind && self.is_proxy_item_usable(item, username)).
+                find_map(|item: &ProxyItem| item.resolve(resource_url))
+        };
 
-    pub fn proxy_item_exists(&self, resource_url: &str) -> bool {
-        self.proxy_items.iter().any(|item: &ProxyItem| item.resource_url().eq(resource_url))
+        if let Some(item) = find_by_kind(MatchKind::Exact, &exact_resource_url) {
+            return Some(item);
+        }
+
+        let mut longest_prefix: Option<&ProxyItem> = None;
+        self.proxy_items.iter().
+            filter(|item: &&ProxyItem| item.match_kind() == MatchKind::Prefix && resource_url.starts_with(item.resource_url())).
+            filter(|item: &&ProxyItem| self.is_proxy_item_usable(item, username)).
+            for_each(|item: &ProxyItem| {
+                if longest_prefix.is_none_or(|longest: &ProxyItem| item.resource_url().len() > longest.resource_url().len()) {
+                    longest_prefix = Some(item);
+                }
+            });
+
+        if let Some(item) = longest_prefix.and_then(|item: &ProxyItem| item.resolve(resource_url)) {
+            return Some(item);
+        }
+
+        find_by_kind(MatchKind::Glob, resource_url).or_else(|| find_by_kind(MatchKind::Regex, resource_url))
     }
 
-    pub fn add_proxy_item(&mut self, resource_url: &str, proxy_resource_url: &str, content_type: &str) -> bool {
-        if self.proxy_item_exists(resource_url) {
-            return false;
+    ///
+    /// Canonicalize the resource url of exact and prefix proxy items, glob and regex patterns are kept as is
+    ///
+    fn canonical_resource_url(match_kind: MatchKind, resource_url: &str, sort_query_params: bool) -> String {
+        match match_kind {
+            MatchKind::Exact => canonicalize_url_or_keep(resource_url, sort_query_params),
+            MatchKind::Prefix => canonicalize_url_or_keep(resource_url, false),
+            MatchKind::Glob | MatchKind::Regex => resource_url.to_string()
         }
+    }
 
+    fn is_same_resource(item: &ProxyItem, resource_url: &str, sort_query_params: bool) -> bool {
+        item.resource_url().eq(resource_url) ||
+            item.resource_url().eq(&LopxyConfig::canonical_resource_url(item.match_kind(), resource_url, sort_query_params))
+    }
+
+    pub fn proxy_item<'a>(&'a self, resource_url: &str) -> Option<&'a ProxyItem> {
+        self.proxy_items.iter().find(|item: &&ProxyItem| LopxyConfig::is_same_resource(item, resource_url, self.sort_query_params))
+    }
+
+    pub fn proxy_item_exists(&self, resource_url: &str) -> bool {
+        self.proxy_items.iter().any(|item: &ProxyItem| LopxyConfig::is_same_resource(item, resource_url, self.sort_query_params))
+    }
+
+    ///
+    /// Verify the proxy item, the pattern of glob and regex items must compile
+    ///
+    fn verify_proxy_item(&self, item: &mut ProxyItem) -> bool {
         let verify_url = |url: &str| -> bool {
             url::Url::parse(url).is_ok()
         };
 
-        if !verify_url(resource_url) || !verify_url(proxy_resource_url) {
+        let resource_url_valid = match item.match_kind() {
+            MatchKind::Exact | MatchKind::Prefix => verify_url(item.resource_url()),
+            MatchKind::Glob | MatchKind::Regex => true
+        };
+
+        // the proxy resource of mock item is never requested
+        let target_valid = match item.mock_response() {
+            Some(mock_response) => mock_response.verify(),
+            None => item.requests_original_resource() || verify_url(item.proxy_resource_url())
+        };
+
+        // the client of redirect mode fetches the proxy resource itself, so that it must be a web url
+        let redirect_valid = item.mode() != ProxyMode::Redirect || {
+            is_redirect_status(item.redirect_status()) &&
+                ["http", "https"].iter().any(|scheme: &&str| super::proxy::request::is_uri_scheme(item.proxy_resource_url(), scheme))
+        };
+
+        if !resource_url_valid ||
+            !target_valid ||
+            !redirect_valid ||
+            item.network_condition().is_some_and(|condition: &NetworkCondition| !condition.verify()) ||
+            HeaderRule::parse_all(item.request_headers()).is_none() ||
+            HeaderRule::parse_all(item.response_headers()).is_none() ||
+            BodyRule::parse_all(item.body_rules()).is_none() ||
+            !item.mirror_urls().iter().all(|url: &String| verify_url(url)) ||
+            item.upstream_proxy().is_some_and(|upstream_proxy: &str| UpstreamRoute::parse(upstream_proxy).is_none()) ||
+            match_status_list(item.fallback_status(), 0).is_none() {
+            return false;
+        }
+
+        let resource_url = LopxyConfig::canonical_resource_url(item.match_kind(), item.resource_url(), self.sort_query_params);
+        item.update_resource_url(&resource_url);
+
+        item.compile_matcher()
+    }
+
+    pub fn add_proxy_item(&mut self, mut item: ProxyItem) -> bool {
+        if !self.verify_proxy_item(&mut item) {
             return false;
         }
 
-        self.proxy_items.push(ProxyItem::new(resource_url, proxy_resource_url, content_type));
+        if self.proxy_item_exists(item.resource_url()) {
+            return false;
+        }
+
+        self.proxy_items.push(item);
         self.update_timestamp();
 
         true
@@ -106,32 +431,35 @@ impl LopxyConfig {
             return false;
         }
 
-        self.proxy_items.retain(|item: &ProxyItem| item.resource_url().ne(resource_url));
+        let sort_query_params = self.sort_query_params;
+        self.proxy_items.retain(|item: &ProxyItem| !LopxyConfig::is_same_resource(item, resource_url, sort_query_params));
         self.update_timestamp();
 
         true
     }
 
-    pub fn modify_proxy_item(&mut self, resource_url: &str, proxy_resource_url: &str, content_type: &str) -> bool {
-        if url::Url::parse(proxy_resource_url).is_err() {
+    ///
+    /// Replace the proxy item which has the same resource url, add it if not exists
+    ///
+    pub fn modify_proxy_item(&mut self, mut item: ProxyItem) -> bool {
+        if !self.verify_proxy_item(&mut item) {
             return false;
         }
 
-        if self.proxy_items.iter_mut().any(|item: &mut ProxyItem| {
-            if !item.resource_url().eq(resource_url) {
-                return false;
-            }
-
-            item.update_proxy_resource_url(proxy_resource_url);
-            item.update_resource_content_type(content_type);
+        let exists = match self.proxy_items.iter_mut().find(|exists: &&mut ProxyItem| exists.resource_url().eq(item.resource_url())) {
+            Some(exists) => {
+                *exists = item.clone();
+                true
+            },
+            None => false
+        };
 
-            true
-        }) {
-            self.update_timestamp();
-        } else {
-            return self.add_proxy_item(resource_url, proxy_resource_url, content_type);
+        if !exists {
+            return self.add_proxy_item(item);
         }
 
+        self.update_timestamp();
+
         true
     }
-}
\ No newline at end of file
+}
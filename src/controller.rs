@@ -34,8 +34,12 @@ impl manager::controller::LopxyManagerServerController for LopxyController {
         self.env.load_config().proxy_item_list()
     }
 
-    fn add_proxy_item(&mut self, resource_url: &str, proxy_resource_url: &str, content_type: &str) -> bool {
-        let result = self.env.load_config().add_proxy_item(resource_url, proxy_resource_url, content_type);
+    fn find_proxy_item(&mut self, resource_url: &str) -> Option<ProxyItem> {
+        self.env.load_config().proxy_item(resource_url).cloned()
+    }
+
+    fn add_proxy_item(&mut self, item: ProxyItem) -> bool {
+        let result = self.env.load_config().add_proxy_item(item);
         self.env.save_config();
         result
     }
@@ -46,8 +50,8 @@ impl manager::controller::LopxyManagerServerController for LopxyController {
         result
     }
 
-    fn modify_proxy_item(&mut self, resource_url: &str, proxy_resource_url: &str, content_type: &str) -> bool {
-        let result = self.env.load_config().modify_proxy_item(resource_url, proxy_resource_url, content_type);
+    fn modify_proxy_item(&mut self, item: ProxyItem) -> bool {
+        let result = self.env.load_config().modify_proxy_item(item);
         self.env.save_config();
         result
     }
@@ -68,23 +72,134 @@ impl manager::controller::LopxyManagerServerController for LopxyController {
     fn lopxy_status(&mut self, config_timestamp: i64, status_log_timestamp: i64) -> String {
         self.env.lopxy_status(config_timestamp, status_log_timestamp)
     }
+
+    fn certificate_authority_pem(&mut self) -> Option<String> {
+        Some(self.env.certificate_authority()?.certificate_pem().to_string())
+    }
+
+    fn regenerate_certificate_authority(&mut self) -> bool {
+        self.env.regenerate_certificate_authority()
+    }
+
+    fn pac_script(&mut self, hostname: Option<&str>) -> String {
+        self.env.pac_script(hostname)
+    }
+
+    fn is_client_allowed(&mut self, address: &std::net::IpAddr) -> bool {
+        self.env.load_config().is_client_allowed(address)
+    }
+
+    fn bypass_list(&mut self) -> Vec<String> {
+        self.env.load_config().bypass_list().clone()
+    }
+
+    fn set_bypass_list(&mut self, entries: Vec<String>) -> bool {
+        let result = self.env.load_config().set_bypass_list(entries);
+        self.env.save_config();
+        result
+    }
+
+    fn cache_entries(&mut self) -> Vec<proxy::cache::CacheEntry> {
+        self.env.open_response_cache().entries()
+    }
+
+    fn purge_cache(&mut self, pattern: Option<&str>) -> Option<usize> {
+        self.env.open_response_cache().purge(pattern)
+    }
 }
 
 impl proxy::controller::LopxyProxyServerController for LopxyController {
-    fn proxy_redirect(&mut self, resource_url: &str) -> Option<ProxyItem> {
-        self.env.load_config().proxy_redirect(resource_url)
+    fn proxy_redirect(&mut self, resource_url: &str, username: Option<&str>) -> Option<ProxyItem> {
+        self.env.load_config().proxy_redirect(resource_url, username)
     }
 
-    fn report_proxy_request_status(&mut self, request_url: &str, status: u16, pid: u32) {
+    fn report_proxy_request_status(&mut self, request_url: &str, status: u16, pid: u32, username: Option<&str>) {
         let exception_status_desc = proxy::response::get_exception_request_status_desc(status);
         if exception_status_desc.is_none() {
             return;
         }
-        self.env.report_proxy_request_status(pid, request_url.to_string(), exception_status_desc.unwrap());
+        self.env.report_proxy_request_status(pid, username, request_url.to_string(), exception_status_desc.unwrap());
     }
 
-    fn report_connection_error(&mut self, host: &str, request_url: Option<String>, err: &dyn std::error::Error, pid: u32) {
+    fn report_connection_error(&mut self, host: &str, request_url: Option<String>, err: &dyn std::error::Error, pid: u32, username: Option<&str>) {
         let path = request_url.unwrap_or(host.to_string());
-        self.env.report_proxy_request_status(pid, path, err.to_string());
+        self.env.report_proxy_request_status(pid, username, path, err.to_string());
+    }
+
+    fn report_fallback(&mut self, request_url: &str, reason: Option<String>, pid: u32, username: Option<&str>) {
+        self.env.report_fallback(pid, username, request_url.to_string(), reason);
+    }
+
+    fn report_network_condition(&mut self, request_url: &str, action: String, pid: u32, username: Option<&str>) {
+        self.env.report_proxy_request_status(pid, username, request_url.to_string(), format!("network condition : {}", action));
+    }
+
+    fn report_body_rewrite(&mut self, request_url: &str, result: String, pid: u32, username: Option<&str>) {
+        self.env.report_proxy_request_status(pid, username, request_url.to_string(), format!("body rewrite : {}", result));
+    }
+
+    fn report_cache(&mut self, request_url: &str, event: String, pid: u32, username: Option<&str>) {
+        self.env.report_proxy_request_status(pid, username, request_url.to_string(), format!("cache : {}", event));
+    }
+
+    fn mirrored_proxy_items(&mut self) -> Vec<ProxyItem> {
+        self.env.load_config().proxy_item_list().iter().
+            filter(|item: &&ProxyItem| !item.mirror_urls().is_empty()).
+            cloned().
+            collect()
+    }
+
+    fn order_proxy_targets(&mut self, item: &ProxyItem) -> Vec<ProxyTarget> {
+        self.env.target_health.order(item.targets(), item.mirror_strategy())
+    }
+
+    fn report_target_state(&mut self, target: &str, healthy: bool, latency: Option<u64>, error: Option<String>) {
+        self.env.target_health.update(target, healthy, latency, error);
+    }
+
+    fn retain_target_states(&mut self, targets: &std::collections::HashSet<String>) {
+        self.env.target_health.retain(targets);
+    }
+
+    fn https_interception(&mut self, host: &str, port: u16) -> Option<std::sync::Arc<rustls::ServerConfig>> {
+        let config = self.env.load_config();
+        let intercepted = config.has_https_proxy_item(host, port) || config.has_host_header_rules(host, port) || config.cache().matches(host, Some(port));
+        if !config.https_interception() || !intercepted {
+            return None;
+        }
+
+        self.env.https_server_config(host)
+    }
+
+    fn may_handle_host(&mut self, host: &str, port: u16) -> bool {
+        self.env.load_config().may_handle_host(host, port)
+    }
+
+    fn socks_credential(&mut self) -> Option<(String, String)> {
+        self.env.load_config().socks_credential()
+    }
+
+    fn proxy_auth_required(&mut self) -> bool {
+        self.env.load_config().proxy_auth_required()
+    }
+
+    fn authenticate_proxy_user(&mut self, username: &str, password: &str) -> bool {
+        self.env.load_config().authenticate_proxy_user(username, password)
+    }
+
+    fn is_client_allowed(&mut self, address: &std::net::IpAddr) -> bool {
+        self.env.load_config().is_client_allowed(address)
+    }
+
+    fn header_rules(&mut self, url: &str, item: Option<&ProxyItem>) -> proxy::header::HeaderRules {
+        self.env.load_config().header_rules(url, item)
+    }
+
+    fn upstream_route(&mut self, item: Option<&ProxyItem>, url: &str) -> Option<proxy::upstream::UpstreamRoute> {
+        self.env.upstream_route(item, url)
+    }
+
+    fn response_cache(&mut self, url: &str) -> Option<std::sync::Arc<proxy::cache::ResponseCache>> {
+        self.env.response_cache(url)
     }
 }
\ No newline at end of file
@@ -1,7 +1,7 @@
 #![allow(dead_code)]
 
 use std::sync::Mutex;
-use std::collections::VecDeque;
+use std::collections::{HashSet, VecDeque};
 
 use chrono::prelude::*;
 use sysinfo::SystemExt;
@@ -20,6 +20,7 @@ struct LopxyInstance {
     pid: u32,
     web_manager_port: u32,
     proxy_port: u32,
+    web_manager_address: Option<String>,
 }
 
 impl LopxyInstance {
@@ -73,10 +74,14 @@ impl LopxyInstance {
             Err(_) => return None,
         };
 
+        // absent in the records of old versions
+        let web_manager_address = lopxy_instance_config.get(3).map(|address: &&str| address.to_string());
+
         Some(LopxyInstance {
             pid,
             web_manager_port,
             proxy_port,
+            web_manager_address,
         })
     }
 
@@ -87,13 +92,15 @@ impl LopxyInstance {
         config_dir: &std::path::PathBuf,
         web_manager_port: u32,
         proxy_port: u32,
+        web_manager_address: &str,
     ) -> std::io::Result<()> {
         let config_path = LopxyInstance::lopxy_pid_path(config_dir);
         let contents = format!(
-            "{}\r\n{}\r\n{}",
+            "{}\r\n{}\r\n{}\r\n{}",
             std::process::id(),
             web_manager_port,
-            proxy_port
+            proxy_port,
+            web_manager_address
         );
         std::fs::write(config_path, contents)
     }
@@ -111,7 +118,10 @@ impl LopxyInstance {
     }
 
     pub fn web_manager_url(&self) -> String {
-        format!("127.0.0.1:{}", self.web_manager_port)
+        match &self.web_manager_address {
+            Some(web_manager_address) => web_manager_address.clone(),
+            None => format!("127.0.0.1:{}", self.web_manager_port)
+        }
     }
 
     pub fn proxy_url(&self) -> String {
@@ -124,6 +134,8 @@ pub struct LopxyProxyRequestStatus {
     pub timestamp: i64,
     pub pid: u32,
     pub bin_name: String,
+    #[serde(default)]
+    pub username: String,
     pub path: String,
     pub status: String,
 }
@@ -138,6 +150,7 @@ impl LopxyProxyRequestStatus {
             timestamp: self.timestamp,
             pid: self.pid,
             bin_name: self.bin_name.clone(),
+            username: self.username.clone(),
             path: self.path.clone(),
             status: self.status.clone()
         }
@@ -149,12 +162,14 @@ pub struct LopxyStatusReport {
     pub success: bool,
     pub web_manager_port: u32,
     pub proxy_port: u32,
+    pub socks_port: u32,
     pub proxy_enabled: bool,
     pub updated: bool,
     pub status_log_timestamp: i64,
     pub request_status_logs: Vec<LopxyProxyRequestStatus>,
     pub config_timestamp: i64,
-    pub proxy_items: Vec<proxy::item::ProxyItem>
+    pub proxy_items: Vec<proxy::item::ProxyItem>,
+    pub target_states: Vec<proxy::health::TargetState>
 }
 
 pub struct LopxyEnv {
@@ -164,7 +179,13 @@ pub struct LopxyEnv {
     pub command_args: LopxyCommand,
     pub proxy_shutdown: proxy::async_shutdown::Shutdown,
     pub request_status_logs: Mutex<VecDeque<LopxyProxyRequestStatus>>,
-    pub status_refresh_timestamp: i64
+    pub status_refresh_timestamp: i64,
+    pub fallback_paths: HashSet<String>,
+    pub target_health: proxy::health::TargetHealthRegistry,
+    pub certificate_authority: Option<proxy::ca::CertificateAuthority>,
+    pub environment_upstream: Option<proxy::upstream::UpstreamConfig>,
+    pub pac_script: Option<(i64, String, String)>,
+    pub response_cache: Option<(i64, std::sync::Arc<proxy::cache::ResponseCache>)>
 }
 
 impl LopxyEnv {
@@ -181,7 +202,13 @@ impl LopxyEnv {
             command_args: args.command,
             proxy_shutdown: proxy::async_shutdown::Shutdown::new(),
             request_status_logs: Mutex::new(VecDeque::new()),
-            status_refresh_timestamp: 0
+            status_refresh_timestamp: 0,
+            fallback_paths: HashSet::new(),
+            target_health: proxy::health::TargetHealthRegistry::new(),
+            certificate_authority: None,
+            environment_upstream: None,
+            pac_script: None,
+            response_cache: None
         })
     }
 
@@ -213,6 +240,27 @@ impl LopxyEnv {
         }
     }
 
+    pub fn ca_args<'a>(&'a self) -> Option<&'a CaArgs> {
+        match &self.command_args {
+            LopxyCommand::Ca(arg) => Some(arg),
+            _ => None,
+        }
+    }
+
+    pub fn bypass_args<'a>(&'a self) -> Option<&'a BypassArgs> {
+        match &self.command_args {
+            LopxyCommand::Bypass(arg) => Some(arg),
+            _ => None,
+        }
+    }
+
+    pub fn cache_args<'a>(&'a self) -> Option<&'a CacheArgs> {
+        match &self.command_args {
+            LopxyCommand::Cache(arg) => Some(arg),
+            _ => None,
+        }
+    }
+
     pub fn clone_proxy_shutdown(&self) -> proxy::async_shutdown::Shutdown {
         self.proxy_shutdown.clone()
     }
@@ -291,10 +339,27 @@ impl LopxyEnv {
             &self.config_dir,
             start_args.web_manager_port,
             start_args.proxy_port,
+            &start_args.web_manager_address(),
         )
         .expect("record lopxy instance failed");
     }
 
+    ///
+    /// Get the token of web manager api, it's generated on first start
+    ///
+    pub fn manager_token(&self) -> Option<String> {
+        match self.start_args() {
+            Some(_) => match super::manager::auth::load_or_generate_token(&self.config_dir) {
+                Ok(token) => Some(token),
+                Err(err) => {
+                    eprintln!("generate web manager token failed : {}", err);
+                    None
+                }
+            },
+            None => super::manager::auth::load_token(&self.config_dir)
+        }
+    }
+
     ///
     /// Get Web Manager Server Instance URL
     ///
@@ -307,8 +372,8 @@ impl LopxyEnv {
     /// Record proxy request status
     /// 
     /// # Notes
-    /// Only record exception request
-    pub fn report_proxy_request_status(&mut self, pid: u32, path: String, status: String) {
+    /// Only record exception request, `username` is the authenticated proxy user if any
+    pub fn report_proxy_request_status(&mut self, pid: u32, username: Option<&str>, path: String, status: String) {
         let record = &mut *self.request_status_logs.lock().unwrap();
 
         if record.len() >= LopxyProxyRequestStatus::quota() {
@@ -336,11 +401,159 @@ impl LopxyEnv {
             timestamp: self.status_refresh_timestamp,
             pid,
             bin_name: bin_name,
+            username: username.unwrap_or_default().to_string(),
             path,
             status
         });
     }
 
+    ///
+    /// Record fallback event of fallback mode proxy item
+    /// 
+    /// # Notes
+    /// `reason` is None when the original resource works, which is recorded only once after it recovers
+    pub fn report_fallback(&mut self, pid: u32, username: Option<&str>, path: String, reason: Option<String>) {
+        match reason {
+            Some(reason) => {
+                self.fallback_paths.insert(path.clone());
+                self.report_proxy_request_status(pid, username, path, format!("fallback : {}", reason));
+            },
+            None => {
+                if self.fallback_paths.remove(&path) {
+                    self.report_proxy_request_status(pid, username, path, "original recovered".to_string());
+                }
+            }
+        }
+    }
+
+    ///
+    /// Get lopxy root CA, it's generated at the first time
+    ///
+    pub fn certificate_authority<'a>(&'a mut self) -> Option<&'a mut proxy::ca::CertificateAuthority> {
+        if self.certificate_authority.is_none() {
+            self.certificate_authority = match proxy::ca::CertificateAuthority::load_or_generate(&self.config_dir) {
+                Ok(ca) => Some(ca),
+                Err(err) => {
+                    eprintln!("load lopxy CA failed : {}", err);
+                    None
+                }
+            };
+        }
+
+        self.certificate_authority.as_mut()
+    }
+
+    ///
+    /// Regenerate lopxy root CA, certificates minted by the old one are dropped
+    ///
+    pub fn regenerate_certificate_authority(&mut self) -> bool {
+        match proxy::ca::CertificateAuthority::generate(&self.config_dir) {
+            Ok(ca) => {
+                self.certificate_authority = Some(ca);
+                true
+            },
+            Err(err) => {
+                eprintln!("regenerate lopxy CA failed : {}", err);
+                false
+            }
+        }
+    }
+
+    ///
+    /// Get TLS server config of intercepted host
+    ///
+    pub fn https_server_config(&mut self, host: &str) -> Option<std::sync::Arc<rustls::ServerConfig>> {
+        match self.certificate_authority()?.server_config(host) {
+            Ok(server_config) => Some(server_config),
+            Err(err) => {
+                eprintln!("mint certificate of {} failed : {}", host, err);
+                None
+            }
+        }
+    }
+
+    ///
+    /// Get upstream proxies from environment variables, the ones pointing to lopxy itself are dropped
+    ///
+    fn environment_upstream<'a>(&'a mut self) -> &'a proxy::upstream::UpstreamConfig {
+        if self.environment_upstream.is_none() {
+            let local_ports = match self.start_args() {
+                Some(start_args) => vec![start_args.proxy_port, start_args.socks_port],
+                None => vec![]
+            };
+
+            self.environment_upstream = Some(proxy::upstream::UpstreamConfig::from_env().without_local_ports(&local_ports));
+        }
+
+        self.environment_upstream.as_ref().unwrap()
+    }
+
+    ///
+    /// Get the route to `url`, None if no upstream proxy is set
+    ///
+    /// # Notes
+    /// The precedence is : proxy item > lopxy config > environment variables, the bypass list of
+    /// lopxy config applies to upstream proxies from environment variables and system proxy too
+    pub fn upstream_route(&mut self, item: Option<&proxy::item::ProxyItem>, url: &str) -> Option<proxy::upstream::UpstreamRoute> {
+        if let Some(route) = item.and_then(|item: &proxy::item::ProxyItem| item.upstream_route()) {
+            return Some(route);
+        }
+
+        let upstream = self.load_config().upstream();
+        if proxy::bypass::BypassList::from_entries(&upstream.no_proxy).matches_url(url) {
+            return Some(proxy::upstream::UpstreamRoute::Direct);
+        }
+
+        if !upstream.is_empty() {
+            return Some(upstream.route(url));
+        }
+
+        let upstream = self.environment_upstream();
+        if !upstream.is_empty() {
+            return Some(upstream.route(url));
+        }
+
+        None
+    }
+
+    ///
+    /// Get the offline cache, it's opened in the `cache` dir under config dir at the first time
+    ///
+    /// # Notes
+    /// The cache config is updated when the config timestamp changes
+    pub fn open_response_cache(&mut self) -> std::sync::Arc<proxy::cache::ResponseCache> {
+        let timestamp = self.load_config().timestamp();
+
+        match &mut self.response_cache {
+            Some((cache_timestamp, response_cache)) => {
+                if *cache_timestamp != timestamp {
+                    response_cache.update_config(self.config.as_ref().unwrap().cache());
+                    *cache_timestamp = timestamp;
+                }
+            },
+            None => {
+                let mut cache_dir = self.config_dir.clone();
+                cache_dir.push("cache");
+
+                let cache_config = self.load_config().cache().clone();
+                self.response_cache = Some((timestamp, std::sync::Arc::new(proxy::cache::ResponseCache::open(cache_dir, &cache_config))));
+            }
+        }
+
+        self.response_cache.as_ref().unwrap().1.clone()
+    }
+
+    ///
+    /// Get the offline cache if the url is in its scope
+    ///
+    pub fn response_cache(&mut self, url: &str) -> Option<std::sync::Arc<proxy::cache::ResponseCache>> {
+        if !self.load_config().cache().matches_url(url) {
+            return None;
+        }
+
+        Some(self.open_response_cache())
+    }
+
     ///
     /// Get proxy request status logs
     ///
@@ -349,6 +562,45 @@ impl LopxyEnv {
         serde_json::to_string(&record).unwrap_or("[]".to_string())
     }
 
+    ///
+    /// Get the proxy auto-config script, it's regenerated when the config timestamp changes
+    ///
+    /// # Notes
+    /// `hostname` is the host which the client reached the web manager by, lan clients get the
+    /// proxy address on it while local clients get the one set as system proxy
+    pub fn pac_script(&mut self, hostname: Option<&str>) -> String {
+        let start_args = match self.start_args() {
+            Some(start_args) => start_args,
+            None => {
+                return String::new();
+            }
+        };
+
+        let is_local = |hostname: &str| -> bool {
+            let hostname = hostname.trim_start_matches('[').trim_end_matches(']');
+            hostname.eq_ignore_ascii_case("localhost") || hostname.parse::<std::net::IpAddr>().map(|ip: std::net::IpAddr| ip.is_loopback()).unwrap_or(false)
+        };
+
+        let proxy_address = match hostname {
+            Some(hostname) if !hostname.is_empty() && !is_local(hostname) => format!("{}:{}", hostname, start_args.proxy_port),
+            _ => start_args.proxy_address()
+        };
+
+        let timestamp = self.load_config().timestamp();
+
+        let outdated = match &self.pac_script {
+            Some((pac_timestamp, pac_proxy_address, _)) => *pac_timestamp != timestamp || *pac_proxy_address != proxy_address,
+            None => true
+        };
+
+        if outdated {
+            let pac_script = proxy::pac::generate_pac(self.load_config().proxy_item_list(), &proxy_address);
+            self.pac_script = Some((timestamp, proxy_address, pac_script));
+        }
+
+        self.pac_script.as_ref().unwrap().2.clone()
+    }
+
     ///
     /// Get lopxy status
     /// 
@@ -359,12 +611,14 @@ impl LopxyEnv {
             success: true,
             web_manager_port: start_args.web_manager_port,
             proxy_port: start_args.proxy_port,
+            socks_port: start_args.socks_port,
             proxy_enabled: proxy::ProxyConfig::is_system_proxy_enabled(),
             updated: false,
             status_log_timestamp: status_log_timestamp,
             request_status_logs: vec![],
             config_timestamp: config_timestamp,
-            proxy_items: vec![]
+            proxy_items: vec![],
+            target_states: self.target_health.states()
         };
 
         if self.status_refresh_timestamp > status_log_timestamp {
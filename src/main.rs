@@ -16,11 +16,13 @@ mod proxy;
 mod util;
 mod assets;
 
+use chrono::TimeZone;
 use clap::Parser;
 use std::sync::Arc;
 use std::sync::Mutex;
 
 use manager::controller::LopxyManagerServerController;
+use proxy::condition::BlockKind;
 use proxy::item::*;
 
 #[tokio::main]
@@ -63,6 +65,15 @@ async fn run(mut controller: controller::LopxyController) {
         },
         args::LopxyCommand::Manager => {
             open_web_manager_page_in_browser(controller).await;
+        },
+        args::LopxyCommand::Ca(_) => {
+            manage_certificate_authority(controller).await;
+        },
+        args::LopxyCommand::Bypass(_) => {
+            manage_bypass_list(controller).await;
+        },
+        args::LopxyCommand::Cache(_) => {
+            manage_response_cache(controller).await;
         }
     }
 }
@@ -100,12 +111,24 @@ async fn start_server(mut controller: controller::LopxyController) {
 
     // wrap controller
     let web_manager_port = start_args.web_manager_port;
-    let proxy_addr = format!("127.0.0.1:{}", start_args.proxy_port);
+    let web_manager_address = start_args.web_manager_address();
+    let web_manager_token = lopxy_env.manager_token().expect("web manager token unavailable");
+    let manager_listen = start_args.manager_listen.clone();
+    let proxy_addr = start_args.proxy_address();
+    let proxy_servers: Vec<String> = start_args.listen.iter().
+        map(|address: &std::net::IpAddr| std::net::SocketAddr::new(*address, start_args.proxy_port as u16).to_string()).
+        collect();
+    let socks_servers: Vec<String> = match start_args.socks_port {
+        0 => vec![],
+        socks_port => start_args.listen.iter().
+            map(|address: &std::net::IpAddr| std::net::SocketAddr::new(*address, socks_port as u16).to_string()).
+            collect()
+    };
     let proxy_shutdown = lopxy_env.clone_proxy_shutdown();
     let controller = Arc::new(Mutex::new(controller));
 
     // start manager server
-    let mut web_manager_server = manager::LopxyManagerServer::build(web_manager_port, static_assets_dir, controller.clone());
+    let mut web_manager_server = manager::LopxyManagerServer::build(manager_listen, web_manager_port, static_assets_dir, web_manager_token.clone(), controller.clone());
     let web_manager_server_future = web_manager_server.start();
 
     // build proxy config
@@ -116,10 +139,10 @@ async fn start_server(mut controller: controller::LopxyController) {
     proxy_config.update_system_proxy().expect("set proxy config failed");
 
     // start proxy server
-    let proxy_server_future = proxy::Proxy::start(system_proxy_config.clone(), proxy_config, proxy_shutdown, controller.clone());
+    let proxy_server_future = proxy::Proxy::start(system_proxy_config.clone(), proxy_config, proxy_servers, socks_servers, proxy_shutdown, controller.clone());
 
     // open web manager page
-    match webbrowser::open(&format!("http://127.0.0.1:{}", web_manager_port)) { _ => {} }
+    let _ = webbrowser::open(&format!("http://{}/?token={}", web_manager_address, web_manager_token));
 
     // wait for all server
     match futures::join!(web_manager_server_future, proxy_server_future) { _ => {} }
@@ -151,7 +174,39 @@ async fn list_all_proxy_item(mut controller: controller::LopxyController) {
 
     let show_proxy_item_list = |proxy_items: &Vec<ProxyItem>| {
         for item in proxy_items {
-            println!("{} => {} [{}]", item.resource_url(), item.proxy_resource_url(), item.content_type());
+            println!("{} => {} [{}] ({}, {})", item.resource_url(), item.proxy_resource_url(), item.content_type(), item.match_kind(), item.mode());
+            if item.mode() == ProxyMode::Redirect {
+                println!("    redirect => {}{}", item.redirect_status(), if item.preserve_query() { " (preserve query)" } else { "" });
+            }
+            for mirror_url in item.mirror_urls() {
+                println!("    mirror => {} ({})", mirror_url, item.mirror_strategy());
+            }
+            if let Some(upstream_proxy) = item.upstream_proxy() {
+                println!("    upstream => {}", upstream_proxy);
+            }
+            if let Some(rule_set) = item.rule_set() {
+                println!("    rule set => {}", rule_set);
+            }
+            if let Some(mock_response) = item.mock_response() {
+                println!("    mock => {} ({} headers, {} body)", mock_response.status, mock_response.headers.len(), mock_response.body_encoding);
+            }
+            if let Some(condition) = item.network_condition() {
+                let block = match condition.block {
+                    Some(BlockKind::Status) => format!("status {} {}%", condition.block_status, condition.failure_rate),
+                    Some(block) => format!("{} {}%", block, condition.failure_rate),
+                    None => "none".to_string()
+                };
+                println!("    network condition => block {}, latency {}ms, bandwidth {}B/s", block, condition.latency, condition.bandwidth);
+            }
+            for rule in item.request_headers() {
+                println!("    request header => {}", rule);
+            }
+            for rule in item.response_headers() {
+                println!("    response header => {}", rule);
+            }
+            for rule in item.body_rules() {
+                println!("    body rule => {}", rule);
+            }
         }
     };
 
@@ -174,13 +229,41 @@ async fn add_proxy_item(mut controller: controller::LopxyController) {
     let lopxy_env = controller.env();
     let add_args = lopxy_env.add_args().expect("add args invalid");
 
+    let modification = ProxyItemModification {
+        resource_url: add_args.resource_url.clone(),
+        proxy_resource_url: add_args.proxy_resource_url.clone(),
+        content_type: add_args.content_type.clone(),
+        match_kind: Some(add_args.match_kind),
+        directory_index: Some(add_args.directory_index),
+        directory_listing: Some(add_args.directory_listing),
+        mode: Some(add_args.mode),
+        fallback_status: Some(add_args.fallback_status.clone()),
+        fallback_timeout: Some(add_args.fallback_timeout),
+        redirect_status: Some(add_args.redirect_status),
+        preserve_query: Some(add_args.preserve_query),
+        mirror_urls: Some(add_args.mirror_urls.iter().filter(|url: &&String| !url.is_empty()).cloned().collect()),
+        mirror_strategy: Some(add_args.mirror_strategy),
+        upstream_proxy: add_args.upstream_proxy.clone(),
+        rule_set: add_args.rule_set.clone(),
+        target_kind: Some(add_args.target_kind),
+        mock_status: Some(add_args.mock_status),
+        mock_headers: Some(add_args.mock_headers.iter().filter(|header: &&String| !header.is_empty()).cloned().collect()),
+        mock_body: Some(add_args.mock_body.clone()),
+        mock_body_encoding: Some(add_args.mock_body_encoding),
+        block: add_args.block.map(Some),
+        block_status: Some(add_args.block_status),
+        failure_rate: Some(add_args.failure_rate),
+        latency: Some(add_args.latency),
+        bandwidth: Some(add_args.bandwidth),
+        request_headers: Some(add_args.request_headers.iter().filter(|rule: &&String| !rule.is_empty()).cloned().collect()),
+        response_headers: Some(add_args.response_headers.iter().filter(|rule: &&String| !rule.is_empty()).cloned().collect()),
+        body_rules: Some(add_args.body_rules.iter().filter(|rule: &&String| !rule.is_empty()).cloned().collect())
+    };
+
     let web_manager_instance = match lopxy_env.web_manager_instance() {
         Some(s) => s,
         None => {
-            let resource_url = add_args.resource_url.clone();
-            let proxy_resource_url = add_args.proxy_resource_url.clone();
-            let content_type = add_args.content_type.clone();
-            if !controller.add_proxy_item(&resource_url, &proxy_resource_url, &content_type) {
+            if !controller.add_proxy_item(modification.apply(None)) {
                 eprintln!("add proxy item failed");
                 std::process::exit(1);
             }
@@ -188,7 +271,7 @@ async fn add_proxy_item(mut controller: controller::LopxyController) {
         }
     };
 
-    let result = manager::LopxyManagerServer::add_proxy_item(&web_manager_instance, &add_args.resource_url, &add_args.proxy_resource_url, &add_args.content_type).
+    let result = manager::LopxyManagerServer::add_proxy_item(&web_manager_instance, &modification).
         await.expect("add proxy item failed");
     print!("{}", result);
 }
@@ -218,13 +301,53 @@ async fn modify_proxy_item(mut controller: controller::LopxyController) {
     let lopxy_env = controller.env();
     let modify_args = lopxy_env.modify_args().expect("modify args invalid");
 
+    let modification = ProxyItemModification {
+        resource_url: modify_args.resource_url.clone(),
+        proxy_resource_url: modify_args.proxy_resource_url.clone(),
+        content_type: modify_args.content_type.clone(),
+        match_kind: modify_args.match_kind,
+        directory_index: modify_args.directory_index,
+        directory_listing: modify_args.directory_listing,
+        mode: modify_args.mode,
+        fallback_status: modify_args.fallback_status.clone(),
+        fallback_timeout: modify_args.fallback_timeout,
+        redirect_status: modify_args.redirect_status,
+        preserve_query: modify_args.preserve_query,
+        mirror_urls: modify_args.mirror_urls.as_ref().map(|urls: &Vec<String>| {
+            urls.iter().filter(|url: &&String| !url.is_empty()).cloned().collect()
+        }),
+        mirror_strategy: modify_args.mirror_strategy,
+        upstream_proxy: modify_args.upstream_proxy.clone(),
+        rule_set: modify_args.rule_set.clone(),
+        target_kind: modify_args.target_kind,
+        mock_status: modify_args.mock_status,
+        mock_headers: modify_args.mock_headers.as_ref().map(|headers: &Vec<String>| {
+            headers.iter().filter(|header: &&String| !header.is_empty()).cloned().collect()
+        }),
+        mock_body: modify_args.mock_body.clone(),
+        mock_body_encoding: modify_args.mock_body_encoding,
+        block: modify_args.block.map(|block: args::BlockModification| block.0),
+        block_status: modify_args.block_status,
+        failure_rate: modify_args.failure_rate,
+        latency: modify_args.latency,
+        bandwidth: modify_args.bandwidth,
+        request_headers: modify_args.request_headers.as_ref().map(|rules: &Vec<String>| {
+            rules.iter().filter(|rule: &&String| !rule.is_empty()).cloned().collect()
+        }),
+        response_headers: modify_args.response_headers.as_ref().map(|rules: &Vec<String>| {
+            rules.iter().filter(|rule: &&String| !rule.is_empty()).cloned().collect()
+        }),
+        body_rules: modify_args.body_rules.as_ref().map(|rules: &Vec<String>| {
+            rules.iter().filter(|rule: &&String| !rule.is_empty()).cloned().collect()
+        })
+    };
+
     let web_manager_instance = match lopxy_env.web_manager_instance() {
         Some(s) => s,
         None => {
-            let resource_url = modify_args.resource_url.clone();
-            let proxy_resource_url = modify_args.proxy_resource_url.clone();
-            let content_type = modify_args.content_type.clone();
-            if !controller.modify_proxy_item(&resource_url, &proxy_resource_url, &content_type) {
+            let item = modification.apply(controller.find_proxy_item(&modification.resource_url));
+
+            if !controller.modify_proxy_item(item) {
                 eprintln!("modify proxy item failed");
                 std::process::exit(1);
             }
@@ -232,7 +355,7 @@ async fn modify_proxy_item(mut controller: controller::LopxyController) {
         }
     };
 
-    let result = manager::LopxyManagerServer::modify_proxy_item(&web_manager_instance, &modify_args.resource_url, &modify_args.proxy_resource_url, &modify_args.content_type).
+    let result = manager::LopxyManagerServer::modify_proxy_item(&web_manager_instance, &modification).
         await.expect("modify proxy item failed");
     print!("{}", result);
 }
@@ -287,5 +410,142 @@ async fn open_web_manager_page_in_browser(mut controller: controller::LopxyContr
         }
     };
 
-    webbrowser::open(&web_manager_instance).expect("open web manager page in browser failed");
-}
\ No newline at end of file
+    let web_manager_page = match lopxy_env.manager_token() {
+        Some(token) => format!("{}/?token={}", web_manager_instance, token),
+        None => web_manager_instance
+    };
+
+    webbrowser::open(&web_manager_page).expect("open web manager page in browser failed");
+}
+
+async fn manage_certificate_authority(mut controller: controller::LopxyController) {
+    let lopxy_env = controller.env();
+    let ca_args = lopxy_env.ca_args().expect("ca args invalid");
+    let web_manager_instance = lopxy_env.web_manager_instance();
+
+    match &ca_args.command {
+        args::CaCommand::Export(export_args) => {
+            let output = export_args.output.clone();
+
+            let certificate_pem = match web_manager_instance {
+                Some(web_manager_instance) => manager::LopxyManagerServer::export_certificate_authority(&web_manager_instance).
+                    await.expect("export lopxy CA failed"),
+                None => match controller.certificate_authority_pem() {
+                    Some(certificate_pem) => certificate_pem,
+                    None => {
+                        eprintln!("export lopxy CA failed");
+                        std::process::exit(1);
+                    }
+                }
+            };
+
+            match output {
+                Some(output) => std::fs::write(&output, certificate_pem).expect("write lopxy CA failed"),
+                None => print!("{}", certificate_pem)
+            }
+        },
+        args::CaCommand::Regenerate => {
+            let web_manager_instance = match web_manager_instance {
+                Some(s) => s,
+                None => {
+                    if !controller.regenerate_certificate_authority() {
+                        eprintln!("regenerate lopxy CA failed");
+                        std::process::exit(1);
+                    }
+                    return;
+                }
+            };
+
+            let result = manager::LopxyManagerServer::regenerate_certificate_authority(&web_manager_instance).
+                await.expect("regenerate lopxy CA failed");
+            print!("{}", result);
+        }
+    }
+}
+
+async fn manage_bypass_list(mut controller: controller::LopxyController) {
+    let lopxy_env = controller.env();
+    let bypass_command = lopxy_env.bypass_args().expect("bypass args invalid").command.clone();
+    let web_manager_instance = lopxy_env.web_manager_instance();
+
+    let entries = match &web_manager_instance {
+        Some(web_manager_instance) => manager::LopxyManagerServer::bypass_list(web_manager_instance).
+            await.expect("get bypass list failed"),
+        None => controller.bypass_list()
+    };
+
+    let new_entries = match bypass_command {
+        args::BypassCommand::List => {
+            entries.iter().for_each(|entry: &String| println!("{}", entry));
+            return;
+        },
+        args::BypassCommand::Add(add_args) => {
+            let mut new_entries = entries.clone();
+            add_args.entries.iter().
+                filter(|entry: &&String| !entries.contains(entry)).
+                for_each(|entry: &String| new_entries.push(entry.clone()));
+            new_entries
+        },
+        args::BypassCommand::Remove(remove_args) => entries.into_iter().
+            filter(|entry: &String| !remove_args.entries.contains(entry)).
+            collect(),
+        args::BypassCommand::Set(set_args) => set_args.entries.clone()
+    };
+
+    match web_manager_instance {
+        Some(web_manager_instance) => {
+            let result = manager::LopxyManagerServer::set_bypass_list(&web_manager_instance, &new_entries).
+                await.expect("set bypass list failed");
+            print!("{}", result);
+        },
+        None => {
+            if !controller.set_bypass_list(new_entries) {
+                eprintln!("set bypass list failed");
+                std::process::exit(1);
+            }
+        }
+    }
+}
+
+async fn manage_response_cache(mut controller: controller::LopxyController) {
+    let lopxy_env = controller.env();
+    let cache_command = lopxy_env.cache_args().expect("cache args invalid").command.clone();
+    let web_manager_instance = lopxy_env.web_manager_instance();
+
+    match cache_command {
+        args::CacheCommand::List => {
+            let entries = match &web_manager_instance {
+                Some(web_manager_instance) => manager::LopxyManagerServer::cache_entries(web_manager_instance).
+                    await.expect("get cache entries failed"),
+                None => controller.cache_entries()
+            };
+
+            for entry in entries.iter() {
+                let stored_time = match chrono::Local.timestamp_millis_opt(entry.stored_timestamp).single() {
+                    Some(stored_time) => stored_time.format("%Y-%m-%d %H:%M:%S").to_string(),
+                    None => "-".to_string()
+                };
+                println!("{} {} bytes, {}, stored at {}",
+                    entry.url,
+                    entry.size,
+                    if entry.is_fresh() { "fresh" } else { "stale" },
+                    stored_time);
+            }
+        },
+        args::CacheCommand::Purge(purge_args) => {
+            let purged = match &web_manager_instance {
+                Some(web_manager_instance) => manager::LopxyManagerServer::purge_cache(web_manager_instance, purge_args.url.as_deref()).
+                    await.expect("purge cache failed"),
+                None => controller.purge_cache(purge_args.url.as_deref())
+            };
+
+            match purged {
+                Some(purged) => println!("{} cached responses purged", purged),
+                None => {
+                    eprintln!("purge cache failed, the url glob is rejected");
+                    std::process::exit(1);
+                }
+            }
+        }
+    }
+}
@@ -0,0 +1,155 @@
+use std::io::Write;
+
+use rand::RngCore;
+use rocket::http::{Method, Status};
+use rocket::request::{FromRequest, Outcome};
+
+use super::controller::LopxyManagerServerStatus;
+
+/// File in config dir which keeps the web manager token
+pub const TOKEN_FILE_NAME: &str = "manager.token";
+
+/// Cookie which keeps the web manager token for browsers
+pub const TOKEN_COOKIE_NAME: &str = "lopxy_token";
+
+fn token_path(config_dir: &std::path::Path) -> std::path::PathBuf {
+    config_dir.join(TOKEN_FILE_NAME)
+}
+
+///
+/// Load the web manager token from config dir
+///
+pub fn load_token(config_dir: &std::path::Path) -> Option<String> {
+    let token = std::fs::read_to_string(token_path(config_dir)).ok()?;
+    let token = token.trim();
+
+    match token.is_empty() {
+        true => None,
+        false => Some(token.to_string())
+    }
+}
+
+///
+/// Load the web manager token, a new one is generated if absent
+///
+/// # Notes
+/// Only the owner can read the token file on unix
+pub fn load_or_generate_token(config_dir: &std::path::Path) -> std::io::Result<String> {
+    if let Some(token) = load_token(config_dir) {
+        return Ok(token);
+    }
+
+    let mut bytes = [0u8; 32];
+    rand::thread_rng().fill_bytes(&mut bytes);
+    let token: String = bytes.iter().map(|byte: &u8| format!("{:02x}", byte)).collect();
+
+    let mut options = std::fs::OpenOptions::new();
+    options.write(true).create(true).truncate(true);
+
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::OpenOptionsExt;
+        options.mode(0o600);
+    }
+
+    options.open(token_path(config_dir))?.write_all(token.as_bytes())?;
+
+    Ok(token)
+}
+
+///
+/// Compare tokens in constant time
+///
+pub fn token_matches(expected: &str, provided: &str) -> bool {
+    let expected = expected.as_bytes();
+    let provided = provided.as_bytes();
+
+    expected.len() == provided.len() &&
+        expected.iter().zip(provided.iter()).fold(0u8, |diff: u8, (a, b): (&u8, &u8)| diff | (a ^ b)) == 0
+}
+
+///
+/// Check whether a state-changing request comes from the web manager page itself
+///
+/// # Notes
+/// `Origin` must be the web manager if present, otherwise `Sec-Fetch-Site` must not be cross-site
+fn is_same_origin(request: &rocket::Request<'_>) -> bool {
+    let headers = request.headers();
+
+    if let Some(origin) = headers.get_one("Origin") {
+        return match headers.get_one("Host") {
+            Some(host) => origin.eq_ignore_ascii_case(&format!("http://{}", host)),
+            None => false
+        };
+    }
+
+    !matches!(headers.get_one("Sec-Fetch-Site"), Some("cross-site") | Some("same-site"))
+}
+
+///
+/// Request guard of web manager routes
+///
+/// # Notes
+/// The token is taken from `Authorization: Bearer <token>` sent by CLI or the cookie set for
+/// browsers, state-changing requests must also pass the origin check
+pub struct ManagerAuth;
+
+#[rocket::async_trait]
+impl<'r> FromRequest<'r> for ManagerAuth {
+    type Error = ();
+
+    async fn from_request(request: &'r rocket::Request<'_>) -> Outcome<Self, Self::Error> {
+        let status = match request.rocket().state::<LopxyManagerServerStatus>() {
+            Some(status) => status,
+            None => {
+                return Outcome::Failure((Status::InternalServerError, ()));
+            }
+        };
+
+        let bearer_token = request.headers().get_one("Authorization").
+            and_then(|authorization: &str| authorization.strip_prefix("Bearer ")).
+            map(|token: &str| token.trim().to_string());
+
+        // cookies are never sent by cli, the origin check only applies to browsers
+        let (token, from_cookie) = match bearer_token {
+            Some(token) => (Some(token), false),
+            None => (request.cookies().get(TOKEN_COOKIE_NAME).map(|cookie| cookie.value().to_string()), true)
+        };
+
+        match token {
+            Some(token) if status.token_matches(&token) => {},
+            _ => {
+                return Outcome::Failure((Status::Unauthorized, ()));
+            }
+        }
+
+        if from_cookie && request.method() != Method::Get && !is_same_origin(request) {
+            eprintln!("reject cross origin web manager request {} {}", request.method(), request.uri());
+            return Outcome::Failure((Status::Forbidden, ()));
+        }
+
+        Outcome::Success(ManagerAuth)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn generate_and_compare_token() {
+        let config_dir = std::env::temp_dir().join(format!("lopxy-auth-test-{}", std::process::id()));
+        std::fs::create_dir_all(&config_dir).unwrap();
+
+        let token = load_or_generate_token(&config_dir).unwrap();
+        assert_eq!(token.len(), 64);
+        assert_eq!(load_or_generate_token(&config_dir).unwrap(), token);
+        assert_eq!(load_token(&config_dir), Some(token.clone()));
+
+        assert!(token_matches(&token, &token));
+        assert!(!token_matches(&token, &token[1..]));
+        assert!(!token_matches(&token, &token.replace(&token[..1], "x")));
+
+        std::fs::remove_dir_all(&config_dir).unwrap();
+    }
+}
@@ -2,11 +2,15 @@ use std::sync::Arc;
 use std::sync::Mutex;
 
 use rocket::form::Form;
+use rocket::request::{FromRequest, Outcome};
 use rocket::response::content::{Html, Json};
 use urlencoding::decode;
 
+use super::auth::*;
 use super::request::*;
+use super::super::proxy::condition::BlockKind;
 use super::super::proxy::item::*;
+use super::super::proxy::pac::PAC_CONTENT_TYPE;
 
 fn decode_url_string(urlstr: &str) -> Option<String> {
     match decode(urlstr) {
@@ -15,62 +19,176 @@ fn decode_url_string(urlstr: &str) -> Option<String> {
     }
 }
 
+///
+/// Decode an optional form field, `Some(None)` means the field is absent
+///
+fn decode_optional_url_string(urlstr: Option<&str>) -> Option<Option<String>> {
+    match urlstr {
+        Some(urlstr) => decode_url_string(urlstr).map(Some),
+        None => Some(None)
+    }
+}
+
+fn parse_optional_form_field<T: std::str::FromStr>(value: Option<&str>) -> Option<Option<T>> {
+    match decode_optional_url_string(value)? {
+        Some(value) => value.parse::<T>().ok().map(Some),
+        None => Some(None)
+    }
+}
+
+///
+/// Decode newline separated mirror urls, bypass entries, mock headers or header rules, blank lines are ignored
+///
+fn decode_mirror_urls(urlstr: Option<&str>) -> Option<Option<Vec<String>>> {
+    let mirrors = match decode_optional_url_string(urlstr)? {
+        Some(mirrors) => mirrors,
+        None => {
+            return Some(None);
+        }
+    };
+
+    Some(Some(mirrors.lines().map(|url: &str| url.trim()).filter(|url: &&str| !url.is_empty()).map(String::from).collect()))
+}
+
+///
+/// Decode the block kind, an empty one removes the current block
+///
+fn decode_block(block: Option<&str>) -> Option<Option<Option<BlockKind>>> {
+    match decode_optional_url_string(block)? {
+        Some(block) if !block.trim().is_empty() => block.parse::<BlockKind>().ok().map(|block: BlockKind| Some(Some(block))),
+        Some(_) => Some(Some(None)),
+        None => Some(None)
+    }
+}
+
+///
+/// Collect the proxy item modification from add or modify request, return None if any field is invalid
+///
+fn proxy_item_modification(item: &ProxyItemRequest<'_>) -> Option<ProxyItemModification> {
+    Some(ProxyItemModification {
+        resource_url: decode_url_string(item.resource_url)?,
+        proxy_resource_url: decode_url_string(item.proxy_resource_url)?,
+        content_type: decode_url_string(item.resource_content_type)?,
+        match_kind: parse_optional_form_field(item.match_kind)?,
+        directory_index: item.directory_index,
+        directory_listing: item.directory_listing,
+        mode: parse_optional_form_field(item.mode)?,
+        fallback_status: decode_optional_url_string(item.fallback_status)?,
+        fallback_timeout: item.fallback_timeout,
+        redirect_status: item.redirect_status,
+        preserve_query: item.preserve_query,
+        mirror_urls: decode_mirror_urls(item.mirror_urls)?,
+        mirror_strategy: parse_optional_form_field(item.mirror_strategy)?,
+        upstream_proxy: decode_optional_url_string(item.upstream_proxy)?,
+        rule_set: decode_optional_url_string(item.rule_set)?,
+        target_kind: parse_optional_form_field(item.target_kind)?,
+        mock_status: item.mock_status,
+        mock_headers: decode_mirror_urls(item.mock_headers)?,
+        mock_body: decode_optional_url_string(item.mock_body)?,
+        mock_body_encoding: parse_optional_form_field(item.mock_body_encoding)?,
+        block: decode_block(item.block)?,
+        block_status: item.block_status,
+        failure_rate: item.failure_rate,
+        latency: item.latency,
+        bandwidth: item.bandwidth,
+        request_headers: decode_mirror_urls(item.request_headers)?,
+        response_headers: decode_mirror_urls(item.response_headers)?,
+        body_rules: decode_mirror_urls(item.body_rules)?
+    })
+}
+
 pub type LopxyManagerServerControllerArc = Arc<Mutex<dyn LopxyManagerServerController + Send>>;
 
 pub trait LopxyManagerServerController {
     fn shutdown(&mut self);
 
     fn list_all_proxy_item(&mut self) -> &Vec<ProxyItem>;
-    fn add_proxy_item(&mut self, resource_url: &str, proxy_resource_url: &str, content_type: &str) -> bool;
+    fn find_proxy_item(&mut self, resource_url: &str) -> Option<ProxyItem>;
+    fn add_proxy_item(&mut self, item: ProxyItem) -> bool;
     fn remove_proxy_item(&mut self, resource_url: &str) -> bool;
-    fn modify_proxy_item(&mut self, resource_url: &str, proxy_resource_url: &str, content_type: &str) -> bool;
+    fn modify_proxy_item(&mut self, item: ProxyItem) -> bool;
 
     fn is_system_proxy_enabled(&mut self) -> bool;
     fn set_system_proxy_enabled(&mut self, enabled: bool) -> bool;
 
     fn proxy_request_logs(&mut self) -> String;
     fn lopxy_status(&mut self, config_timestamp: i64, status_log_timestamp: i64) -> String;
+
+    fn certificate_authority_pem(&mut self) -> Option<String>;
+    fn regenerate_certificate_authority(&mut self) -> bool;
+
+    fn pac_script(&mut self, hostname: Option<&str>) -> String;
+    fn is_client_allowed(&mut self, address: &std::net::IpAddr) -> bool;
+
+    fn bypass_list(&mut self) -> Vec<String>;
+    fn set_bypass_list(&mut self, entries: Vec<String>) -> bool;
+
+    fn cache_entries(&mut self) -> Vec<super::super::proxy::cache::CacheEntry>;
+    fn purge_cache(&mut self, pattern: Option<&str>) -> Option<usize>;
 }
 
 pub struct LopxyManagerServerStatus {
-    controller: Arc<Mutex<dyn LopxyManagerServerController + Send>>
+    controller: Arc<Mutex<dyn LopxyManagerServerController + Send>>,
+    token: String
 }
 
 impl LopxyManagerServerStatus {
-    pub fn new(controller: LopxyManagerServerControllerArc) -> LopxyManagerServerStatus {
+    pub fn new(controller: LopxyManagerServerControllerArc, token: String) -> LopxyManagerServerStatus {
         LopxyManagerServerStatus {
-            controller
+            controller,
+            token
         }
     }
+
+    pub fn token_matches(&self, token: &str) -> bool {
+        token_matches(&self.token, token)
+    }
+}
+
+///
+/// Sign in the browser opened by lopxy with the token in url, it's kept in cookie afterwards
+///
+#[get("/?<token>")]
+fn sign_in(state: &rocket::State<LopxyManagerServerStatus>, token: &str, cookies: &rocket::http::CookieJar<'_>) -> Result<rocket::response::Redirect, rocket::http::Status> {
+    if !state.token_matches(token) {
+        return Err(rocket::http::Status::Unauthorized);
+    }
+
+    cookies.add(rocket::http::Cookie::build(TOKEN_COOKIE_NAME, token.to_string()).
+        path("/").
+        http_only(true).
+        same_site(rocket::http::SameSite::Strict).
+        finish());
+
+    Ok(rocket::response::Redirect::to("/"))
 }
 
-#[get("/shutdown")]
-fn shutdown(state: &rocket::State<LopxyManagerServerStatus>, shutdown: rocket::Shutdown) -> &'static str {
+#[post("/shutdown")]
+fn shutdown(state: &rocket::State<LopxyManagerServerStatus>, _auth: ManagerAuth, shutdown: rocket::Shutdown) -> &'static str {
     state.controller.lock().unwrap().shutdown();
     shutdown.notify();
     "Shutting down..."
 }
 
 #[get("/list")]
-fn list_all_proxy_item(state: &rocket::State<LopxyManagerServerStatus>) -> Json<String> {
+fn list_all_proxy_item(state: &rocket::State<LopxyManagerServerStatus>, _auth: ManagerAuth) -> Json<String> {
     Json(serde_json::to_string(state.controller.lock().unwrap().list_all_proxy_item()).unwrap_or("{}".to_string()))
 }
 
 #[post("/add", data = "<item>")]
-fn add_proxy_item<'r>(state: &rocket::State<LopxyManagerServerStatus>, item: Form<AddRequest<'r>>) -> Json<String> {
-    let resource_url = decode_url_string(item.resource_url);
-    let proxy_resource_url = decode_url_string(item.proxy_resource_url);
-    let resource_content_type = decode_url_string(item.resource_content_type);
-
-    if resource_url.is_none() || proxy_resource_url.is_none() || resource_content_type.is_none() {
-        return Json(String::from("{\"result\":false}"));
-    }
+fn add_proxy_item<'r>(state: &rocket::State<LopxyManagerServerStatus>, _auth: ManagerAuth, item: Form<AddRequest<'r>>) -> Json<String> {
+    let modification = match proxy_item_modification(&item) {
+        Some(modification) => modification,
+        None => {
+            return Json(String::from("{\"result\":false}"));
+        }
+    };
 
-    Json(format!("{{\"result\":{}}}", state.controller.lock().unwrap().add_proxy_item(&resource_url.unwrap(), &proxy_resource_url.unwrap(), &resource_content_type.unwrap())))
+    Json(format!("{{\"result\":{}}}", state.controller.lock().unwrap().add_proxy_item(modification.apply(None))))
 }
 
 #[delete("/remove", data = "<item>")]
-fn remove_proxy_item<'r>(state: &rocket::State<LopxyManagerServerStatus>, item: Form<RemoveRequest<'r>>) -> Json<String> {
+fn remove_proxy_item<'r>(state: &rocket::State<LopxyManagerServerStatus>, _auth: ManagerAuth, item: Form<RemoveRequest<'r>>) -> Json<String> {
     let resource_url = decode_url_string(item.resource_url);
     if resource_url.is_none() {
         return Json(String::from("{\"result\":false}"));
@@ -78,39 +196,147 @@ fn remove_proxy_item<'r>(state: &rocket::State<LopxyManagerServerStatus>, item:
     Json(format!("{{\"result\":{}}}", state.controller.lock().unwrap().remove_proxy_item(&resource_url.unwrap())))
 }
 
+///
+/// Modify proxy item
+///
+/// # Notes
+/// Absent optional fields keep the values of the existing proxy item
 #[post("/modify", data = "<item>")]
-fn modify_proxy_item<'r>(state: &rocket::State<LopxyManagerServerStatus>, item: Form<ModifyRequest<'r>>) -> Json<String> {
-    let resource_url = decode_url_string(item.resource_url);
-    let proxy_resource_url = decode_url_string(item.proxy_resource_url);
-    let resource_content_type = decode_url_string(item.resource_content_type);
+fn modify_proxy_item<'r>(state: &rocket::State<LopxyManagerServerStatus>, _auth: ManagerAuth, item: Form<ModifyRequest<'r>>) -> Json<String> {
+    let modification = match proxy_item_modification(&item) {
+        Some(modification) => modification,
+        None => {
+            return Json(String::from("{\"result\":false}"));
+        }
+    };
 
-    if resource_url.is_none() || proxy_resource_url.is_none() || resource_content_type.is_none() {
-        return Json(String::from("{\"result\":false}"));
-    }
+    let mut controller = state.controller.lock().unwrap();
+    let proxy_item = modification.apply(controller.find_proxy_item(&modification.resource_url));
 
-    Json(format!("{{\"result\":{}}}", state.controller.lock().unwrap().modify_proxy_item(&resource_url.unwrap(), &proxy_resource_url.unwrap(), &resource_content_type.unwrap())))
+    Json(format!("{{\"result\":{}}}", controller.modify_proxy_item(proxy_item)))
 }
 
 #[get("/is_proxy_enabled")]
-fn is_lopxy_proxy_enabled<'r>(state: &rocket::State<LopxyManagerServerStatus>) -> Json<String> {
+fn is_lopxy_proxy_enabled(state: &rocket::State<LopxyManagerServerStatus>, _auth: ManagerAuth) -> Json<String> {
     Json(format!("{{\"result\":{}}}", state.controller.lock().unwrap().is_system_proxy_enabled()))
 }
 
 #[post("/enable_proxy", data = "<item>")]
-fn set_lopxy_proxy_enabled<'r>(state: &rocket::State<LopxyManagerServerStatus>, item: Form<SetProxyEnabledRequest>) -> Json<String> {
+fn set_lopxy_proxy_enabled(state: &rocket::State<LopxyManagerServerStatus>, _auth: ManagerAuth, item: Form<SetProxyEnabledRequest>) -> Json<String> {
    Json(format!("{{\"result\":{}}}", state.controller.lock().unwrap().set_system_proxy_enabled(item.enabled)))
 }
 
 #[get("/proxy_request_logs")]
-fn proxy_request_logs(state: &rocket::State<LopxyManagerServerStatus>) -> Json<String> {
+fn proxy_request_logs(state: &rocket::State<LopxyManagerServerStatus>, _auth: ManagerAuth) -> Json<String> {
     Json(state.controller.lock().unwrap().proxy_request_logs())
 }
 
 #[get("/status?<config_timestamp>&<status_log_timestamp>")]
-fn lopxy_status<'r>(state: &rocket::State<LopxyManagerServerStatus>, config_timestamp: i64, status_log_timestamp: i64) -> Json<String> {
+fn lopxy_status(state: &rocket::State<LopxyManagerServerStatus>, _auth: ManagerAuth, config_timestamp: i64, status_log_timestamp: i64) -> Json<String> {
     Json(state.controller.lock().unwrap().lopxy_status(config_timestamp, status_log_timestamp))
 }
 
+///
+/// Export root CA certificate of https interception
+///
+#[get("/ca.pem")]
+fn export_certificate_authority(state: &rocket::State<LopxyManagerServerStatus>, _auth: ManagerAuth) -> Option<(rocket::http::ContentType, String)> {
+    let certificate_pem = state.controller.lock().unwrap().certificate_authority_pem()?;
+    Some((rocket::http::ContentType::new("application", "x-pem-file"), certificate_pem))
+}
+
+#[post("/ca/regenerate")]
+fn regenerate_certificate_authority(state: &rocket::State<LopxyManagerServerStatus>, _auth: ManagerAuth) -> Json<String> {
+    Json(format!("{{\"result\":{}}}", state.controller.lock().unwrap().regenerate_certificate_authority()))
+}
+
+///
+/// Host header of request without port
+///
+pub struct RequestHostname(Option<String>);
+
+#[rocket::async_trait]
+impl<'r> FromRequest<'r> for RequestHostname {
+    type Error = ();
+
+    async fn from_request(request: &'r rocket::Request<'_>) -> Outcome<Self, Self::Error> {
+        let hostname = request.headers().get_one("Host").map(|host: &str| {
+            match host.rfind(']') {
+                Some(pos) => host[..pos + 1].to_string(),
+                None => host.split(':').next().unwrap_or("").to_string()
+            }
+        });
+
+        Outcome::Success(RequestHostname(hostname))
+    }
+}
+
+///
+/// Export proxy auto-config file, only hosts with proxy items go through lopxy
+///
+#[get("/proxy.pac")]
+fn proxy_auto_config(state: &rocket::State<LopxyManagerServerStatus>, hostname: RequestHostname) -> (rocket::http::ContentType, String) {
+    let (top, sub) = PAC_CONTENT_TYPE;
+    (rocket::http::ContentType::new(top, sub), state.controller.lock().unwrap().pac_script(hostname.0.as_deref()))
+}
+
+///
+/// Target of requests from clients out of the allowlist
+///
+#[get("/forbidden")]
+fn forbidden() -> rocket::http::Status {
+    rocket::http::Status::Forbidden
+}
+
+///
+/// Get the hosts which skip the upstream proxy
+///
+#[get("/bypass")]
+fn bypass_list(state: &rocket::State<LopxyManagerServerStatus>, _auth: ManagerAuth) -> Json<String> {
+    Json(serde_json::to_string(&state.controller.lock().unwrap().bypass_list()).unwrap_or("[]".to_string()))
+}
+
+///
+/// Replace the bypass list with newline separated entries
+///
+#[post("/bypass", data = "<request>")]
+fn set_bypass_list<'r>(state: &rocket::State<LopxyManagerServerStatus>, _auth: ManagerAuth, request: Form<SetBypassRequest<'r>>) -> Json<String> {
+    let entries = match decode_mirror_urls(Some(request.entries)) {
+        Some(Some(entries)) => entries,
+        _ => {
+            return Json(String::from("{\"result\":false}"));
+        }
+    };
+
+    Json(format!("{{\"result\":{}}}", state.controller.lock().unwrap().set_bypass_list(entries)))
+}
+
+///
+/// Get the entries of offline response cache
+///
+#[get("/cache")]
+fn cache_entries(state: &rocket::State<LopxyManagerServerStatus>, _auth: ManagerAuth) -> Json<String> {
+    Json(serde_json::to_string(&state.controller.lock().unwrap().cache_entries()).unwrap_or("[]".to_string()))
+}
+
+///
+/// Purge the cached responses whose urls match the glob, all of them if it's absent
+///
+#[post("/cache/purge", data = "<request>")]
+fn purge_cache<'r>(state: &rocket::State<LopxyManagerServerStatus>, _auth: ManagerAuth, request: Form<PurgeCacheRequest<'r>>) -> Json<String> {
+    let pattern = match decode_optional_url_string(request.url) {
+        Some(pattern) => pattern,
+        None => {
+            return Json(String::from("{\"result\":false,\"purged\":0}"));
+        }
+    };
+
+    match state.controller.lock().unwrap().purge_cache(pattern.as_deref()) {
+        Some(purged) => Json(format!("{{\"result\":true,\"purged\":{}}}", purged)),
+        None => Json(String::from("{\"result\":false,\"purged\":0}"))
+    }
+}
+
 #[catch(404)]
 fn general_not_found() -> Html<&'static str> {
     Html(r#"
@@ -130,6 +356,7 @@ fn general_not_found() -> Html<&'static str> {
 /// 
 pub fn lopxy_web_manager_routes() -> Vec<rocket::Route> {
     routes![
+        sign_in,
         shutdown,
         list_all_proxy_item,
         add_proxy_item,
@@ -138,7 +365,15 @@ pub fn lopxy_web_manager_routes() -> Vec<rocket::Route> {
         is_lopxy_proxy_enabled,
         set_lopxy_proxy_enabled,
         proxy_request_logs,
-        lopxy_status
+        lopxy_status,
+        export_certificate_authority,
+        regenerate_certificate_authority,
+        proxy_auto_config,
+        forbidden,
+        bypass_list,
+        set_bypass_list,
+        cache_entries,
+        purge_cache
     ]
 }
 
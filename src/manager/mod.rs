@@ -1,63 +1,218 @@
+pub mod auth;
 pub mod controller;
 pub mod request;
 pub mod response;
 
 use std::sync::mpsc;
 use std::sync::Arc;
-use std::net::{IpAddr, Ipv4Addr};
+use std::net::IpAddr;
 
 use urlencoding::encode;
 
 use controller::*;
+use super::proxy::condition::BlockKind;
+use super::proxy::item::*;
 
-async fn launch(port: u32, static_assets_dir: String, controller: LopxyManagerServerControllerArc, shutdown_sign: mpsc::Receiver<bool>) -> Result<(), rocket::Error> {
-    let config = rocket::Config {
-        address: IpAddr::V4(Ipv4Addr::new(0, 0, 0, 0)),
-        port: port as u16,
-        shutdown: rocket::config::Shutdown {
-            force: false,
-            ..rocket::config::Shutdown::default()
-        },
-        ..rocket::Config::default()
-    };
-
-    let server = rocket::custom(&config).
-        attach(rocket::fairing::AdHoc::on_ignite("Lopxy Server Manage State", |rocket| async move {
-            rocket.manage(LopxyManagerServerStatus::new(controller))
-        })).
-        mount("/", lopxy_web_manager_routes()).
-        mount("/", rocket::fs::FileServer::from(static_assets_dir)).
-        register("/", lopxy_web_manager_catcher()).
-        ignite().
-        await?;
-    let shutdown = server.shutdown();
+///
+/// Launch web manager servers on each of `addresses`, all servers stop if any of them is shut down
+///
+async fn launch(addresses: Vec<IpAddr>, port: u32, static_assets_dir: String, token: String, controller: LopxyManagerServerControllerArc, shutdown_sign: mpsc::Receiver<bool>) -> Result<(), rocket::Error> {
+    let mut servers = vec![];
 
+    for address in addresses {
+        let config = rocket::Config {
+            address,
+            port: port as u16,
+            shutdown: rocket::config::Shutdown {
+                force: false,
+                ..rocket::config::Shutdown::default()
+            },
+            ..rocket::Config::default()
+        };
+
+        let state_controller = controller.clone();
+        let state_token = token.clone();
+        let allowlist_controller = controller.clone();
+
+        let server = rocket::custom(&config).
+            attach(rocket::fairing::AdHoc::on_ignite("Lopxy Server Manage State", |rocket| async move {
+                rocket.manage(LopxyManagerServerStatus::new(state_controller, state_token))
+            })).
+            attach(rocket::fairing::AdHoc::on_request("Lopxy Client Allowlist", move |request, _| {
+                let allowed = match request.remote() {
+                    Some(remote) => allowlist_controller.lock().unwrap().is_client_allowed(&remote.ip()),
+                    None => false
+                };
+
+                // route the request of unknown client to forbidden
+                if !allowed {
+                    eprintln!("reject web manager request from {:?}", request.remote());
+                    request.set_method(rocket::http::Method::Get);
+                    request.set_uri(rocket::http::uri::Origin::parse("/forbidden").unwrap());
+                }
+
+                Box::pin(async move {})
+            })).
+            mount("/", lopxy_web_manager_routes()).
+            mount("/", rocket::fs::FileServer::from(static_assets_dir.clone())).
+            register("/", lopxy_web_manager_catcher()).
+            ignite().
+            await?;
+
+        servers.push(server);
+    }
+
+    let shutdowns: Vec<rocket::Shutdown> = servers.iter().map(|server| server.shutdown()).collect();
+
+    let sign_shutdowns = shutdowns.clone();
     rocket::tokio::spawn(async move {
         if match shutdown_sign.recv() {
             Ok(v) => v,
             Err(_) => false
         } {
-            shutdown.notify();
+            sign_shutdowns.into_iter().for_each(|shutdown: rocket::Shutdown| shutdown.notify());
         }
     });
 
-    server.launch().await.expect("web manager server panic");
+    let launches = servers.into_iter().map(|server| {
+        let shutdowns = shutdowns.clone();
+        async move {
+            let result = server.launch().await;
+            shutdowns.into_iter().for_each(|shutdown: rocket::Shutdown| shutdown.notify());
+            result
+        }
+    });
+
+    for result in futures::future::join_all(launches).await {
+        result.expect("web manager server panic");
+    }
+
+    Ok(())
+}
+
+///
+/// Collect the form params of add and modify request, `None` fields are not sent
+///
+fn proxy_item_form_params(modification: &ProxyItemModification) -> std::collections::HashMap<&'static str, String> {
+    let mut params = std::collections::HashMap::new();
+    params.insert("resource", encode(&modification.resource_url).to_string());
+    params.insert("resource_proxy", encode(&modification.proxy_resource_url).to_string());
+    params.insert("resource_content_type", encode(&modification.content_type).to_string());
+    if let Some(match_kind) = modification.match_kind {
+        params.insert("match_kind", match_kind.to_string());
+    }
+    if let Some(directory_index) = modification.directory_index {
+        params.insert("directory_index", directory_index.to_string());
+    }
+    if let Some(directory_listing) = modification.directory_listing {
+        params.insert("directory_listing", directory_listing.to_string());
+    }
+    if let Some(mode) = modification.mode {
+        params.insert("mode", mode.to_string());
+    }
+    if let Some(fallback_status) = &modification.fallback_status {
+        params.insert("fallback_status", encode(fallback_status).to_string());
+    }
+    if let Some(fallback_timeout) = modification.fallback_timeout {
+        params.insert("fallback_timeout", fallback_timeout.to_string());
+    }
+    if let Some(redirect_status) = modification.redirect_status {
+        params.insert("redirect_status", redirect_status.to_string());
+    }
+    if let Some(preserve_query) = modification.preserve_query {
+        params.insert("preserve_query", preserve_query.to_string());
+    }
+    if let Some(mirror_urls) = &modification.mirror_urls {
+        params.insert("mirrors", encode(&mirror_urls.join("\n")).to_string());
+    }
+    if let Some(mirror_strategy) = modification.mirror_strategy {
+        params.insert("mirror_strategy", mirror_strategy.to_string());
+    }
+    if let Some(upstream_proxy) = &modification.upstream_proxy {
+        params.insert("upstream_proxy", encode(upstream_proxy).to_string());
+    }
+    if let Some(rule_set) = &modification.rule_set {
+        params.insert("rule_set", encode(rule_set).to_string());
+    }
+    if let Some(target_kind) = modification.target_kind {
+        params.insert("target_kind", target_kind.to_string());
+    }
+    if let Some(mock_status) = modification.mock_status {
+        params.insert("mock_status", mock_status.to_string());
+    }
+    if let Some(mock_headers) = &modification.mock_headers {
+        params.insert("mock_headers", encode(&mock_headers.join("\n")).to_string());
+    }
+    if let Some(mock_body) = &modification.mock_body {
+        params.insert("mock_body", encode(mock_body).to_string());
+    }
+    if let Some(mock_body_encoding) = modification.mock_body_encoding {
+        params.insert("mock_body_encoding", mock_body_encoding.to_string());
+    }
+    if let Some(block) = modification.block {
+        params.insert("block", block.map(|block: BlockKind| block.to_string()).unwrap_or_default());
+    }
+    if let Some(block_status) = modification.block_status {
+        params.insert("block_status", block_status.to_string());
+    }
+    if let Some(failure_rate) = modification.failure_rate {
+        params.insert("failure_rate", failure_rate.to_string());
+    }
+    if let Some(latency) = modification.latency {
+        params.insert("latency", latency.to_string());
+    }
+    if let Some(bandwidth) = modification.bandwidth {
+        params.insert("bandwidth", bandwidth.to_string());
+    }
+    if let Some(request_headers) = &modification.request_headers {
+        params.insert("request_headers", encode(&request_headers.join("\n")).to_string());
+    }
+    if let Some(response_headers) = &modification.response_headers {
+        params.insert("response_headers", encode(&response_headers.join("\n")).to_string());
+    }
+    if let Some(body_rules) = &modification.body_rules {
+        params.insert("body_rules", encode(&body_rules.join("\n")).to_string());
+    }
+    params
+}
+
+///
+/// Build the client of web manager api, the token in config dir is sent if any
+///
+fn manager_client() -> reqwest::Result<reqwest::Client> {
+    let mut headers = reqwest::header::HeaderMap::new();
 
-    Ok(())        
+    let token = super::util::config::program_config_dir(env!("CARGO_PKG_NAME")).
+        and_then(|config_dir: std::path::PathBuf| auth::load_token(&config_dir));
+
+    if let Some(token) = token {
+        if let Ok(value) = reqwest::header::HeaderValue::from_str(&format!("Bearer {}", token)) {
+            headers.insert(reqwest::header::AUTHORIZATION, value);
+        }
+    }
+
+    reqwest::Client::builder().
+        no_proxy().
+        default_headers(headers).
+        build()
 }
 
 pub struct LopxyManagerServer {
+    addresses: Vec<IpAddr>,
     port: u32,
     static_assets_dir: String,
+    token: String,
     controller: LopxyManagerServerControllerArc,
     shutdown_sign_trigger: Option<mpsc::Sender<bool>>
 }
 
 impl LopxyManagerServer {
-    pub fn build(port: u32, static_assets_dir: String, controller: LopxyManagerServerControllerArc) -> LopxyManagerServer {
+    pub fn build(addresses: Vec<IpAddr>, port: u32, static_assets_dir: String, token: String, controller: LopxyManagerServerControllerArc) -> LopxyManagerServer {
         LopxyManagerServer {
+            addresses,
             port,
             static_assets_dir,
+            token,
             controller,
             shutdown_sign_trigger: None
         }
@@ -73,12 +228,14 @@ impl LopxyManagerServer {
         let (trigger, shutdown_sign): (mpsc::Sender<bool>, mpsc::Receiver<bool>) = mpsc::channel();
         self.shutdown_sign_trigger = Some(trigger);
 
+        let server_addresses = self.addresses.clone();
         let server_port = self.port;
         let server_controller = Arc::clone(&self.controller);
         let static_assets_dir = self.static_assets_dir.clone();
+        let token = self.token.clone();
         
         rocket::tokio::spawn(async move {
-            launch(server_port, static_assets_dir, server_controller, shutdown_sign).await.expect("web manager server launch failed");
+            launch(server_addresses, server_port, static_assets_dir, token, server_controller, shutdown_sign).await.expect("web manager server launch failed");
         })
     }
 
@@ -92,40 +249,34 @@ impl LopxyManagerServer {
     }
 
     pub async fn stop_lopxy_server(web_manager_url: &str) -> reqwest::Result<String> {
-        reqwest::Client::builder().
-            no_proxy().
-            build()?.
-            get(format!("{}/shutdown", web_manager_url)).
+        manager_client()?.
+            post(format!("{}/shutdown", web_manager_url)).
             send().
             await?.
+            error_for_status()?.
             text().
             await
     }
 
     pub async fn list_all_proxy_item(web_manager_url: &str) -> reqwest::Result<String> {
-        reqwest::Client::builder().
-            no_proxy().
-            build()?.
+        manager_client()?.
             get(format!("{}/list", web_manager_url)).
             send().
             await?.
+            error_for_status()?.
             text().
             await
     }
 
-    pub async fn add_proxy_item(web_manager_url: &str, resource_url: &str, proxy_resource_url: &str, content_type: &str) -> reqwest::Result<String> {
-        let mut params = std::collections::HashMap::new();
-        params.insert("resource", encode(resource_url));
-        params.insert("resource_proxy", encode(proxy_resource_url));
-        params.insert("resource_content_type", encode(content_type));
-        
-        let response = reqwest::Client::builder().
-            no_proxy().
-            build()?.
+    pub async fn add_proxy_item(web_manager_url: &str, modification: &ProxyItemModification) -> reqwest::Result<String> {
+        let params = proxy_item_form_params(modification);
+
+        let response = manager_client()?.
             post(format!("{}/add", web_manager_url)).
             form(&params).
             send().
             await?.
+            error_for_status()?.
             json::<response::AddResponse>().
             await.
             expect("add proxy item expect");
@@ -141,13 +292,12 @@ impl LopxyManagerServer {
         let mut params = std::collections::HashMap::new();
         params.insert("resource", encode(resource_url));
 
-        let response = reqwest::Client::builder().
-            no_proxy().
-            build()?.
+        let response = manager_client()?.
             delete(format!("{}/remove", web_manager_url)).
             form(&params).
             send().
             await?.
+            error_for_status()?.
             json::<response::RemoveResponse>().
             await.
             expect("remove proxy item expect");
@@ -159,19 +309,15 @@ impl LopxyManagerServer {
         })
     }
 
-    pub async fn modify_proxy_item(web_manager_url: &str, resource_url: &str, proxy_resource_url: &str, content_type: &str) -> reqwest::Result<String> {
-        let mut params = std::collections::HashMap::new();
-        params.insert("resource", encode(resource_url));
-        params.insert("resource_proxy", encode(proxy_resource_url));
-        params.insert("resource_content_type", encode(content_type));
+    pub async fn modify_proxy_item(web_manager_url: &str, modification: &ProxyItemModification) -> reqwest::Result<String> {
+        let params = proxy_item_form_params(modification);
 
-        let response = reqwest::Client::builder().
-            no_proxy().
-            build()?.
+        let response = manager_client()?.
             post(format!("{}/modify", web_manager_url)).
             form(&params).
             send().
             await?.
+            error_for_status()?.
             json::<response::ModifyResponse>().
             await.
             expect("modify proxy item expect");
@@ -184,12 +330,11 @@ impl LopxyManagerServer {
     }
 
     pub async fn is_lopxy_proxy_enabled(web_manager_url: &str) -> reqwest::Result<bool> {
-        Ok(reqwest::Client::builder().
-            no_proxy().
-            build()?.
+        Ok(manager_client()?.
             get(format!("{}/is_proxy_enabled", web_manager_url)).
             send().
             await?.
+            error_for_status()?.
             json::<response::IsProxyEnabledResponse>().
             await.
             expect("get lopxy proxy enabled status failed").
@@ -203,13 +348,12 @@ impl LopxyManagerServer {
 
         let method = if enabled { "enable" } else { "disable" };
 
-        let response = reqwest::Client::builder().
-            no_proxy().
-            build()?.
+        let response = manager_client()?.
             post(format!("{}/enable_proxy", web_manager_url)).
             form(&params).
             send().
             await?.
+            error_for_status()?.
             json::<response::SetProxyEnabledResponse>().
             await.
             expect(&format!("{} lopxy proxy expect", method));
@@ -220,4 +364,98 @@ impl LopxyManagerServer {
             format!("{} lopxy proxy failed\r\n", method)
         })
     }
-}
\ No newline at end of file
+
+    pub async fn export_certificate_authority(web_manager_url: &str) -> reqwest::Result<String> {
+        manager_client()?.
+            get(format!("{}/ca.pem", web_manager_url)).
+            send().
+            await?.
+            error_for_status()?.
+            text().
+            await
+    }
+
+    pub async fn regenerate_certificate_authority(web_manager_url: &str) -> reqwest::Result<String> {
+        let response = manager_client()?.
+            post(format!("{}/ca/regenerate", web_manager_url)).
+            send().
+            await?.
+            error_for_status()?.
+            json::<response::RegenerateCertificateAuthorityResponse>().
+            await.
+            expect("regenerate lopxy CA expect");
+
+        Ok(if response.result {
+            "".to_string()
+        } else {
+            "regenerate lopxy CA failed\r\n".to_string()
+        })
+    }
+
+    pub async fn bypass_list(web_manager_url: &str) -> reqwest::Result<Vec<String>> {
+        manager_client()?.
+            get(format!("{}/bypass", web_manager_url)).
+            send().
+            await?.
+            error_for_status()?.
+            json::<Vec<String>>().
+            await
+    }
+
+    pub async fn set_bypass_list(web_manager_url: &str, entries: &[String]) -> reqwest::Result<String> {
+        let mut params = std::collections::HashMap::new();
+        params.insert("entries", encode(&entries.join("\n")).to_string());
+
+        let response = manager_client()?.
+            post(format!("{}/bypass", web_manager_url)).
+            form(&params).
+            send().
+            await?.
+            error_for_status()?.
+            json::<response::SetBypassResponse>().
+            await.
+            expect("set bypass list expect");
+
+        Ok(if response.result {
+            "".to_string()
+        } else {
+            "set bypass list failed\r\n".to_string()
+        })
+    }
+
+    pub async fn cache_entries(web_manager_url: &str) -> reqwest::Result<Vec<super::proxy::cache::CacheEntry>> {
+        manager_client()?.
+            get(format!("{}/cache", web_manager_url)).
+            send().
+            await?.
+            error_for_status()?.
+            json::<Vec<super::proxy::cache::CacheEntry>>().
+            await
+    }
+
+    ///
+    /// Purge cached responses, return the count of purged ones, None if the glob is rejected
+    ///
+    pub async fn purge_cache(web_manager_url: &str, pattern: Option<&str>) -> reqwest::Result<Option<usize>> {
+        let mut params = std::collections::HashMap::new();
+        if let Some(pattern) = pattern {
+            params.insert("url", encode(pattern).to_string());
+        }
+
+        let response = manager_client()?.
+            post(format!("{}/cache/purge", web_manager_url)).
+            form(&params).
+            send().
+            await?.
+            error_for_status()?.
+            json::<response::PurgeCacheResponse>().
+            await.
+            expect("purge cache expect");
+
+        Ok(if response.result {
+            Some(response.purged)
+        } else {
+            None
+        })
+    }
+}
@@ -1,5 +1,9 @@
+///
+/// Proxy item fields of add and modify request, absent optional fields use the default
+/// values when adding and keep the current values when modifying
+///
 #[derive(FromForm)]
-pub struct AddRequest<'r> {
+pub struct ProxyItemRequest<'r> {
     #[field(name = "resource")]
     pub resource_url: &'r str,
 
@@ -7,29 +11,107 @@ pub struct AddRequest<'r> {
     pub proxy_resource_url: &'r str,
 
     #[field(name = "resource_content_type")]
-    pub resource_content_type: &'r str
+    pub resource_content_type: &'r str,
+
+    #[field(name = "match_kind")]
+    pub match_kind: Option<&'r str>,
+
+    #[field(name = "directory_index")]
+    pub directory_index: Option<bool>,
+
+    #[field(name = "directory_listing")]
+    pub directory_listing: Option<bool>,
+
+    #[field(name = "mode")]
+    pub mode: Option<&'r str>,
+
+    #[field(name = "fallback_status")]
+    pub fallback_status: Option<&'r str>,
+
+    #[field(name = "fallback_timeout")]
+    pub fallback_timeout: Option<u64>,
+
+    #[field(name = "redirect_status")]
+    pub redirect_status: Option<u16>,
+
+    #[field(name = "preserve_query")]
+    pub preserve_query: Option<bool>,
+
+    #[field(name = "mirrors")]
+    pub mirror_urls: Option<&'r str>,
+
+    #[field(name = "mirror_strategy")]
+    pub mirror_strategy: Option<&'r str>,
+
+    #[field(name = "upstream_proxy")]
+    pub upstream_proxy: Option<&'r str>,
+
+    #[field(name = "rule_set")]
+    pub rule_set: Option<&'r str>,
+
+    #[field(name = "target_kind")]
+    pub target_kind: Option<&'r str>,
+
+    #[field(name = "mock_status")]
+    pub mock_status: Option<u16>,
+
+    #[field(name = "mock_headers")]
+    pub mock_headers: Option<&'r str>,
+
+    #[field(name = "mock_body")]
+    pub mock_body: Option<&'r str>,
+
+    #[field(name = "mock_body_encoding")]
+    pub mock_body_encoding: Option<&'r str>,
+
+    #[field(name = "block")]
+    pub block: Option<&'r str>,
+
+    #[field(name = "block_status")]
+    pub block_status: Option<u16>,
+
+    #[field(name = "failure_rate")]
+    pub failure_rate: Option<u8>,
+
+    #[field(name = "latency")]
+    pub latency: Option<u64>,
+
+    #[field(name = "bandwidth")]
+    pub bandwidth: Option<u64>,
+
+    #[field(name = "request_headers")]
+    pub request_headers: Option<&'r str>,
+
+    #[field(name = "response_headers")]
+    pub response_headers: Option<&'r str>,
+
+    #[field(name = "body_rules")]
+    pub body_rules: Option<&'r str>
 }
 
+pub type AddRequest<'r> = ProxyItemRequest<'r>;
+
+pub type ModifyRequest<'r> = ProxyItemRequest<'r>;
+
 #[derive(FromForm)]
 pub struct RemoveRequest<'r> {
     #[field(name = "resource")]
     pub resource_url: &'r str
 }
 
-#[derive(FromForm)]
-pub struct ModifyRequest<'r> {
-    #[field(name = "resource")]
-    pub resource_url: &'r str,
-
-    #[field(name = "resource_proxy")]
-    pub proxy_resource_url: &'r str,
-
-    #[field(name = "resource_content_type")]
-    pub resource_content_type: &'r str
-}
-
 #[derive(FromForm)]
 pub struct SetProxyEnabledRequest {
     #[field(name = "enabled")]
     pub enabled: bool,
-}
\ No newline at end of file
+}
+#[derive(FromForm)]
+pub struct SetBypassRequest<'r> {
+    #[field(name = "entries")]
+    pub entries: &'r str
+}
+
+#[derive(FromForm)]
+pub struct PurgeCacheRequest<'r> {
+    #[field(name = "url")]
+    pub url: Option<&'r str>
+}
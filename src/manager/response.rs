@@ -23,4 +23,19 @@ pub struct SetProxyEnabledResponse {
 #[derive(Deserialize)]
 pub struct IsProxyEnabledResponse {
     pub result: bool
-}
\ No newline at end of file
+}
+
+#[derive(Deserialize)]
+pub struct RegenerateCertificateAuthorityResponse {
+    pub result: bool
+}
+#[derive(Deserialize)]
+pub struct SetBypassResponse {
+    pub result: bool
+}
+
+#[derive(Deserialize)]
+pub struct PurgeCacheResponse {
+    pub result: bool,
+    pub purged: usize
+}
@@ -0,0 +1,86 @@
+use serde_derive::{Serialize, Deserialize};
+
+use super::item::ProxyItem;
+
+/// Realm of the `Proxy-Authenticate` challenge
+pub const PROXY_AUTH_REALM: &str = "lopxy";
+
+///
+/// User of proxy server, it can use the proxy items without rule set and the ones in `rule_sets`
+///
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct ProxyUser {
+    pub username: String,
+    pub password: String,
+    #[serde(default)]
+    pub rule_sets: Vec<String>,
+}
+
+impl ProxyUser {
+    ///
+    /// Check the credential of user, the password is compared in constant time
+    ///
+    pub fn verify(&self, username: &str, password: &str) -> bool {
+        let expected = self.password.as_bytes();
+        let provided = password.as_bytes();
+
+        let password_matches = expected.len() == provided.len() &&
+            expected.iter().zip(provided.iter()).fold(0u8, |diff: u8, (a, b): (&u8, &u8)| diff | (a ^ b)) == 0;
+
+        self.username.eq(username) && password_matches
+    }
+
+    ///
+    /// Check whether the user is bound to the rule set of proxy item
+    ///
+    pub fn can_use(&self, item: &ProxyItem) -> bool {
+        match item.rule_set() {
+            Some(rule_set) => self.rule_sets.iter().any(|bound: &String| bound.eq(rule_set)),
+            None => true
+        }
+    }
+}
+
+///
+/// Parse the username and password of `Basic` credential, like the value of `Proxy-Authorization`
+///
+pub fn parse_basic_credential(value: &str) -> Option<(String, String)> {
+    let (scheme, credential) = value.trim().split_once(' ')?;
+    if !scheme.eq_ignore_ascii_case("basic") {
+        return None;
+    }
+
+    let credential = String::from_utf8(base64::decode(credential.trim()).ok()?).ok()?;
+    let (username, password) = credential.split_once(':')?;
+
+    Some((username.to_string(), password.to_string()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn verify_basic_credential() {
+        assert_eq!(parse_basic_credential("Basic YWxpY2U6czNjcjN0"), Some(("alice".to_string(), "s3cr3t".to_string())));
+        assert_eq!(parse_basic_credential("basic  Ym9iOmE6Yg=="), Some(("bob".to_string(), "a:b".to_string())));
+        assert_eq!(parse_basic_credential("Bearer YWxpY2U6czNjcjN0"), None);
+        assert_eq!(parse_basic_credential("Basic !!!"), None);
+
+        let user = ProxyUser {
+            username: "alice".to_string(),
+            password: "s3cr3t".to_string(),
+            rule_sets: vec!["frontend".to_string()]
+        };
+        assert!(user.verify("alice", "s3cr3t"));
+        assert!(!user.verify("alice", "s3cr3"));
+        assert!(!user.verify("bob", "s3cr3t"));
+
+        let mut item = ProxyItem::new("http://a.com/x.js", "http://b.com/x.js", "auto");
+        assert!(user.can_use(&item));
+        item.update_rule_set(Some("frontend".to_string()));
+        assert!(user.can_use(&item));
+        item.update_rule_set(Some("backend".to_string()));
+        assert!(!user.can_use(&item));
+    }
+}
@@ -0,0 +1,388 @@
+use std::io::Read;
+
+/// Max size of response body buffered for rewriting, a larger body is sent as is
+pub const REWRITE_BODY_LIMIT: usize = 16 * 1024 * 1024;
+
+///
+/// Rule which rewrites response body, written as text in config :
+///
+/// `text search => replacement`, `regex pattern => replacement`, `json-set /pointer => value` or `json-remove /pointer`
+///
+#[derive(Debug, Clone)]
+pub enum BodyRule {
+    /// Replace all occurrences of the literal text
+    Text(String, String),
+    /// Replace all matches of the regex, `$1` refers to the captured groups
+    Regex(regex::Regex, String),
+    /// Set the value at JSON Pointer, the parent must exist
+    JsonSet(String, serde_json::Value),
+    /// Remove the value at JSON Pointer
+    JsonRemove(String),
+}
+
+impl BodyRule {
+    ///
+    /// Parse a body rule, None if it's invalid
+    ///
+    pub fn parse(rule: &str) -> Option<BodyRule> {
+        let (action, rest) = rule.trim().split_once(char::is_whitespace)?;
+        let rest = rest.trim();
+
+        let split_target = || -> Option<(&str, &str)> {
+            let (target, value) = rest.split_once("=>")?;
+            Some((target.trim(), value.trim()))
+        };
+
+        let is_pointer = |pointer: &str| pointer.is_empty() || pointer.starts_with('/');
+
+        match action.to_lowercase().as_str() {
+            "text" => {
+                let (search, replacement) = split_target()?;
+                match search.is_empty() {
+                    true => None,
+                    false => Some(BodyRule::Text(search.to_string(), replacement.to_string()))
+                }
+            },
+            "regex" => {
+                let (pattern, replacement) = split_target()?;
+                Some(BodyRule::Regex(regex::Regex::new(pattern).ok()?, replacement.to_string()))
+            },
+            "json-set" => {
+                let (pointer, value) = split_target()?;
+                match is_pointer(pointer) {
+                    true => Some(BodyRule::JsonSet(pointer.to_string(), serde_json::from_str(value).ok()?)),
+                    false => None
+                }
+            },
+            "json-remove" if rest.starts_with('/') => Some(BodyRule::JsonRemove(rest.to_string())),
+            _ => None
+        }
+    }
+
+    ///
+    /// Parse all body rules, None if any of them is invalid
+    ///
+    pub fn parse_all<S: AsRef<str>>(rules: &[S]) -> Option<Vec<BodyRule>> {
+        rules.iter().map(|rule: &S| BodyRule::parse(rule.as_ref())).collect()
+    }
+
+    pub fn is_json(&self) -> bool {
+        matches!(self, BodyRule::JsonSet(..) | BodyRule::JsonRemove(..))
+    }
+}
+
+fn media_type(content_type: &str) -> String {
+    content_type.split(';').next().unwrap_or("").trim().to_lowercase()
+}
+
+pub fn is_json_content_type(content_type: &str) -> bool {
+    let media_type = media_type(content_type);
+    media_type == "application/json" || media_type == "text/json" || media_type.ends_with("+json")
+}
+
+pub fn is_text_content_type(content_type: &str) -> bool {
+    let media_type = media_type(content_type);
+    media_type.starts_with("text/") ||
+        media_type.ends_with("+xml") ||
+        media_type.ends_with("/xml") ||
+        media_type.ends_with("/javascript") ||
+        media_type.ends_with("/ecmascript") ||
+        media_type == "application/x-www-form-urlencoded" ||
+        is_json_content_type(content_type)
+}
+
+///
+/// Check whether any rule applies to the content type, text rules apply to all text types and JSON rules to JSON types
+///
+pub fn is_rewritable(rules: &[BodyRule], content_type: &str) -> bool {
+    rules.iter().any(|rule: &BodyRule| match rule.is_json() {
+        true => is_json_content_type(content_type),
+        false => is_text_content_type(content_type)
+    })
+}
+
+///
+/// Decode the body by `content-encoding`, the codings are undone from the last one
+///
+/// # Notes
+/// The decoded body is bounded by `REWRITE_BODY_LIMIT`, a larger one is an error so that the original
+/// body is sent as is
+pub fn decode_content(content_encoding: &str, body: &[u8]) -> Result<Vec<u8>, String> {
+    let mut body = body.to_vec();
+    let limit = REWRITE_BODY_LIMIT as u64 + 1;
+
+    for coding in content_encoding.rsplit(',').map(|coding: &str| coding.trim().to_lowercase()).filter(|coding: &String| !coding.is_empty()) {
+        let mut decoded = vec![];
+        let result = match coding.as_str() {
+            "identity" => {
+                continue;
+            },
+            "gzip" | "x-gzip" => flate2::read::MultiGzDecoder::new(&body[..]).take(limit).read_to_end(&mut decoded),
+            "br" => brotli_decompressor::Decompressor::new(&body[..], 4096).take(limit).read_to_end(&mut decoded),
+            "deflate" => {
+                // deflate is zlib wrapped by the spec, but some servers send the raw one
+                match flate2::read::ZlibDecoder::new(&body[..]).take(limit).read_to_end(&mut decoded) {
+                    Ok(size) => Ok(size),
+                    Err(_) => {
+                        decoded.clear();
+                        flate2::read::DeflateDecoder::new(&body[..]).take(limit).read_to_end(&mut decoded)
+                    }
+                }
+            },
+            _ => {
+                return Err(format!("unsupported content encoding {}", coding));
+            }
+        };
+
+        if let Err(err) = result {
+            return Err(format!("decode {} content failed, {}", coding, err));
+        }
+
+        if decoded.len() > REWRITE_BODY_LIMIT {
+            return Err(format!("decoded {} content is larger than {} bytes", coding, REWRITE_BODY_LIMIT));
+        }
+
+        body = decoded;
+    }
+
+    Ok(body)
+}
+
+fn unescape_pointer_token(token: &str) -> String {
+    token.replace("~1", "/").replace("~0", "~")
+}
+
+///
+/// Set the value at JSON Pointer, return whether it's set
+///
+/// # Notes
+/// The parent of the pointer must exist, `-` appends to an array
+pub fn json_pointer_set(document: &mut serde_json::Value, pointer: &str, value: serde_json::Value) -> bool {
+    if pointer.is_empty() {
+        *document = value;
+        return true;
+    }
+
+    let (parent, token) = match pointer.rsplit_once('/') {
+        Some(parts) => parts,
+        None => {
+            return false;
+        }
+    };
+    let token = unescape_pointer_token(token);
+
+    match document.pointer_mut(parent) {
+        Some(serde_json::Value::Object(object)) => {
+            object.insert(token, value);
+            true
+        },
+        Some(serde_json::Value::Array(array)) => {
+            if token == "-" {
+                array.push(value);
+                return true;
+            }
+
+            match token.parse::<usize>().ok().and_then(|index: usize| array.get_mut(index)) {
+                Some(item) => {
+                    *item = value;
+                    true
+                },
+                None => false
+            }
+        },
+        _ => false
+    }
+}
+
+///
+/// Remove the value at JSON Pointer, return whether it existed
+///
+pub fn json_pointer_remove(document: &mut serde_json::Value, pointer: &str) -> bool {
+    let (parent, token) = match pointer.rsplit_once('/') {
+        Some(parts) => parts,
+        None => {
+            return false;
+        }
+    };
+    let token = unescape_pointer_token(token);
+
+    match document.pointer_mut(parent) {
+        Some(serde_json::Value::Object(object)) => object.remove(&token).is_some(),
+        Some(serde_json::Value::Array(array)) => {
+            match token.parse::<usize>() {
+                Ok(index) if index < array.len() => {
+                    array.remove(index);
+                    true
+                },
+                _ => false
+            }
+        },
+        _ => false
+    }
+}
+
+///
+/// Rewrite the decoded body by the rules which apply to the content type, return the new body and the count of changes
+///
+pub fn rewrite_body(rules: &[BodyRule], content_type: &str, body: &[u8]) -> Result<(Vec<u8>, usize), String> {
+    let mut text = match String::from_utf8(body.to_vec()) {
+        Ok(text) => text,
+        Err(_) => {
+            return Err("body is not utf-8 text".to_string());
+        }
+    };
+    let mut changes = 0;
+
+    for rule in rules.iter() {
+        match rule {
+            BodyRule::Text(search, replacement) if is_text_content_type(content_type) => {
+                changes += text.matches(search.as_str()).count();
+                text = text.replace(search.as_str(), replacement);
+            },
+            BodyRule::Regex(pattern, replacement) if is_text_content_type(content_type) => {
+                changes += pattern.find_iter(&text).count();
+                text = pattern.replace_all(&text, replacement.as_str()).to_string();
+            },
+            BodyRule::JsonSet(..) | BodyRule::JsonRemove(..) if is_json_content_type(content_type) => {
+                let mut document: serde_json::Value = match serde_json::from_str(&text) {
+                    Ok(document) => document,
+                    Err(err) => {
+                        return Err(format!("body is not json, {}", err));
+                    }
+                };
+
+                let changed = match rule {
+                    BodyRule::JsonSet(pointer, value) => json_pointer_set(&mut document, pointer, value.clone()),
+                    BodyRule::JsonRemove(pointer) => json_pointer_remove(&mut document, pointer),
+                    _ => false
+                };
+
+                if changed {
+                    changes += 1;
+                    text = document.to_string();
+                }
+            },
+            _ => {}
+        }
+    }
+
+    Ok((text.into_bytes(), changes))
+}
+
+///
+/// Rewrite the body of a raw response, the framing and encoding headers are fixed up for the new body
+///
+/// # Notes
+/// Return `Ok(None)` if the response is not a successful one with a body any rule applies to, the
+/// decoded body is sent without `content-encoding`
+pub fn rewrite_raw_response(raw_response_bytes: &[u8], rules: &[BodyRule]) -> Result<Option<(Vec<u8>, usize)>, String> {
+    let head_end = match raw_response_bytes.windows(4).position(|window: &[u8]| window == b"\r\n\r\n") {
+        Some(pos) => pos,
+        None => {
+            return Ok(None);
+        }
+    };
+
+    let head = String::from_utf8_lossy(&raw_response_bytes[..head_end]);
+    let mut lines = head.split("\r\n");
+    let status_line = lines.next().unwrap_or("");
+    let status: u16 = status_line.split_ascii_whitespace().nth(1).and_then(|status: &str| status.parse().ok()).unwrap_or(0);
+
+    if !(200..300).contains(&status) || status == 204 || status == 206 {
+        return Ok(None);
+    }
+
+    let headers: Vec<(String, String)> = lines.
+        filter_map(|line: &str| line.split_once(':')).
+        map(|(name, value)| (name.trim().to_string(), value.trim().to_string())).
+        collect();
+
+    let header_value = |header_name: &str| -> String {
+        headers.iter().
+            find(|(name, _): &&(String, String)| name.eq_ignore_ascii_case(header_name)).
+            map(|(_, value): &(String, String)| value.clone()).
+            unwrap_or_default()
+    };
+
+    let content_type = header_value("content-type");
+    if !is_rewritable(rules, &content_type) {
+        return Ok(None);
+    }
+
+    let body = decode_content(&header_value("content-encoding"), &raw_response_bytes[head_end + 4..])?;
+    let (body, changes) = rewrite_body(rules, &content_type, &body)?;
+
+    let mut rewritten = format!("{}\r\n", status_line);
+    for (name, value) in headers.iter() {
+        if ["content-length", "content-encoding", "transfer-encoding", "connection"].iter().any(|framing: &&str| name.eq_ignore_ascii_case(framing)) {
+            continue;
+        }
+        rewritten.push_str(&format!("{}: {}\r\n", name, value));
+    }
+    rewritten.push_str(&format!("content-length: {}\r\n\r\n", body.len()));
+
+    let mut rewritten = rewritten.into_bytes();
+    rewritten.extend_from_slice(&body);
+
+    Ok(Some((rewritten, changes)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn rewrite_response_bodies() {
+        let rules = BodyRule::parse_all(&[
+            "text http://dead.cdn.com/ => https://live.cdn.com/",
+            "regex v(\\d+)\\.js => v$1.min.js",
+            "json-set /api/host => \"live.api.com\"",
+            "json-remove /debug",
+        ]).unwrap();
+
+        let script = b"HTTP/1.1 200 OK\r\ncontent-type: application/javascript\r\ncontent-length: 33\r\n\r\nload('http://dead.cdn.com/v2.js')";
+        let (rewritten, changes) = rewrite_raw_response(script, &rules).unwrap().unwrap();
+        assert_eq!(changes, 2);
+        assert_eq!(
+            String::from_utf8(rewritten).unwrap(),
+            "HTTP/1.1 200 OK\r\ncontent-type: application/javascript\r\ncontent-length: 38\r\n\r\nload('https://live.cdn.com/v2.min.js')"
+        );
+
+        let mut gzipped = vec![];
+        {
+            use std::io::Write;
+            let mut encoder = flate2::write::GzEncoder::new(&mut gzipped, flate2::Compression::default());
+            encoder.write_all(br#"{"api":{"host":"dead.api.com"},"debug":true}"#).unwrap();
+            encoder.finish().unwrap();
+        }
+
+        let mut json = b"HTTP/1.1 200 OK\r\ncontent-type: application/json\r\ncontent-encoding: gzip\r\ntransfer-encoding: chunked\r\n\r\n".to_vec();
+        json.extend_from_slice(&gzipped);
+        let (rewritten, changes) = rewrite_raw_response(&json, &rules).unwrap().unwrap();
+        assert_eq!(changes, 2);
+        assert!(String::from_utf8(rewritten).unwrap().ends_with("content-length: 31\r\n\r\n{\"api\":{\"host\":\"live.api.com\"}}"));
+
+        let image = b"HTTP/1.1 200 OK\r\ncontent-type: image/png\r\n\r\n\x89PNG";
+        assert!(rewrite_raw_response(image, &rules).unwrap().is_none());
+
+        let not_found = b"HTTP/1.1 404 Not Found\r\ncontent-type: text/html\r\n\r\nhttp://dead.cdn.com/";
+        assert!(rewrite_raw_response(not_found, &rules).unwrap().is_none());
+
+        let unknown = b"HTTP/1.1 200 OK\r\ncontent-type: text/plain\r\ncontent-encoding: zstd\r\n\r\n...";
+        assert!(rewrite_raw_response(unknown, &rules).is_err());
+
+        // a body decoded over the limit is not rewritten
+        let mut bomb = vec![];
+        {
+            use std::io::Write;
+            let mut encoder = flate2::write::GzEncoder::new(&mut bomb, flate2::Compression::best());
+            encoder.write_all(&vec![b'a'; REWRITE_BODY_LIMIT + 1]).unwrap();
+            encoder.finish().unwrap();
+        }
+        assert!(decode_content("gzip", &bomb).is_err());
+
+        assert!(BodyRule::parse("json-set api/host => 1").is_none());
+        assert!(BodyRule::parse("json-set /api/host => not json").is_none());
+        assert!(BodyRule::parse("text => x").is_none());
+    }
+}
@@ -0,0 +1,255 @@
+use std::net::IpAddr;
+
+use super::super::util::network::IpNetwork;
+
+///
+/// Rule of a bypass entry
+///
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum BypassRule {
+    /// `*` matches all hosts
+    All,
+    /// `<local>` matches hostnames without a dot and loopback addresses
+    Local,
+    /// `example.com` and `.example.com` match the domain and its subdomains
+    Domain(String),
+    /// `*.example.com` or `192.168.*`, `*` matches any characters
+    Wildcard(String),
+    /// `10.0.0.0/8` or `fd00::/8`, a bare ip address is a network of itself
+    Network(IpNetwork),
+}
+
+///
+/// Entry of bypass list, an entry with port only matches that port
+///
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct BypassEntry {
+    rule: BypassRule,
+    port: Option<u16>,
+}
+
+impl BypassEntry {
+    ///
+    /// Parse bypass entry, None if it's invalid
+    ///
+    /// # Notes
+    /// The scheme prefix like `http://` of windows proxy override is ignored
+    pub fn parse(entry: &str) -> Option<BypassEntry> {
+        let mut entry = entry.trim().to_lowercase();
+        if let Some(pos) = entry.find("://") {
+            entry = entry[pos + 3..].to_string();
+        }
+
+        match entry.as_str() {
+            "" => {
+                return None;
+            },
+            "*" => {
+                return Some(BypassEntry { rule: BypassRule::All, port: None });
+            },
+            "<local>" => {
+                return Some(BypassEntry { rule: BypassRule::Local, port: None });
+            },
+            _ => {}
+        }
+
+        // cidr
+        if entry.contains('/') {
+            return Some(BypassEntry { rule: BypassRule::Network(IpNetwork::parse(&entry)?), port: None });
+        }
+
+        // port, bracketed ipv6 address keeps its colons
+        let (host, port) = match entry.strip_prefix('[') {
+            Some(rest) => {
+                let (host, rest) = rest.split_once(']')?;
+                match rest {
+                    "" => (host.to_string(), None),
+                    rest => (host.to_string(), Some(rest.strip_prefix(':')?.parse::<u16>().ok()?))
+                }
+            },
+            None => match entry.matches(':').count() {
+                1 => {
+                    let (host, port) = entry.split_once(':')?;
+                    (host.to_string(), Some(port.parse::<u16>().ok()?))
+                },
+                _ => (entry.clone(), None)
+            }
+        };
+
+        if host.is_empty() {
+            return None;
+        }
+
+        let rule = if let Some(network) = IpNetwork::parse(&host) {
+            BypassRule::Network(network)
+        } else if host.contains('*') {
+            BypassRule::Wildcard(host)
+        } else {
+            let domain = host.trim_start_matches('.');
+            if domain.is_empty() || domain.contains(|c: char| c.is_whitespace() || c == '/') {
+                return None;
+            }
+            BypassRule::Domain(domain.to_string())
+        };
+
+        Some(BypassEntry { rule, port })
+    }
+
+    ///
+    /// Check whether `host` matches the entry, `host` is a hostname or ip address without port
+    ///
+    pub fn matches(&self, host: &str, port: Option<u16>) -> bool {
+        if self.port.is_some() && self.port != port {
+            return false;
+        }
+
+        let host = host.trim_start_matches('[').trim_end_matches(']').trim_end_matches('.').to_lowercase();
+        let address = host.parse::<IpAddr>().ok();
+
+        match &self.rule {
+            BypassRule::All => true,
+            BypassRule::Local => match address {
+                Some(address) => address.is_loopback(),
+                None => !host.contains('.')
+            },
+            BypassRule::Domain(domain) => host == *domain || host.ends_with(&format!(".{}", domain)),
+            BypassRule::Wildcard(pattern) => wildcard_match(pattern, &host),
+            BypassRule::Network(network) => match address {
+                Some(address) => network.contains(&address),
+                None => false
+            }
+        }
+    }
+}
+
+///
+/// Match `text` with `pattern` in which `*` matches any characters
+///
+fn wildcard_match(pattern: &str, text: &str) -> bool {
+    let pattern = pattern.as_bytes();
+    let text = text.as_bytes();
+
+    let (mut p, mut t) = (0, 0);
+    let mut backtrack: Option<(usize, usize)> = None;
+
+    while t < text.len() {
+        if p < pattern.len() && pattern[p] == b'*' {
+            backtrack = Some((p, t));
+            p += 1;
+        } else if p < pattern.len() && pattern[p] == text[t] {
+            p += 1;
+            t += 1;
+        } else if let Some((star, matched)) = backtrack {
+            p = star + 1;
+            t = matched + 1;
+            backtrack = Some((star, matched + 1));
+        } else {
+            return false;
+        }
+    }
+
+    pattern[p..].iter().all(|c: &u8| *c == b'*')
+}
+
+///
+/// List of hosts which are connected directly instead of through the upstream proxy
+///
+/// # Notes
+/// Hostnames are not resolved, so network entries only match requests to ip addresses
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct BypassList {
+    entries: Vec<BypassEntry>,
+}
+
+impl BypassList {
+    ///
+    /// Parse the bypass list separated by `;`, `,` or whitespace, like windows proxy override
+    /// or `NO_PROXY`, invalid entries are skipped
+    ///
+    pub fn parse(list: &str) -> BypassList {
+        BypassList::from_entries(&list.split(|c: char| c == ';' || c == ',' || c.is_whitespace()).collect::<Vec<&str>>())
+    }
+
+    pub fn from_entries<S: AsRef<str>>(entries: &[S]) -> BypassList {
+        BypassList {
+            entries: entries.iter().filter_map(|entry: &S| BypassEntry::parse(entry.as_ref())).collect()
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    pub fn matches(&self, host: &str, port: Option<u16>) -> bool {
+        self.entries.iter().any(|entry: &BypassEntry| entry.matches(host, port))
+    }
+
+    ///
+    /// Check whether the host of `url` matches the list, the default port of scheme is used if absent
+    ///
+    pub fn matches_url(&self, url: &str) -> bool {
+        if self.entries.is_empty() {
+            return false;
+        }
+
+        match url::Url::parse(url) {
+            Ok(url) => match url.host_str() {
+                Some(host) => self.matches(host, url.port_or_known_default()),
+                None => false
+            },
+            Err(_) => false
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn match_bypass_entries() {
+        let list = BypassList::parse("<local>; *.corp.com;192.168.*, internal.com 10.0.0.0/8;fd00::/8;[::1]:8080;api.example.com:443;http://legacy.com");
+
+        assert!(list.matches("intranet", None));
+        assert!(list.matches("localhost", Some(80)));
+        assert!(list.matches("127.0.0.1", None));
+        assert!(!list.matches("example.com", None));
+
+        assert!(list.matches("git.corp.com", None));
+        assert!(!list.matches("corp.com", None));
+        assert!(list.matches("192.168.1.20", None));
+
+        assert!(list.matches("internal.com", None));
+        assert!(list.matches("cdn.internal.com", None));
+        assert!(!list.matches("notinternal.com", None));
+
+        assert!(list.matches("10.20.30.40", None));
+        assert!(!list.matches("11.0.0.1", None));
+        assert!(list.matches("[fd12::1]", None));
+        assert!(!list.matches("fe80::1", None));
+
+        assert!(list.matches("::1", Some(8080)));
+        assert!(list.matches("api.example.com", Some(443)));
+        assert!(!list.matches("api.example.com", Some(80)));
+        assert!(list.matches("legacy.com", None));
+
+        assert!(list.matches_url("https://api.example.com/v1"));
+        assert!(!list.matches_url("http://api.example.com/v1"));
+
+        assert!(BypassList::parse("*").matches("anything", None));
+        assert!(BypassList::parse("").is_empty());
+
+        assert_eq!(BypassEntry::parse("10.0.0.0/33"), None);
+        assert_eq!(BypassEntry::parse("host:port"), None);
+        assert_eq!(BypassEntry::parse("."), None);
+    }
+
+    #[test]
+    fn match_wildcards() {
+        assert!(wildcard_match("*.example.com", "a.b.example.com"));
+        assert!(!wildcard_match("*.example.com", "example.com"));
+        assert!(wildcard_match("*internal*", "my-internal-host"));
+        assert!(wildcard_match("a*b*c", "aXbYbZc"));
+        assert!(!wildcard_match("a*b*c", "aXbYbZ"));
+    }
+}
@@ -0,0 +1,225 @@
+use std::collections::HashMap;
+use std::sync::Arc;
+
+use chrono::prelude::*;
+
+/// File name of the root CA certificate in lopxy config directory
+pub const CA_CERT_FILE_NAME: &str = "lopxy-ca.pem";
+
+/// File name of the root CA private key in lopxy config directory
+pub const CA_KEY_FILE_NAME: &str = "lopxy-ca-key.pem";
+
+/// Years of validity of the root CA certificate
+const CA_VALIDITY_YEARS: i32 = 10;
+
+/// Days of validity of leaf certificates
+const LEAF_VALIDITY_DAYS: i64 = 365;
+
+/// Max count of cached leaf server configs, the least recently used one is dropped beyond it
+const MAX_SERVER_CONFIGS: usize = 256;
+
+fn ca_error<E: std::fmt::Display>(err: E) -> std::io::Error {
+    std::io::Error::other(format!("{}", err))
+}
+
+fn to_offset_date_time(date: DateTime<Utc>) -> time::OffsetDateTime {
+    rcgen::date_time_ymd(date.year(), date.month() as u8, date.day() as u8)
+}
+
+///
+/// Root CA of https interception, leaf certificates of intercepted hosts are minted on demand
+///
+/// # Notes
+/// The certificate and private key are persisted in lopxy config directory, the user has to trust
+/// the exported certificate before intercepted hosts can be visited without certificate errors
+pub struct CertificateAuthority {
+    certificate: rcgen::Certificate,
+    certificate_pem: String,
+    certificate_der: Vec<u8>,
+    /// Leaf server configs of hosts and the sequence number of their last use
+    server_configs: HashMap<String, (Arc<rustls::ServerConfig>, u64)>,
+    use_sequence: u64,
+}
+
+impl CertificateAuthority {
+    pub fn cert_path(config_dir: &std::path::Path) -> std::path::PathBuf {
+        config_dir.join(CA_CERT_FILE_NAME)
+    }
+
+    pub fn key_path(config_dir: &std::path::Path) -> std::path::PathBuf {
+        config_dir.join(CA_KEY_FILE_NAME)
+    }
+
+    ///
+    /// Load the root CA from config directory, generate and persist a new one if it doesn't exist
+    ///
+    pub fn load_or_generate(config_dir: &std::path::Path) -> std::io::Result<CertificateAuthority> {
+        let cert_path = CertificateAuthority::cert_path(config_dir);
+        let key_path = CertificateAuthority::key_path(config_dir);
+
+        if !cert_path.exists() || !key_path.exists() {
+            return CertificateAuthority::generate(config_dir);
+        }
+
+        let certificate_pem = std::fs::read_to_string(&cert_path)?;
+        let key_pem = std::fs::read_to_string(&key_path)?;
+
+        CertificateAuthority::from_pem(&certificate_pem, &key_pem)
+    }
+
+    ///
+    /// Generate a new root CA and persist it, the old one is overwritten
+    ///
+    pub fn generate(config_dir: &std::path::Path) -> std::io::Result<CertificateAuthority> {
+        let now = Utc::now();
+
+        let mut params = rcgen::CertificateParams::default();
+        params.distinguished_name = rcgen::DistinguishedName::new();
+        params.distinguished_name.push(rcgen::DnType::CommonName, format!("lopxy root CA {}", now.format("%Y%m%d%H%M%S")));
+        params.distinguished_name.push(rcgen::DnType::OrganizationName, "lopxy");
+        params.is_ca = rcgen::IsCa::Ca(rcgen::BasicConstraints::Constrained(0));
+        params.key_usages = vec![
+            rcgen::KeyUsagePurpose::KeyCertSign,
+            rcgen::KeyUsagePurpose::CrlSign,
+            rcgen::KeyUsagePurpose::DigitalSignature,
+        ];
+        params.not_before = to_offset_date_time(now - chrono::Duration::days(1));
+        params.not_after = rcgen::date_time_ymd(now.year() + CA_VALIDITY_YEARS, now.month() as u8, now.day().min(28) as u8);
+
+        let certificate = rcgen::Certificate::from_params(params).map_err(ca_error)?;
+        let certificate_pem = certificate.serialize_pem().map_err(ca_error)?;
+        let key_pem = certificate.serialize_private_key_pem();
+
+        std::fs::create_dir_all(config_dir)?;
+        std::fs::write(CertificateAuthority::cert_path(config_dir), &certificate_pem)?;
+        write_private_file(&CertificateAuthority::key_path(config_dir), &key_pem)?;
+
+        CertificateAuthority::from_pem(&certificate_pem, &key_pem)
+    }
+
+    fn from_pem(certificate_pem: &str, key_pem: &str) -> std::io::Result<CertificateAuthority> {
+        let key_pair = rcgen::KeyPair::from_pem(key_pem).map_err(ca_error)?;
+        let params = rcgen::CertificateParams::from_ca_cert_pem(certificate_pem, key_pair).map_err(ca_error)?;
+        let certificate = rcgen::Certificate::from_params(params).map_err(ca_error)?;
+
+        // the original DER is served in the chain, re-serializing would change the signature
+        let certificate_der = match pem_to_der(certificate_pem) {
+            Some(der) => der,
+            None => {
+                return Err(ca_error("invalid CA certificate pem"));
+            }
+        };
+
+        Ok(CertificateAuthority {
+            certificate,
+            certificate_pem: certificate_pem.to_string(),
+            certificate_der,
+            server_configs: HashMap::new(),
+            use_sequence: 0
+        })
+    }
+
+    pub fn certificate_pem<'a>(&'a self) -> &'a str {
+        &self.certificate_pem
+    }
+
+    ///
+    /// Get the TLS server config of host, the leaf certificate is minted at the first time and cached
+    ///
+    /// # Notes
+    /// At most `MAX_SERVER_CONFIGS` configs are cached, the least recently used one is dropped
+    pub fn server_config(&mut self, host: &str) -> std::io::Result<Arc<rustls::ServerConfig>> {
+        self.use_sequence += 1;
+        if let Some((server_config, last_used)) = self.server_configs.get_mut(host) {
+            *last_used = self.use_sequence;
+            return Ok(server_config.clone());
+        }
+
+        let now = Utc::now();
+
+        let mut params = rcgen::CertificateParams::default();
+        params.distinguished_name = rcgen::DistinguishedName::new();
+        params.distinguished_name.push(rcgen::DnType::CommonName, host);
+        params.subject_alt_names = vec![match host.trim_start_matches('[').trim_end_matches(']').parse::<std::net::IpAddr>() {
+            Ok(ip) => rcgen::SanType::IpAddress(ip),
+            Err(_) => rcgen::SanType::DnsName(host.to_string())
+        }];
+        params.key_usages = vec![rcgen::KeyUsagePurpose::DigitalSignature, rcgen::KeyUsagePurpose::KeyEncipherment];
+        params.extended_key_usages = vec![rcgen::ExtendedKeyUsagePurpose::ServerAuth];
+        params.serial_number = Some((now.timestamp() as u64) << 32 | now.timestamp_subsec_nanos() as u64);
+        params.use_authority_key_identifier_extension = true;
+        params.not_before = to_offset_date_time(now - chrono::Duration::days(1));
+        params.not_after = to_offset_date_time(now + chrono::Duration::days(LEAF_VALIDITY_DAYS));
+
+        let leaf = rcgen::Certificate::from_params(params).map_err(ca_error)?;
+        let leaf_der = leaf.serialize_der_with_signer(&self.certificate).map_err(ca_error)?;
+
+        let mut server_config = rustls::ServerConfig::builder().
+            with_safe_defaults().
+            with_no_client_auth().
+            with_single_cert(
+                vec![rustls::Certificate(leaf_der), rustls::Certificate(self.certificate_der.clone())],
+                rustls::PrivateKey(leaf.serialize_private_key_der())
+            ).
+            map_err(ca_error)?;
+
+        // lopxy speaks HTTP/1.x only
+        server_config.alpn_protocols = vec![b"http/1.1".to_vec()];
+
+        let server_config = Arc::new(server_config);
+        if self.server_configs.len() >= MAX_SERVER_CONFIGS {
+            let least_recent = self.server_configs.iter().
+                min_by_key(|(_, (_, last_used)): &(&String, &(Arc<rustls::ServerConfig>, u64))| *last_used).
+                map(|(host, _): (&String, &(Arc<rustls::ServerConfig>, u64))| host.clone());
+            if let Some(least_recent) = least_recent {
+                self.server_configs.remove(&least_recent);
+            }
+        }
+        self.server_configs.insert(host.to_string(), (server_config.clone(), self.use_sequence));
+
+        Ok(server_config)
+    }
+}
+
+fn pem_to_der(pem: &str) -> Option<Vec<u8>> {
+    pem::parse(pem).ok().map(|pem: pem::Pem| pem.contents)
+}
+
+#[cfg(unix)]
+fn write_private_file(path: &std::path::Path, content: &str) -> std::io::Result<()> {
+    use std::io::Write;
+    use std::os::unix::fs::OpenOptionsExt;
+
+    let mut file = std::fs::OpenOptions::new().write(true).create(true).truncate(true).mode(0o600).open(path)?;
+    file.write_all(content.as_bytes())
+}
+
+#[cfg(not(unix))]
+fn write_private_file(path: &std::path::Path, content: &str) -> std::io::Result<()> {
+    std::fs::write(path, content)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn generate_and_reload_ca() {
+        let config_dir = std::env::temp_dir().join(format!("lopxy-ca-test-{}", std::process::id()));
+
+        let mut ca = CertificateAuthority::generate(&config_dir).unwrap();
+        assert!(ca.certificate_pem().starts_with("-----BEGIN CERTIFICATE-----"));
+
+        let config = ca.server_config("www.test.com").unwrap();
+        assert!(Arc::ptr_eq(&config, &ca.server_config("www.test.com").unwrap()));
+        ca.server_config("127.0.0.1").unwrap();
+
+        let reloaded = CertificateAuthority::load_or_generate(&config_dir).unwrap();
+        assert_eq!(reloaded.certificate_pem(), ca.certificate_pem());
+
+        let regenerated = CertificateAuthority::generate(&config_dir).unwrap();
+        assert_ne!(regenerated.certificate_pem(), ca.certificate_pem());
+
+        std::fs::remove_dir_all(&config_dir).unwrap();
+    }
+}
@@ -0,0 +1,650 @@
+use std::collections::HashMap;
+use std::hash::{Hash, Hasher};
+use std::sync::{Arc, Mutex};
+
+use chrono::prelude::*;
+use serde_derive::{Serialize, Deserialize};
+
+use super::bypass::BypassList;
+use super::item::glob_to_regex;
+
+/// Default max bytes of cached responses on disk
+pub const DEFAULT_CACHE_MAX_SIZE: u64 = 256 * 1024 * 1024;
+
+/// Default seconds to wait for the response head before the cached copy is answered
+pub const DEFAULT_CACHE_TIMEOUT: u64 = 10;
+
+/// File of cache index under cache dir
+const CACHE_INDEX_FILE: &str = "index.json";
+
+fn default_cache_max_size() -> u64 {
+    DEFAULT_CACHE_MAX_SIZE
+}
+
+fn default_cache_timeout() -> u64 {
+    DEFAULT_CACHE_TIMEOUT
+}
+
+///
+/// Offline cache of lopxy config, the scope is the hosts written like bypass entries and the url globs
+///
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct CacheConfig {
+    #[serde(default)]
+    pub hosts: Vec<String>,
+    /// Glob patterns like `http://cdn.example.com/libs/**`
+    #[serde(default)]
+    pub urls: Vec<String>,
+    /// Max bytes of cached responses on disk, the least recently used ones are evicted
+    #[serde(default = "default_cache_max_size")]
+    pub max_size: u64,
+    /// Seconds a cached response stays fresh, it overrides `Cache-Control` of the response if set
+    #[serde(default)]
+    pub ttl: Option<u64>,
+    /// Seconds to wait for the response head before the cached copy is answered
+    #[serde(default = "default_cache_timeout")]
+    pub timeout: u64,
+}
+
+impl Default for CacheConfig {
+    fn default() -> CacheConfig {
+        CacheConfig {
+            hosts: vec![],
+            urls: vec![],
+            max_size: DEFAULT_CACHE_MAX_SIZE,
+            ttl: None,
+            timeout: DEFAULT_CACHE_TIMEOUT
+        }
+    }
+}
+
+impl CacheConfig {
+    pub fn is_empty(&self) -> bool {
+        self.hosts.is_empty() && self.urls.is_empty()
+    }
+
+    pub fn verify(&self) -> bool {
+        self.urls.iter().all(|url: &String| regex::Regex::new(&glob_to_regex(url)).is_ok())
+    }
+
+    pub fn matches(&self, host: &str, port: Option<u16>) -> bool {
+        BypassList::from_entries(&self.hosts).matches(host, port)
+    }
+
+    pub fn matches_url(&self, url: &str) -> bool {
+        BypassList::from_entries(&self.hosts).matches_url(url) ||
+            self.urls.iter().any(|pattern: &String| {
+                regex::Regex::new(&glob_to_regex(pattern)).is_ok_and(|matcher: regex::Regex| matcher.is_match(url))
+            })
+    }
+}
+
+///
+/// Get the seconds the response is fresh by its `Cache-Control` or `Expires`, None if it must not be stored
+///
+/// # Notes
+/// A response without freshness is stored as a stale copy, which is only answered when the upstream fails
+pub fn response_freshness(headers: &reqwest::header::HeaderMap) -> Option<u64> {
+    let header_value = |name: reqwest::header::HeaderName| -> String {
+        joined_header_value(headers, name)
+    };
+
+    if header_value(reqwest::header::VARY).split(',').any(|field: &str| field.trim() == "*") {
+        return None;
+    }
+
+    let mut max_age: Option<u64> = None;
+    let mut shared_max_age: Option<u64> = None;
+    let mut no_cache = false;
+
+    for (name, value) in cache_control_directives(headers) {
+        let value = value.as_deref();
+        match name.as_str() {
+            "no-store" | "private" => {
+                return None;
+            },
+            "no-cache" => no_cache = true,
+            "max-age" => max_age = value.and_then(|value: &str| value.parse().ok()),
+            "s-maxage" => shared_max_age = value.and_then(|value: &str| value.parse().ok()),
+            _ => {}
+        }
+    }
+
+    if no_cache {
+        return Some(0);
+    }
+
+    if let Some(age) = shared_max_age.or(max_age) {
+        return Some(age);
+    }
+
+    match super::local::parse_http_date(&header_value(reqwest::header::EXPIRES)) {
+        Some(expires) => {
+            let date = super::local::parse_http_date(&header_value(reqwest::header::DATE)).unwrap_or(Utc::now());
+            Some(std::cmp::max((expires - date).num_seconds(), 0) as u64)
+        },
+        None => Some(0)
+    }
+}
+
+///
+/// Check whether the response may be shared with other clients of lopxy
+///
+/// # Notes
+/// A response which sets cookies is never shared, the response to a request with `Authorization` or
+/// `Cookie` is shared only if it's marked `public` or has `s-maxage`
+pub fn is_shareable(request_headers: &reqwest::header::HeaderMap, response_headers: &reqwest::header::HeaderMap) -> bool {
+    if response_headers.contains_key(reqwest::header::SET_COOKIE) {
+        return false;
+    }
+
+    if !request_headers.contains_key(reqwest::header::AUTHORIZATION) && !request_headers.contains_key(reqwest::header::COOKIE) {
+        return true;
+    }
+
+    cache_control_directives(response_headers).iter().
+        any(|(name, _): &(String, Option<String>)| name == "public" || name == "s-maxage")
+}
+
+///
+/// Collect the values of request headers named by `Vary` of response, the cached response is only
+/// answered to the requests with the same values
+///
+pub fn vary_selection(request_headers: &reqwest::header::HeaderMap, response_headers: &reqwest::header::HeaderMap) -> Vec<(String, String)> {
+    joined_header_value(response_headers, reqwest::header::VARY).split(',').
+        map(|name: &str| name.trim().to_lowercase()).
+        filter(|name: &String| !name.is_empty()).
+        map(|name: String| {
+            let value = joined_header_value(request_headers, name.as_str());
+            (name, value)
+        }).
+        collect()
+}
+
+///
+/// Join the values of headers named `name` with `,`
+///
+fn joined_header_value<K: reqwest::header::AsHeaderName>(headers: &reqwest::header::HeaderMap, name: K) -> String {
+    headers.get_all(name).iter().
+        filter_map(|value: &reqwest::header::HeaderValue| value.to_str().ok()).
+        collect::<Vec<&str>>().
+        join(",")
+}
+
+///
+/// Collect the `Cache-Control` directives with lowercase names
+///
+fn cache_control_directives(headers: &reqwest::header::HeaderMap) -> Vec<(String, Option<String>)> {
+    joined_header_value(headers, reqwest::header::CACHE_CONTROL).split(',').
+        filter(|directive: &&str| !directive.trim().is_empty()).
+        map(|directive: &str| match directive.split_once('=') {
+            Some((name, value)) => (name.trim().to_lowercase(), Some(value.trim().trim_matches('"').to_string())),
+            None => (directive.trim().to_lowercase(), None)
+        }).
+        collect()
+}
+
+///
+/// Build the raw response stored in cache, the body is framed by `content-length`
+///
+pub fn build_cached_response(status: reqwest::StatusCode, headers: &reqwest::header::HeaderMap, body: &[u8]) -> Vec<u8> {
+    let mut raw_response_bytes = format!("HTTP/1.1 {}\r\n", status).into_bytes();
+
+    for (name, value) in headers {
+        if name == reqwest::header::TRANSFER_ENCODING ||
+            name == reqwest::header::CONNECTION ||
+            name == reqwest::header::CONTENT_LENGTH ||
+            name.as_str().eq_ignore_ascii_case("keep-alive") {
+            continue;
+        }
+
+        raw_response_bytes.extend_from_slice(name.as_str().as_bytes());
+        raw_response_bytes.extend_from_slice(b": ");
+        raw_response_bytes.extend_from_slice(value.as_bytes());
+        raw_response_bytes.extend_from_slice(b"\r\n");
+    }
+
+    raw_response_bytes.extend_from_slice(format!("content-length: {}\r\n\r\n", body.len()).as_bytes());
+    raw_response_bytes.extend_from_slice(body);
+    raw_response_bytes
+}
+
+///
+/// A cached response, `file` is the name of the raw response file under cache dir
+///
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct CacheEntry {
+    pub url: String,
+    pub file: String,
+    pub size: u64,
+    pub stored_timestamp: i64,
+    pub expires_timestamp: i64,
+    pub accessed_timestamp: i64,
+    /// Request headers named by `Vary` of the response and their values
+    #[serde(default)]
+    pub vary: Vec<(String, String)>,
+}
+
+impl CacheEntry {
+    pub fn is_fresh(&self) -> bool {
+        Local::now().timestamp_millis() < self.expires_timestamp
+    }
+
+    ///
+    /// Check whether the request has the same values of the headers named by `Vary`
+    ///
+    pub fn matches_vary(&self, request_headers: &reqwest::header::HeaderMap) -> bool {
+        self.vary.iter().all(|(name, value): &(String, String)| joined_header_value(request_headers, name.as_str()) == *value)
+    }
+}
+
+///
+/// How a request takes part in fetching a url of cache
+///
+pub enum CacheFetch {
+    /// The request fetches the url, the waiting requests are woken when the guard is dropped
+    Leader(CacheFetchGuard),
+    /// Another request is fetching the url, the receiver completes when it's done
+    Follower(futures::channel::oneshot::Receiver<()>),
+}
+
+pub struct CacheFetchGuard {
+    cache: Arc<ResponseCache>,
+    url: String,
+}
+
+impl Drop for CacheFetchGuard {
+    fn drop(&mut self) {
+        // dropping the senders wakes the followers
+        self.cache.fetching.lock().unwrap().remove(&self.url);
+    }
+}
+
+///
+/// Offline cache of successful GET responses, the raw responses are kept in files under cache dir
+///
+/// # Notes
+/// The index is kept in memory and written to `index.json` when it's changed, so the access time
+/// of entries read since the last change is lost on restart
+pub struct ResponseCache {
+    dir: std::path::PathBuf,
+    /// The size cap, ttl and timeout are taken from it
+    config: Mutex<CacheConfig>,
+    entries: Mutex<HashMap<String, CacheEntry>>,
+    /// Version of the entries, bumped with every change while they are locked
+    version: std::sync::atomic::AtomicU64,
+    /// Version of the index written last, an older index never overwrites a newer one
+    saved_version: Arc<Mutex<u64>>,
+    fetching: Mutex<HashMap<String, Vec<futures::channel::oneshot::Sender<()>>>>,
+}
+
+impl ResponseCache {
+    ///
+    /// Open the cache in `dir`, the entries whose files are lost are dropped
+    ///
+    pub fn open(dir: std::path::PathBuf, config: &CacheConfig) -> ResponseCache {
+        let mut index_path = dir.clone();
+        index_path.push(CACHE_INDEX_FILE);
+
+        let entries: Vec<CacheEntry> = std::fs::read_to_string(&index_path).ok().
+            and_then(|index: String| serde_json::from_str(&index).ok()).
+            unwrap_or_default();
+
+        let entries = entries.into_iter().
+            filter(|entry: &CacheEntry| dir.join(&entry.file).is_file()).
+            map(|entry: CacheEntry| (entry.url.clone(), entry)).
+            collect();
+
+        ResponseCache {
+            dir,
+            config: Mutex::new(config.clone()),
+            entries: Mutex::new(entries),
+            version: std::sync::atomic::AtomicU64::new(0),
+            saved_version: Arc::new(Mutex::new(0)),
+            fetching: Mutex::new(HashMap::new())
+        }
+    }
+
+    ///
+    /// Update the size cap, ttl and timeout when lopxy config is changed
+    ///
+    /// # Notes
+    /// A smaller size cap evicts the least recently used entries at the next store
+    pub fn update_config(&self, config: &CacheConfig) {
+        *self.config.lock().unwrap() = config.clone();
+    }
+
+    pub fn timeout(&self) -> std::time::Duration {
+        std::time::Duration::from_secs(self.config.lock().unwrap().timeout)
+    }
+
+    ///
+    /// Max bytes of a cached response, a larger one is not stored
+    ///
+    pub fn max_entry_size(&self) -> u64 {
+        self.config.lock().unwrap().max_size / 4
+    }
+
+    ///
+    /// Get the seconds the response stays fresh, None if it must not be stored
+    ///
+    /// # Notes
+    /// The configured ttl only overrides the freshness of a storable response
+    pub fn freshness(&self, request_headers: &reqwest::header::HeaderMap, response_headers: &reqwest::header::HeaderMap) -> Option<u64> {
+        if !is_shareable(request_headers, response_headers) {
+            return None;
+        }
+
+        let freshness = response_freshness(response_headers)?;
+        Some(self.config.lock().unwrap().ttl.unwrap_or(freshness))
+    }
+
+    pub fn contains(&self, url: &str) -> bool {
+        self.entries.lock().unwrap().contains_key(url)
+    }
+
+    ///
+    /// Get all cached entries sorted by url
+    ///
+    pub fn entries(&self) -> Vec<CacheEntry> {
+        let mut entries: Vec<CacheEntry> = self.entries.lock().unwrap().values().cloned().collect();
+        entries.sort_by(|a: &CacheEntry, b: &CacheEntry| a.url.cmp(&b.url));
+        entries
+    }
+
+    ///
+    /// Read the cached raw response of url for the request, `fresh_only` skips the stale one
+    ///
+    pub async fn load(&self, url: &str, fresh_only: bool, request_headers: &reqwest::header::HeaderMap) -> Option<Vec<u8>> {
+        let entry = {
+            let mut entries = self.entries.lock().unwrap();
+            let entry = entries.get_mut(url).
+                filter(|entry: &&mut CacheEntry| (!fresh_only || entry.is_fresh()) && entry.matches_vary(request_headers))?;
+            entry.accessed_timestamp = Local::now().timestamp_millis();
+            entry.clone()
+        };
+
+        match async_std::fs::read(self.dir.join(&entry.file)).await {
+            Ok(raw_response_bytes) => Some(raw_response_bytes),
+            Err(err) => {
+                eprintln!("read cached response of {} failed : {}", url, err);
+                if let Some(index_write) = self.remove_entries(|cached: &CacheEntry| cached.url == url) {
+                    async_std::task::spawn_blocking(move || index_write.apply()).await;
+                }
+                None
+            }
+        }
+    }
+
+    ///
+    /// Store the raw response of url, the least recently used entries are evicted to keep the size cap
+    ///
+    /// # Notes
+    /// `vary` is the selection of request headers by [`vary_selection`], only one variant of url is kept
+    pub async fn store(&self, url: &str, raw_response_bytes: &[u8], freshness: u64, vary: Vec<(String, String)>) {
+        let size = raw_response_bytes.len() as u64;
+        if size > self.max_entry_size() {
+            return;
+        }
+
+        let file = cache_file_name(url);
+        if let Err(err) = async_std::fs::create_dir_all(&self.dir).await {
+            eprintln!("create cache dir failed : {}", err);
+            return;
+        }
+
+        if let Err(err) = async_std::fs::write(self.dir.join(&file), raw_response_bytes).await {
+            eprintln!("store cached response of {} failed : {}", url, err);
+            return;
+        }
+
+        let max_size = self.config.lock().unwrap().max_size;
+        let now = Local::now().timestamp_millis();
+        let index_write = {
+            let mut entries = self.entries.lock().unwrap();
+            entries.insert(url.to_string(), CacheEntry {
+                url: url.to_string(),
+                file,
+                size,
+                stored_timestamp: now,
+                expires_timestamp: now + (freshness * 1000) as i64,
+                accessed_timestamp: now,
+                vary
+            });
+
+            let mut removed_files = vec![];
+            let mut total_size: u64 = entries.values().map(|entry: &CacheEntry| entry.size).sum();
+            while total_size > max_size {
+                let least_recent = entries.values().
+                    filter(|entry: &&CacheEntry| entry.url != url).
+                    min_by_key(|entry: &&CacheEntry| entry.accessed_timestamp).
+                    map(|entry: &CacheEntry| entry.url.clone());
+
+                let evicted = match least_recent.and_then(|least_recent: String| entries.remove(&least_recent)) {
+                    Some(evicted) => evicted,
+                    None => break
+                };
+
+                total_size -= evicted.size;
+                removed_files.push(evicted.file);
+            }
+
+            self.index_write(&entries, removed_files)
+        };
+
+        async_std::task::spawn_blocking(move || index_write.apply()).await;
+    }
+
+    ///
+    /// Purge the entries whose url matches the glob pattern, all entries if it's None
+    ///
+    /// # Notes
+    /// Return the count of purged entries, None if the pattern is invalid
+    pub fn purge(&self, pattern: Option<&str>) -> Option<usize> {
+        let matcher = match pattern.filter(|pattern: &&str| !pattern.is_empty()) {
+            Some(pattern) => match regex::Regex::new(&glob_to_regex(pattern)) {
+                Ok(matcher) => Some(matcher),
+                Err(_) => {
+                    return None;
+                }
+            },
+            None => None
+        };
+
+        let index_write = self.remove_entries(|entry: &CacheEntry| matcher.as_ref().is_none_or(|matcher: &regex::Regex| matcher.is_match(&entry.url)));
+
+        Some(match index_write {
+            Some(index_write) => {
+                let purged = index_write.removed_files.len();
+                index_write.apply();
+                purged
+            },
+            None => 0
+        })
+    }
+
+    ///
+    /// Take part in fetching url, the concurrent requests of the same url wait for the first one
+    ///
+    pub fn begin_fetch(self: &Arc<Self>, url: &str) -> CacheFetch {
+        let mut fetching = self.fetching.lock().unwrap();
+
+        match fetching.get_mut(url) {
+            Some(followers) => {
+                let (sender, receiver) = futures::channel::oneshot::channel();
+                followers.push(sender);
+                CacheFetch::Follower(receiver)
+            },
+            None => {
+                fetching.insert(url.to_string(), vec![]);
+                CacheFetch::Leader(CacheFetchGuard {
+                    cache: self.clone(),
+                    url: url.to_string()
+                })
+            }
+        }
+    }
+
+    ///
+    /// Remove the entries matching predicate, the index write is returned if any entry is removed
+    ///
+    fn remove_entries<F: Fn(&CacheEntry) -> bool>(&self, predicate: F) -> Option<IndexWrite> {
+        let mut entries = self.entries.lock().unwrap();
+        let removed: Vec<String> = entries.values().
+            filter(|entry: &&CacheEntry| predicate(entry)).
+            map(|entry: &CacheEntry| entry.url.clone()).
+            collect();
+
+        if removed.is_empty() {
+            return None;
+        }
+
+        let removed_files = removed.iter().
+            filter_map(|url: &String| entries.remove(url)).
+            map(|entry: CacheEntry| entry.file).
+            collect();
+
+        Some(self.index_write(&entries, removed_files))
+    }
+
+    ///
+    /// Snapshot the index while the entries are locked, the files are written after unlocking
+    ///
+    fn index_write(&self, entries: &HashMap<String, CacheEntry>, removed_files: Vec<String>) -> IndexWrite {
+        IndexWrite {
+            dir: self.dir.clone(),
+            version: self.version.fetch_add(1, std::sync::atomic::Ordering::SeqCst) + 1,
+            saved_version: self.saved_version.clone(),
+            index: serde_json::to_string(&entries.values().collect::<Vec<&CacheEntry>>()).unwrap_or("[]".to_string()),
+            removed_files
+        }
+    }
+}
+
+///
+/// Pending changes of cache files, the index and the files of removed entries
+///
+struct IndexWrite {
+    dir: std::path::PathBuf,
+    version: u64,
+    saved_version: Arc<Mutex<u64>>,
+    index: String,
+    removed_files: Vec<String>,
+}
+
+impl IndexWrite {
+    ///
+    /// Remove the files and write the index, it blocks so async callers run it by `spawn_blocking`
+    ///
+    fn apply(self) {
+        for file in self.removed_files.iter() {
+            let _ = std::fs::remove_file(self.dir.join(file));
+        }
+
+        let mut saved_version = self.saved_version.lock().unwrap();
+        if *saved_version > self.version {
+            return;
+        }
+
+        if let Err(err) = std::fs::create_dir_all(&self.dir).and(std::fs::write(self.dir.join(CACHE_INDEX_FILE), &self.index)) {
+            eprintln!("save cache index failed : {}", err);
+        }
+        *saved_version = self.version;
+    }
+}
+
+///
+/// Name the raw response file by the hash of url, the name is kept in index so the hash needn't be stable
+///
+fn cache_file_name(url: &str) -> String {
+    let mut hasher = std::collections::hash_map::DefaultHasher::new();
+    url.hash(&mut hasher);
+    format!("{:016x}.http", hasher.finish())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn headers(pairs: &[(&'static str, &'static str)]) -> reqwest::header::HeaderMap {
+        let mut headers = reqwest::header::HeaderMap::new();
+        for (name, value) in pairs {
+            headers.append(*name, reqwest::header::HeaderValue::from_static(value));
+        }
+        headers
+    }
+
+    #[test]
+    fn store_and_evict_responses() {
+        assert_eq!(response_freshness(&headers(&[("cache-control", "public, max-age=60, s-maxage=120")])), Some(120));
+        assert_eq!(response_freshness(&headers(&[("cache-control", "no-cache")])), Some(0));
+        assert_eq!(response_freshness(&headers(&[("cache-control", "private, max-age=60")])), None);
+        assert_eq!(response_freshness(&headers(&[("vary", "*")])), None);
+        assert!(!is_shareable(&headers(&[]), &headers(&[("set-cookie", "a=1"), ("cache-control", "public")])));
+        assert!(!is_shareable(&headers(&[("cookie", "a=1")]), &headers(&[("cache-control", "max-age=60")])));
+        assert!(is_shareable(&headers(&[("authorization", "Bearer a")]), &headers(&[("cache-control", "public, max-age=60")])));
+        assert!(is_shareable(&headers(&[]), &headers(&[("cache-control", "max-age=60")])));
+        assert_eq!(response_freshness(&headers(&[
+            ("date", "Sun, 06 Nov 1994 08:49:37 GMT"),
+            ("expires", "Sun, 06 Nov 1994 08:59:37 GMT")
+        ])), Some(600));
+
+        let config = CacheConfig {
+            hosts: vec!["*.resource.com".to_string()],
+            urls: vec!["http://cdn.other.com/libs/**".to_string()],
+            max_size: 4 * 100,
+            ..CacheConfig::default()
+        };
+        assert!(config.matches_url("https://cdn.resource.com/a.js"));
+        assert!(config.matches_url("http://cdn.other.com/libs/a/b.js"));
+        assert!(!config.matches_url("http://cdn.other.com/a.js"));
+
+        let dir = std::env::temp_dir().join(format!("lopxy-cache-test-{}", std::process::id()));
+        let cache = Arc::new(ResponseCache::open(dir.clone(), &config));
+
+        async_std::task::block_on(async {
+            let raw_response_bytes = build_cached_response(reqwest::StatusCode::OK, &headers(&[("transfer-encoding", "chunked")]), &[b'a'; 60]);
+            assert!(String::from_utf8_lossy(&raw_response_bytes).starts_with("HTTP/1.1 200 OK\r\ncontent-length: 60\r\n\r\n"));
+
+            // 4 entries fit in the cap
+            for (index, freshness) in [60, 0, 60, 60].iter().enumerate() {
+                cache.store(&format!("http://a.com/{}", index + 1), &raw_response_bytes, *freshness, vec![]).await;
+                async_std::task::sleep(std::time::Duration::from_millis(5)).await;
+            }
+            assert_eq!(cache.load("http://a.com/1", true, &headers(&[])).await, Some(raw_response_bytes.clone()));
+            assert_eq!(cache.load("http://a.com/2", true, &headers(&[])).await, None);
+
+            // the variant is only answered to the requests with the same header values
+            let vary = vary_selection(&headers(&[("accept-language", "en")]), &headers(&[("vary", "Accept-Language")]));
+            cache.store("http://a.com/4", &raw_response_bytes, 60, vary).await;
+            assert_eq!(cache.load("http://a.com/4", true, &headers(&[("accept-language", "fr")])).await, None);
+            assert!(cache.load("http://a.com/4", false, &headers(&[("accept-language", "en")])).await.is_some());
+
+            // the least recently used one is evicted
+            cache.store("http://a.com/5", &raw_response_bytes, 60, vec![]).await;
+            assert!(!cache.contains("http://a.com/2"));
+            assert!(cache.contains("http://a.com/1"));
+
+            // the entries are kept in index
+            let reopened = ResponseCache::open(dir.clone(), &config);
+            assert_eq!(reopened.entries().len(), 4);
+            assert_eq!(reopened.purge(Some("http://a.com/1")), Some(1));
+            assert_eq!(reopened.purge(None), Some(3));
+        });
+
+        match cache.begin_fetch("http://a.com/1") {
+            CacheFetch::Leader(guard) => {
+                let mut follower = match cache.begin_fetch("http://a.com/1") {
+                    CacheFetch::Follower(receiver) => receiver,
+                    CacheFetch::Leader(_) => panic!("concurrent fetches are not coalesced")
+                };
+                assert!(follower.try_recv().unwrap().is_none());
+                drop(guard);
+                assert!(follower.try_recv().is_err());
+            },
+            CacheFetch::Follower(_) => panic!("the first fetch must lead")
+        }
+
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+}
@@ -0,0 +1,105 @@
+///
+/// Canonicalize url before matching proxy items
+///
+/// # Notes
+/// - scheme and host are lowercased, IDN host is translated to punycode
+/// - default port is removed, empty path becomes `/`
+/// - non-ASCII characters are percent-encoded, percent-encoded unreserved characters are decoded
+///   and the hex digits of the other percent-encoded bytes are uppercased
+/// - fragment is removed, because it is never sent to the server
+/// - query parameters are sorted by name when `sort_query` is true
+pub fn canonicalize_url(url: &str, sort_query: bool) -> Option<String> {
+    let mut url = url::Url::parse(url.trim()).ok()?;
+
+    url.set_fragment(None);
+
+    let path = normalize_percent_encoding(url.path());
+    url.set_path(&path);
+
+    if let Some(query) = url.query() {
+        let mut query = normalize_percent_encoding(query);
+        if sort_query {
+            query = sort_query_params(&query);
+        }
+        url.set_query(if query.is_empty() { None } else { Some(&query) });
+    }
+
+    Some(url.to_string())
+}
+
+///
+/// Canonicalize url, return the original url if it can't be parsed
+///
+pub fn canonicalize_url_or_keep(url: &str, sort_query: bool) -> String {
+    canonicalize_url(url, sort_query).unwrap_or(url.to_string())
+}
+
+fn is_unreserved(c: u8) -> bool {
+    c.is_ascii_alphanumeric() || c == b'-' || c == b'.' || c == b'_' || c == b'~'
+}
+
+fn hex_value(c: u8) -> Option<u8> {
+    match c {
+        b'0'..=b'9' => Some(c - b'0'),
+        b'a'..=b'f' => Some(c - b'a' + 10),
+        b'A'..=b'F' => Some(c - b'A' + 10),
+        _ => None
+    }
+}
+
+///
+/// Decode percent-encoded unreserved characters and uppercase the hex digits of the others
+///
+fn normalize_percent_encoding(s: &str) -> String {
+    let bytes = s.as_bytes();
+    let mut result = String::with_capacity(s.len());
+    let mut i = 0;
+
+    while i < bytes.len() {
+        if bytes[i] == b'%' && i + 2 < bytes.len() {
+            if let (Some(high), Some(low)) = (hex_value(bytes[i + 1]), hex_value(bytes[i + 2])) {
+                let c = high * 16 + low;
+                if is_unreserved(c) {
+                    result.push(c as char);
+                } else {
+                    result.push_str(&format!("%{:02X}", c));
+                }
+                i += 3;
+                continue;
+            }
+        }
+
+        let c = s[i..].chars().next().unwrap();
+        result.push(c);
+        i += c.len_utf8();
+    }
+
+    result
+}
+
+///
+/// Sort query parameters by name, parameters with the same name keep their order
+///
+fn sort_query_params(query: &str) -> String {
+    let mut params: Vec<&str> = query.split('&').filter(|param: &&str| !param.is_empty()).collect();
+    params.sort_by_key(|param: &&str| param.split('=').next().unwrap_or(""));
+    params.join("&")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn canonicalize_urls() {
+        assert_eq!(canonicalize_url("HTTP://WWW.Test.com", false).as_deref(), Some("http://www.test.com/"));
+        assert_eq!(canonicalize_url("http://www.test.com:80/a", false).as_deref(), Some("http://www.test.com/a"));
+        assert_eq!(canonicalize_url("https://www.test.com:8443/a#top", false).as_deref(), Some("https://www.test.com:8443/a"));
+        assert_eq!(canonicalize_url("http://www.test.com/%7euser/%e6%96%87", false).as_deref(), Some("http://www.test.com/~user/%E6%96%87"));
+        assert_eq!(canonicalize_url("http://www.test.com/文件.txt", false).as_deref(), Some("http://www.test.com/%E6%96%87%E4%BB%B6.txt"));
+        assert_eq!(canonicalize_url("http://例子.测试/a", false).as_deref(), Some("http://xn--fsqu00a.xn--0zwm56d/a"));
+        assert_eq!(canonicalize_url("http://a.com/?b=2&a=1&b=1", true).as_deref(), Some("http://a.com/?a=1&b=2&b=1"));
+        assert_eq!(canonicalize_url("http://a.com/?b=2&a=1", false).as_deref(), Some("http://a.com/?b=2&a=1"));
+        assert_eq!(canonicalize_url("not a url", false), None);
+    }
+}
@@ -0,0 +1,286 @@
+use std::future::Future;
+use std::pin::Pin;
+use std::task::{Context, Poll};
+use std::time::{Duration, Instant};
+
+use async_std::io::Write;
+use rand::Rng;
+use serde_derive::{Serialize, Deserialize};
+
+/// Default status code answered by status block
+pub const DEFAULT_BLOCK_STATUS: u16 = 503;
+
+/// Default percentage of requests the block applies to
+pub const DEFAULT_FAILURE_RATE: u8 = 100;
+
+/// Count of slices a second of throttled bandwidth is written in
+const THROTTLE_SLICES_PER_SECOND: u64 = 10;
+
+fn default_block_status() -> u16 {
+    DEFAULT_BLOCK_STATUS
+}
+
+fn default_failure_rate() -> u8 {
+    DEFAULT_FAILURE_RATE
+}
+
+///
+/// How a blocked request fails
+///
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum BlockKind {
+    /// The connection is closed at once without reading the rest of the request
+    Reset,
+    /// The request is read, then the connection is closed without any response
+    Empty,
+    /// An error page with `block_status` is answered
+    Status,
+}
+
+impl std::str::FromStr for BlockKind {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<BlockKind, String> {
+        match s.to_lowercase().as_str() {
+            "reset" => Ok(BlockKind::Reset),
+            "empty" => Ok(BlockKind::Empty),
+            "status" => Ok(BlockKind::Status),
+            _ => Err(format!("unknown block kind : {}", s))
+        }
+    }
+}
+
+impl std::fmt::Display for BlockKind {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", match self {
+            BlockKind::Reset => "reset",
+            BlockKind::Empty => "empty",
+            BlockKind::Status => "status",
+        })
+    }
+}
+
+///
+/// Network condition simulated for the requests of a proxy item
+///
+/// # Notes
+/// The latency is added before anything is answered, including the block. The bandwidth limits
+/// the response stream, 0 means unlimited
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct NetworkCondition {
+    #[serde(default)]
+    pub block: Option<BlockKind>,
+    #[serde(default = "default_block_status")]
+    pub block_status: u16,
+    /// Percentage of requests the block applies to, the others are answered normally
+    #[serde(default = "default_failure_rate")]
+    pub failure_rate: u8,
+    /// Milliseconds added before the response
+    #[serde(default)]
+    pub latency: u64,
+    /// Bytes per second of the response stream
+    #[serde(default)]
+    pub bandwidth: u64,
+}
+
+impl Default for NetworkCondition {
+    fn default() -> NetworkCondition {
+        NetworkCondition {
+            block: None,
+            block_status: DEFAULT_BLOCK_STATUS,
+            failure_rate: DEFAULT_FAILURE_RATE,
+            latency: 0,
+            bandwidth: 0
+        }
+    }
+}
+
+impl NetworkCondition {
+    ///
+    /// Check whether the condition changes nothing
+    ///
+    pub fn is_empty(&self) -> bool {
+        self.block.is_none() && self.latency == 0 && self.bandwidth == 0
+    }
+
+    pub fn verify(&self) -> bool {
+        self.failure_rate <= 100 && (100..1000).contains(&self.block_status)
+    }
+
+    ///
+    /// Check whether every request is blocked, so that the target is never requested
+    ///
+    pub fn always_blocks(&self) -> bool {
+        self.block.is_some() && self.failure_rate >= 100
+    }
+
+    ///
+    /// Decide whether to block this request, the block applies to `failure_rate` percent of requests
+    ///
+    pub fn roll_block(&self) -> Option<BlockKind> {
+        let block = self.block?;
+
+        match self.failure_rate >= 100 || rand::thread_rng().gen_range(0..100) < self.failure_rate {
+            true => Some(block),
+            false => None
+        }
+    }
+
+    ///
+    /// Describe the actions applied to a request, `blocked` is the block decided for it
+    ///
+    pub fn describe(&self, blocked: Option<BlockKind>) -> String {
+        let mut actions = vec![];
+
+        if self.latency > 0 {
+            actions.push(format!("latency {}ms", self.latency));
+        }
+
+        match blocked {
+            Some(BlockKind::Reset) => actions.push("block by connection reset".to_string()),
+            Some(BlockKind::Empty) => actions.push("block by empty reply".to_string()),
+            Some(BlockKind::Status) => actions.push(format!("block with status {}", self.block_status)),
+            None if self.bandwidth > 0 => actions.push(format!("bandwidth {}B/s", self.bandwidth)),
+            None => {}
+        }
+
+        // the block is not applied to this request and nothing else is configured
+        if actions.is_empty() {
+            return "pass".to_string();
+        }
+
+        actions.join(", ")
+    }
+}
+
+///
+/// Stream which limits the bytes written per second, writes are cut into slices and paced
+///
+/// # Notes
+/// Bandwidth 0 writes through without limit
+pub struct ThrottledStream<'a, W> {
+    inner: &'a mut W,
+    bandwidth: u64,
+    started: Instant,
+    written: u64,
+    delay: Option<Pin<Box<dyn Future<Output = ()> + Send>>>,
+}
+
+impl<'a, W: Write + Unpin> ThrottledStream<'a, W> {
+    pub fn new(inner: &'a mut W, bandwidth: u64) -> ThrottledStream<'a, W> {
+        ThrottledStream {
+            inner,
+            bandwidth,
+            started: Instant::now(),
+            written: 0,
+            delay: None
+        }
+    }
+
+    ///
+    /// Get the max size of one slice
+    ///
+    fn slice_size(&self) -> u64 {
+        std::cmp::max(self.bandwidth / THROTTLE_SLICES_PER_SECOND, 1)
+    }
+}
+
+impl<W: Write + Unpin> Write for ThrottledStream<'_, W> {
+    fn poll_write(self: Pin<&mut Self>, cx: &mut Context<'_>, buf: &[u8]) -> Poll<std::io::Result<usize>> {
+        let this = self.get_mut();
+
+        if this.bandwidth == 0 || buf.is_empty() {
+            return Pin::new(&mut *this.inner).poll_write(cx, buf);
+        }
+
+        loop {
+            if let Some(delay) = this.delay.as_mut() {
+                if delay.as_mut().poll(cx).is_pending() {
+                    return Poll::Pending;
+                }
+                this.delay = None;
+            }
+
+            let size = std::cmp::min(buf.len() as u64, this.slice_size());
+            let due = Duration::from_secs_f64((this.written + size) as f64 / this.bandwidth as f64);
+            let elapsed = this.started.elapsed();
+
+            if elapsed < due {
+                this.delay = Some(Box::pin(async_std::task::sleep(due - elapsed)));
+                continue;
+            }
+
+            let written = match Pin::new(&mut *this.inner).poll_write(cx, &buf[..size as usize]) {
+                Poll::Ready(Ok(written)) => written,
+                other => {
+                    return other;
+                }
+            };
+
+            this.written += written as u64;
+            return Poll::Ready(Ok(written));
+        }
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<std::io::Result<()>> {
+        Pin::new(&mut *self.get_mut().inner).poll_flush(cx)
+    }
+
+    fn poll_close(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<std::io::Result<()>> {
+        Pin::new(&mut *self.get_mut().inner).poll_close(cx)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn describe_network_conditions() {
+        let slow = NetworkCondition {
+            latency: 300,
+            bandwidth: 1024,
+            ..NetworkCondition::default()
+        };
+        assert!(!slow.always_blocks());
+        assert_eq!(slow.roll_block(), None);
+        assert_eq!(slow.describe(None), "latency 300ms, bandwidth 1024B/s");
+
+        let flaky = NetworkCondition {
+            block: Some(BlockKind::Status),
+            failure_rate: 0,
+            ..NetworkCondition::default()
+        };
+        assert!(flaky.verify());
+        assert_eq!(flaky.roll_block(), None);
+        assert_eq!(flaky.describe(None), "pass");
+        assert_eq!(flaky.describe(Some(BlockKind::Status)), "block with status 503");
+
+        let blocked = NetworkCondition {
+            block: Some(BlockKind::Reset),
+            ..NetworkCondition::default()
+        };
+        assert!(blocked.always_blocks());
+        assert_eq!(blocked.roll_block(), Some(BlockKind::Reset));
+
+        assert!(!NetworkCondition { failure_rate: 101, ..NetworkCondition::default() }.verify());
+        assert!(NetworkCondition::default().is_empty());
+    }
+
+    #[test]
+    fn throttle_response_stream() {
+        async_std::task::block_on(async {
+            let mut sink: Vec<u8> = vec![];
+            let started = Instant::now();
+
+            {
+                let mut stream = ThrottledStream::new(&mut sink, 1000);
+                async_std::io::WriteExt::write_all(&mut stream, &[0u8; 200]).await.unwrap();
+            }
+
+            assert_eq!(sink.len(), 200);
+            assert!(started.elapsed() >= Duration::from_millis(190));
+        });
+    }
+}
@@ -0,0 +1,170 @@
+use std::collections::HashMap;
+
+/// Content type which asks lopxy to detect the real content type of local file
+pub const AUTO_CONTENT_TYPE: &str = "auto";
+
+/// Content type used when nothing is detected
+pub const DEFAULT_CONTENT_TYPE: &str = "application/octet-stream";
+
+/// Count of bytes read from the head of file for sniffing
+pub const SNIFF_SIZE: usize = 512;
+
+/// Builtin extension map, can be overridden by `content_type_extensions` of lopxy config
+static BUILTIN_EXTENSIONS: &[(&str, &str)] = &[
+    ("html", "text/html; charset=utf-8"),
+    ("htm", "text/html; charset=utf-8"),
+    ("css", "text/css; charset=utf-8"),
+    ("js", "text/javascript; charset=utf-8"),
+    ("mjs", "text/javascript; charset=utf-8"),
+    ("json", "application/json"),
+    ("map", "application/json"),
+    ("xml", "application/xml"),
+    ("txt", "text/plain; charset=utf-8"),
+    ("md", "text/markdown; charset=utf-8"),
+    ("csv", "text/csv; charset=utf-8"),
+    ("yaml", "application/yaml"),
+    ("yml", "application/yaml"),
+    ("svg", "image/svg+xml"),
+    ("png", "image/png"),
+    ("jpg", "image/jpeg"),
+    ("jpeg", "image/jpeg"),
+    ("gif", "image/gif"),
+    ("webp", "image/webp"),
+    ("avif", "image/avif"),
+    ("bmp", "image/bmp"),
+    ("ico", "image/x-icon"),
+    ("woff", "font/woff"),
+    ("woff2", "font/woff2"),
+    ("ttf", "font/ttf"),
+    ("otf", "font/otf"),
+    ("eot", "application/vnd.ms-fontobject"),
+    ("wasm", "application/wasm"),
+    ("pdf", "application/pdf"),
+    ("zip", "application/zip"),
+    ("gz", "application/gzip"),
+    ("tar", "application/x-tar"),
+    ("7z", "application/x-7z-compressed"),
+    ("mp3", "audio/mpeg"),
+    ("wav", "audio/wav"),
+    ("ogg", "audio/ogg"),
+    ("mp4", "video/mp4"),
+    ("webm", "video/webm"),
+];
+
+pub fn is_auto_content_type(content_type: &str) -> bool {
+    content_type.trim().eq_ignore_ascii_case(AUTO_CONTENT_TYPE)
+}
+
+///
+/// Guess content type from the extension of path
+///
+/// # Notes
+/// Keys of `extensions` are extensions without the leading `.`, case insensitive, and take priority over the builtin map
+pub fn guess_from_extension(path: &str, extensions: &HashMap<String, String>) -> Option<String> {
+    let file_name = path.rsplit(['/', '\\']).next()?;
+    let (_, extension) = file_name.rsplit_once('.')?;
+    let extension = extension.to_lowercase();
+
+    let user_defined = extensions.iter().find(|(key, _)| key.trim_start_matches('.').eq_ignore_ascii_case(&extension));
+    if let Some((_, content_type)) = user_defined {
+        return Some(content_type.clone());
+    }
+
+    BUILTIN_EXTENSIONS.iter().
+        find(|(key, _)| key.eq(&extension)).
+        map(|(_, content_type)| content_type.to_string())
+}
+
+///
+/// Sniff content type from the magic bytes at the head of content
+///
+pub fn sniff(head: &[u8]) -> Option<&'static str> {
+    let magics: &[(&[u8], &str)] = &[
+        (b"\x89PNG\r\n\x1a\n", "image/png"),
+        (b"\xff\xd8\xff", "image/jpeg"),
+        (b"GIF87a", "image/gif"),
+        (b"GIF89a", "image/gif"),
+        (b"\x00\x00\x01\x00", "image/x-icon"),
+        (b"%PDF-", "application/pdf"),
+        (b"PK\x03\x04", "application/zip"),
+        (b"\x1f\x8b", "application/gzip"),
+        (b"7z\xbc\xaf\x27\x1c", "application/x-7z-compressed"),
+        (b"\x00asm", "application/wasm"),
+        (b"wOFF", "font/woff"),
+        (b"wOF2", "font/woff2"),
+        (b"\x00\x01\x00\x00", "font/ttf"),
+        (b"OTTO", "font/otf"),
+        (b"ID3", "audio/mpeg"),
+        (b"OggS", "audio/ogg"),
+        (b"\x1a\x45\xdf\xa3", "video/webm"),
+    ];
+
+    if let Some((_, content_type)) = magics.iter().find(|(magic, _)| head.starts_with(magic)) {
+        return Some(content_type);
+    }
+
+    if head.len() >= 12 && head.starts_with(b"RIFF") {
+        match &head[8..12] {
+            b"WEBP" => return Some("image/webp"),
+            b"WAVE" => return Some("audio/wav"),
+            _ => {}
+        }
+    }
+
+    if head.len() >= 12 && &head[4..8] == b"ftyp" {
+        return Some(if &head[8..12] == b"avif" { "image/avif" } else { "video/mp4" });
+    }
+
+    sniff_text(head)
+}
+
+///
+/// Sniff markup and plain text, bytes cut at the end of head are tolerated
+///
+fn sniff_text(head: &[u8]) -> Option<&'static str> {
+    let text = match std::str::from_utf8(head) {
+        Ok(text) => text,
+        Err(err) if err.error_len().is_none() => std::str::from_utf8(&head[..err.valid_up_to()]).ok()?,
+        Err(_) => return None
+    };
+
+    if text.chars().any(|c| c.is_control() && !c.is_whitespace()) {
+        return None;
+    }
+
+    let trimmed = text.trim_start_matches('\u{feff}').trim_start().to_lowercase();
+
+    if trimmed.starts_with("<!doctype html") || trimmed.starts_with("<html") {
+        Some("text/html; charset=utf-8")
+    } else if trimmed.starts_with("<svg") || (trimmed.starts_with("<?xml") && trimmed.contains("<svg")) {
+        Some("image/svg+xml")
+    } else if trimmed.starts_with("<?xml") {
+        Some("application/xml")
+    } else if trimmed.starts_with('{') || trimmed.starts_with('[') {
+        Some("application/json")
+    } else {
+        Some("text/plain; charset=utf-8")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn detect_content_types() {
+        let mut extensions = HashMap::new();
+        extensions.insert(".JS".to_string(), "application/x-javascript".to_string());
+
+        assert_eq!(guess_from_extension("/srv/a/style.CSS", &extensions).as_deref(), Some("text/css; charset=utf-8"));
+        assert_eq!(guess_from_extension("/srv/a/app.js", &extensions).as_deref(), Some("application/x-javascript"));
+        assert_eq!(guess_from_extension("/srv/a.d/file", &extensions), None);
+
+        assert_eq!(sniff(b"\x89PNG\r\n\x1a\n\x00\x00"), Some("image/png"));
+        assert_eq!(sniff(b"RIFF\x00\x00\x00\x00WEBPVP8 "), Some("image/webp"));
+        assert_eq!(sniff(b"  <!DOCTYPE html><html>"), Some("text/html; charset=utf-8"));
+        assert_eq!(sniff("console.log('\u{6587}');".as_bytes()), Some("text/plain; charset=utf-8"));
+        assert_eq!(sniff(&"\u{6587}".as_bytes()[..2]), Some("text/plain; charset=utf-8"));
+        assert_eq!(sniff(b"\x00\x01\x02\x03\x04"), None);
+    }
+}
@@ -1,30 +1,99 @@
 use async_std::io::WriteExt;
 use async_std::net::TcpStream;
 
-use futures::FutureExt;
-
 use super::super::request::*;
 use super::super::response::*;
 use super::super::stream::*;
 use super::super::item::*;
+use super::super::body::{self, BodyRule};
+use super::super::cache::{CacheFetch, ResponseCache};
+use super::super::condition::{BlockKind, NetworkCondition, ThrottledStream};
+use super::super::header::{self, HeaderRule, HeaderRewriteStream};
+use super::super::upstream::{self, UpstreamRoute};
+
+use futures::SinkExt;
+
+/// Count of request body pieces buffered between proxy client and upstream
+const REQUEST_BODY_CHANNEL_CAPACITY: usize = 4;
+
+/// How long to wait for the interim response of `Expect: 100-continue`
+const EXPECT_CONTINUE_TIMEOUT: std::time::Duration = std::time::Duration::from_secs(1);
 
-pub async fn handle_proxy_request(proxy_request: LopxyProxyRequest<'_>) {
+/// Max size of request body buffered for fallback mode and mirrors, a larger body is only sent once
+const BUFFERED_BODY_LIMIT: u64 = 1024 * 1024;
+
+///
+/// Handle http proxy request
+/// 
+/// # Notes
+/// Return whether the client connection should be kept for the next request
+pub async fn handle_proxy_request(mut proxy_request: LopxyProxyRequest<'_>) -> bool {
     // fetch request url
-    let request_url = match proxy_request.request_header.path {
+    let request_url = match proxy_request.try_request_url() {
         Some(url) => url,
         None => {
             eprintln!("invalid request url");
-            return;
+            proxy_request.client.reply_400().await;
+            return false;
         }
     };
+    let request_url = &request_url[..];
+
+    // whether to keep the connection after this request
+    let keep_alive = proxy_request.keep_alive();
 
     // lopxy proxy redirect
     let proxy_redirect = proxy_request.client.proxy_redirect(request_url);
+    proxy_request.header_rules = proxy_request.client.header_rules(request_url, proxy_redirect.as_ref());
+    proxy_request.body_rules = proxy_redirect.as_ref().
+        map(|item: &ProxyItem| item.body_rules().iter().filter_map(|rule: &String| BodyRule::parse(rule)).collect()).
+        unwrap_or_default();
+
+    // ftp request sent by browser, lopxy acts as the ftp client
+    if proxy_redirect.is_none() && is_uri_scheme(request_url, "ftp") {
+        return super::ftp::handle_ftp_request(proxy_request, request_url, request_url, super::super::content_type::AUTO_CONTENT_TYPE, None).await && keep_alive;
+    }
+
+    // offline cache of GET requests, range requests are not cached
+    let response_cache = match proxy_request.method().eq_ignore_ascii_case("GET") && find_header(&proxy_request.request_header.headers, "range").is_none() {
+        true => proxy_request.client.response_cache(request_url),
+        false => None
+    };
+
+    // direct request, requests of an intercepted connection are sent through https client instead,
+    // so are the requests which go through a http upstream proxy, have header rules or are cached
+    if proxy_redirect.is_none() && !proxy_request.client.stream.is_tls() && proxy_request.header_rules.is_empty() && response_cache.is_none() {
+        let route = proxy_request.client.upstream_route(None, request_url);
+        if !route.is_http_proxy() {
+            return handle_direct_request(proxy_request, request_url, &route).await && keep_alive;
+        }
+    }
+
+    // network condition
+    if let Some(condition) = proxy_redirect.as_ref().and_then(|item: &ProxyItem| item.network_condition()) {
+        if let Some(reusable) = apply_network_condition(&mut proxy_request, request_url, condition).await {
+            return reusable && keep_alive;
+        }
+    }
+
+    // redirect mode
+    if let Some(proxy_item) = proxy_redirect.as_ref().filter(|item: &&ProxyItem| item.mode() == ProxyMode::Redirect) {
+        return handle_redirect_mode_request(proxy_request, request_url, proxy_item.clone()).await && keep_alive;
+    }
 
-    // direct request
-    if proxy_redirect.is_none() && !proxy_request.client.use_system_proxy() {
-        handle_direct_request(proxy_request, request_url).await;
-        return;
+    // fallback mode
+    if let Some(proxy_item) = proxy_redirect.as_ref().filter(|item: &&ProxyItem| item.mode() == ProxyMode::Fallback) {
+        return handle_fallback_request(proxy_request, request_url, proxy_item.clone()).await && keep_alive;
+    }
+
+    // mock response
+    if let Some(proxy_item) = proxy_redirect.as_ref().filter(|item: &&ProxyItem| item.mock_response().is_some()) {
+        return handle_mock_request(proxy_request, request_url, proxy_item.clone()).await && keep_alive;
+    }
+
+    // proxy resource with mirrors
+    if let Some(proxy_item) = proxy_redirect.as_ref().filter(|item: &&ProxyItem| !item.mirror_urls().is_empty()) {
+        return handle_mirror_request(proxy_request, proxy_item.clone()).await && keep_alive;
     }
 
     // local file
@@ -34,130 +103,335 @@ pub async fn handle_proxy_request(proxy_request: LopxyProxyRequest<'_>) {
             Some(s) => s,
             None => {
                 eprintln!("invalid request scheme");
-                return;
+                proxy_request.client.reply_502().await;
+                return keep_alive;
             }
         };
 
         if scheme.eq_ignore_ascii_case("file") {
-            handle_local_file_request(proxy_request, proxy_item.clone()).await;
-            return;
+            return handle_local_file_request(proxy_request, proxy_item.clone()).await && keep_alive;
+        }
+
+        if scheme.eq_ignore_ascii_case("ftp") {
+            return super::ftp::handle_ftp_request(proxy_request, request_url, proxy_item.proxy_resource_url(), proxy_item.content_type(), Some(proxy_item)).await && keep_alive;
         }
     }
 
+    // offline cache
+    if let Some(response_cache) = response_cache {
+        return handle_cached_request(proxy_request, request_url, proxy_redirect, response_cache).await && keep_alive;
+    }
+
     // do redirect request
-    handle_redirect_request(proxy_request, request_url, proxy_redirect).await;
+    handle_redirect_request(proxy_request, request_url, proxy_redirect).await && keep_alive
 }
 
-async fn direct_tunnel_transmit(proxy_request: &mut LopxyProxyRequest<'_>, server_stream: &mut TcpStream) -> std::io::Result<Option<u16>>
+///
+/// Apply the network condition of proxy item to the request
+/// 
+/// # Notes
+/// The latency is waited first. A blocked request returns whether the client connection can be reused,
+/// otherwise None is returned and the response stream of the request is throttled. Reset leaves the rest
+/// of the request unread, so that closing the connection makes the client see a reset if any is pending
+async fn apply_network_condition(proxy_request: &mut LopxyProxyRequest<'_>, request_url: &str, condition: &NetworkCondition) -> Option<bool> {
+    let blocked = condition.roll_block();
+    proxy_request.report_network_condition(request_url, condition.describe(blocked));
+
+    if condition.latency > 0 {
+        async_std::task::sleep(std::time::Duration::from_millis(condition.latency)).await;
+    }
+
+    let block = match blocked {
+        Some(block) => block,
+        None => {
+            proxy_request.bandwidth = condition.bandwidth;
+            return None;
+        }
+    };
+
+    if block == BlockKind::Reset {
+        return Some(false);
+    }
+
+    if let Err(err) = proxy_request.client.reader.skip_body(&mut proxy_request.client.stream).await {
+        eprintln!("read proxy request body failed : {}", err);
+        return Some(false);
+    }
+
+    if block == BlockKind::Empty {
+        return Some(false);
+    }
+
+    let reason = reqwest::StatusCode::from_u16(condition.block_status).ok().
+        and_then(|status: reqwest::StatusCode| status.canonical_reason()).
+        unwrap_or("Blocked");
+
+    Some(proxy_request.client.reply(&build_error_page_response(condition.block_status, reason)).await.is_ok())
+}
+
+///
+/// Transmit request to remote http server and relay the response to client
+/// 
+/// # Notes
+/// Return the final response status code and whether the client connection can be reused
+async fn direct_tunnel_transmit(proxy_request: &mut LopxyProxyRequest<'_>, server_stream: &mut TcpStream) -> Result<(u16, bool), HttpStreamError>
 {
-    server_stream.write_all(proxy_request.request_bytes).await?;
-    let raw_response_bytes = collect_tcp_stream_buffer(server_stream).await?;
-    let status_code = try_response_status(&raw_response_bytes);
-    proxy_request.client.stream.write_all(&raw_response_bytes).await?;
-    Ok(status_code)
+    let mut server_reader = HttpMessageReader::new();
+
+    // request head
+    server_stream.write_all(&proxy_request.request_header.raw).await?;
+
+    // wait for the interim response of `Expect: 100-continue` before relaying the request body
+    if proxy_request.body_length() != BodyLength::Empty && header_has_token(&proxy_request.request_header.headers, "expect", "100-continue") {
+        match async_std::future::timeout(
+            EXPECT_CONTINUE_TIMEOUT,
+            server_reader.read_response_head(server_stream, &proxy_request.method)
+        ).await {
+            Ok(result) => {
+                let response_header = result?;
+
+                if response_header.status != 100 {
+                    // upstream rejects the request body, the client connection state is unknown after the final response
+                    let (status, _) = relay_server_response(proxy_request, server_stream, &mut server_reader, Some(response_header)).await?;
+                    return Ok((status, false));
+                }
+
+                proxy_request.client.stream.write_all(&response_header.raw).await?;
+            }
+
+            Err(_) => {
+                // upstream ignores the expectation, let the client send the body
+                proxy_request.client.stream.write_all(b"HTTP/1.1 100 Continue\r\n\r\n").await?;
+            }
+        }
+    }
+
+    // request body
+    relay_body(&mut proxy_request.client.reader, &mut proxy_request.client.stream, server_stream).await?;
+
+    // response head and body
+    relay_server_response(proxy_request, server_stream, &mut server_reader, None).await
+}
+
+///
+/// Relay the response of remote http server to client
+/// 
+/// # Notes
+/// Informational responses are relayed until the final response, `first_header` is the already read response head
+async fn relay_server_response(proxy_request: &mut LopxyProxyRequest<'_>, server_stream: &mut TcpStream, server_reader: &mut HttpMessageReader, first_header: Option<HttpResponseHead>) -> Result<(u16, bool), HttpStreamError>
+{
+    let mut first_header = first_header;
+
+    loop {
+        let response_header = match first_header.take() {
+            Some(head) => head,
+            None => server_reader.read_response_head(server_stream, &proxy_request.method).await?
+        };
+        proxy_request.client.stream.write_all(&response_header.raw).await?;
+
+        // switching protocols, the connection becomes a tunnel
+        if response_header.status == 101 {
+            let server_buffered = server_reader.take_buffered();
+            proxy_request.client.stream.write_all(&server_buffered).await?;
+            let client_buffered = proxy_request.client.reader.take_buffered();
+            server_stream.write_all(&client_buffered).await?;
+
+            super::tunnel_transmit(proxy_request.client, server_stream, "http upgrade tunnel").await;
+            return Ok((response_header.status, false));
+        }
+
+        // informational response, the final response follows
+        if (100..200).contains(&response_header.status) {
+            continue;
+        }
+
+        let reusable = server_reader.body_length() != BodyLength::UntilClose;
+        relay_body(server_reader, server_stream, &mut proxy_request.client.stream).await?;
+
+        return Ok((response_header.status, reusable));
+    }
 }
 
 ///
 /// Handle direct request
 /// 
 /// # Notes
-/// At present, for the `Keep-Alive Session`, except for the first request, 
-/// the subsequent request will not take over the processing temporarily
-async fn handle_direct_request(mut proxy_request: LopxyProxyRequest<'_>, request_url: &str) {
+/// Each request of a `Keep-Alive Session` is forwarded separately, so that the
+/// subsequent requests can still be taken over by lopxy proxy items. The remote host
+/// is reached directly or through a socks5 upstream proxy
+async fn handle_direct_request(mut proxy_request: LopxyProxyRequest<'_>, request_url: &str, route: &UpstreamRoute) -> bool {
     // connect remote host
-    let mut server_stream = match TcpStream::connect(&proxy_request.host).await {
+    let mut server_stream = match upstream::connect(route, &proxy_request.host).await {
         Ok(stream) => stream,
         Err(err) => {
             eprintln!("connect remote http server failed : {}", err);
             proxy_request.report_connection_error(&proxy_request.host, proxy_request.try_request_url(), &err);
-            return;
+            proxy_request.client.reply_502().await;
+            return true;
         }
     };
 
     match direct_tunnel_transmit(&mut proxy_request, &mut server_stream).await {
-        Ok(Some(status_code)) => {
+        Ok((status_code, reusable)) => {
             proxy_request.report_proxy_request_status(request_url, status_code);
-        },
-        Ok(None) => {
-
+            reusable
         },
         Err(err) => {
             eprintln!("direct tunnel transmit failed : {}", err);
             proxy_request.report_connection_error(&proxy_request.host, proxy_request.try_request_url(), &err);
-            return;
+            false
         }
     }
+}
 
-    //
-    // tunnel
-    //
+///
+/// Handle local file request
+/// 
+/// # Notes
+/// Return whether the client connection can be reused. With body rules the whole file is read and
+/// rewritten before sending, so that ranges are ignored
+async fn handle_local_file_request(proxy_request: LopxyProxyRequest<'_>, proxy_redirect: ProxyItem) -> bool {
+    // the request body is useless for local file
+    if let Err(err) = proxy_request.client.reader.skip_body(&mut proxy_request.client.stream).await {
+        eprintln!("read proxy request body failed : {}", err);
+        return false;
+    }
+
+    let rewrite_body = !proxy_request.body_rules.is_empty() &&
+        !proxy_request.method().eq_ignore_ascii_case("HEAD") &&
+        super::super::local::resolve_local_file_path(proxy_redirect.proxy_resource_url()).
+            and_then(|file_path: std::path::PathBuf| std::fs::metadata(file_path).ok()).
+            is_some_and(|metadata: std::fs::Metadata| metadata.len() <= body::REWRITE_BODY_LIMIT as u64);
 
-    let (client_receiver, client_sender) = &mut (&proxy_request.client.stream, &proxy_request.client.stream);
-    let (server_receiver, server_sender) = &mut (&server_stream, &server_stream);
- 
-    let cf1 = async_std::io::copy(client_receiver, server_sender);
-    let cf2 = async_std::io::copy(server_receiver, client_sender);
+    let mut rewritten: Option<Vec<u8>> = None;
+    if rewrite_body {
+        let mut request_header = proxy_request.request_header.clone();
+        request_header.remove_header("range");
+        request_header.remove_header("if-range");
 
-    let waits = move || async move {
-        futures::select! {
-           r1 = cf1.fuse() => r1,
-           r2 = cf2.fuse() => r2,
+        let mut raw_response_bytes: Vec<u8> = vec![];
+        if let Err(err) = super::super::local::send_local_resource(&mut raw_response_bytes, &request_header, &proxy_redirect).await {
+            eprintln!("read local file failed : {}", err);
+            proxy_request.client.reply_502().await;
+            return true;
         }
-    };
 
-    match proxy_request.client.shutdown.wrap_cancel(waits()).await {
-        Some(Ok(_)) => {},
-        Some(Err(err)) => eprintln!("http tunnel failed : {}", err),
-        None => eprintln!("proxy server shutdown triggered, closing connection"),
+        let request_url = proxy_request.request_url();
+        rewritten = Some(apply_body_rules(&proxy_request, &request_url, raw_response_bytes));
     }
-}
 
-async fn handle_local_file_request(mut proxy_request: LopxyProxyRequest<'_>, proxy_redirect: ProxyItem) {
-    let raw_response_bytes = build_local_file_response(proxy_redirect.proxy_resource_url(), proxy_redirect.content_type());
-    match proxy_request.client.reply(&raw_response_bytes).await { _ => {} };
+    let mut stream = HeaderRewriteStream::new(
+        ThrottledStream::new(&mut proxy_request.client.stream, proxy_request.bandwidth),
+        &proxy_request.header_rules.response
+    );
+
+    let sent = match &rewritten {
+        Some(raw_response_bytes) => stream.write_all(raw_response_bytes).await,
+        None => super::super::local::send_local_resource(&mut stream, &proxy_request.request_header, &proxy_redirect).await.map(|_| ())
+    };
+    match sent.and(stream.flush().await) {
+        Ok(_) => true,
+        Err(err) => {
+            eprintln!("send local file response failed : {}", err);
+            false
+        }
+    }
 }
 
-async fn handle_redirect_request(mut proxy_request: LopxyProxyRequest<'_>, request_url: &str, proxy_redirect: Option<ProxyItem>) {
-    let mut client_builder = reqwest::Client::builder();
+///
+/// Handle request of proxy item in redirect mode
+/// 
+/// # Notes
+/// The location is the first healthy web target of proxy item, so that the client fetches it directly.
+/// Return whether the client connection can be reused
+async fn handle_redirect_mode_request(mut proxy_request: LopxyProxyRequest<'_>, request_url: &str, proxy_item: ProxyItem) -> bool {
+    // the request body is useless for redirect
+    if let Err(err) = proxy_request.client.reader.skip_body(&mut proxy_request.client.stream).await {
+        eprintln!("read proxy request body failed : {}", err);
+        return false;
+    }
 
-    // config proxy
-    if proxy_request.client.use_system_proxy() {
-        let system_proxy_addr = format!("http://{}", proxy_request.client.system_proxy_config.proxy_server.as_ref().unwrap());
-        match reqwest::Proxy::http(system_proxy_addr) {
-            Ok(proxy) => {
-                client_builder = client_builder.proxy(proxy);
-            }
+    let target_url = proxy_request.order_proxy_targets(&proxy_item).into_iter().
+        map(|target: ProxyTarget| target.url).
+        find(|url: &String| is_uri_scheme(url, "http") || is_uri_scheme(url, "https")).
+        unwrap_or(proxy_item.proxy_resource_url().to_string());
 
-            Err(_) => {
+    let location = match proxy_item.preserve_query() {
+        true => append_request_query(&target_url, request_url),
+        false => target_url
+    };
 
-            }
+    let status = proxy_item.redirect_status();
+    let reason = reqwest::StatusCode::from_u16(status).ok().and_then(|status: reqwest::StatusCode| status.canonical_reason()).unwrap_or("Found");
+    let raw_response_head = format!("HTTP/1.1 {} {}\r\nlocation: {}\r\ncontent-length: 0\r\n\r\n", status, reason, location);
+
+    proxy_request.report_proxy_request_status(request_url, status);
+
+    let mut stream = proxy_request.response_stream();
+    match stream.write_all(raw_response_head.as_bytes()).await.and(stream.flush().await) {
+        Ok(_) => true,
+        Err(err) => {
+            eprintln!("send response to proxy client failed : {}", err);
+            false
         }
     }
+}
 
-    // collect headers
-    let mut headers = proxy_request.headers();
+///
+/// Handle request of proxy item with mock response
+/// 
+/// # Notes
+/// The mock response is answered directly, return whether the client connection can be reused
+async fn handle_mock_request(mut proxy_request: LopxyProxyRequest<'_>, request_url: &str, proxy_item: ProxyItem) -> bool {
+    // the request body is useless for mock response
+    if let Err(err) = proxy_request.client.reader.skip_body(&mut proxy_request.client.stream).await {
+        eprintln!("read proxy request body failed : {}", err);
+        return false;
+    }
 
-    // build request
-    let request = match client_builder.build() {
-        Ok(request) => request,
-        Err(err) => {
-            eprintln!("build request failed : {}", err);
+    let mock_response = proxy_item.mock_response().unwrap();
+    let raw_response_bytes = match mock_response.build_raw_response(proxy_request.method(), proxy_item.content_type()) {
+        Some(raw_response_bytes) => raw_response_bytes,
+        None => {
+            eprintln!("invalid mock response of {}", proxy_item.resource_url());
             proxy_request.client.reply_502().await;
-            return;
+            return true;
         }
     };
 
-    // request url
-    let request_url = match proxy_redirect {
-        Some(ref item) => item.proxy_resource_url(),
-        None => request_url
-    };
+    proxy_request.report_proxy_request_status(request_url, mock_response.status);
+
+    let mut stream = proxy_request.response_stream();
+    match stream.write_all(&raw_response_bytes).await.and(stream.flush().await) {
+        Ok(_) => true,
+        Err(err) => {
+            eprintln!("send response to proxy client failed : {}", err);
+            false
+        }
+    }
+}
+
+///
+/// Build the upstream request of proxy request to `target_url`
+/// 
+/// # Notes
+/// The request body is not attached, `Expect` is removed because lopxy answers it itself.
+/// `proxy_item` is the proxy item whose target is requested, its upstream proxy is preferred
+fn build_upstream_request(proxy_request: &LopxyProxyRequest<'_>, proxy_item: Option<&ProxyItem>, target_url: &str) -> reqwest::Result<reqwest::RequestBuilder> {
+    // config proxy
+    let client_builder = proxy_request.client.upstream_route(proxy_item, target_url).apply(reqwest::Client::builder())?;
+
+    // collect headers
+    let mut headers = proxy_request.headers();
+
+    // build request
+    let request = client_builder.build()?;
 
     //
     // request host
     //
 
-    let host = match get_host_from_url(request_url) {
+    let host = match get_host_from_url(target_url) {
         Some(host) => host,
         None => "".to_string()
     };
@@ -172,42 +446,699 @@ async fn handle_redirect_request(mut proxy_request: LopxyProxyRequest<'_>, reque
         }
     };
 
-    // execute request
-    let response = match {
-            match proxy_request.method().to_uppercase().as_ref() {
-                "GET" => request.get(request_url),
-                "POST" => request.post(request_url),
-                "PUT" => request.put(request_url),
-                "DELETE" => request.delete(request_url),
-                "HEAD" => request.head(request_url),
-                "PATCH" => request.patch(request_url),
-                _ => request.get(request_url),
-            }
-            .headers(headers)
-            .body(proxy_request.body())
-            .send()
-            .await
-    } {
+    // request body
+    if let BodyLength::Length(length) = proxy_request.body_length() {
+        headers.insert(reqwest::header::CONTENT_LENGTH, reqwest::header::HeaderValue::from(length));
+    }
+
+    headers.remove(reqwest::header::EXPECT);
+
+    Ok(match proxy_request.method().to_uppercase().as_ref() {
+        "GET" => request.get(target_url),
+        "POST" => request.post(target_url),
+        "PUT" => request.put(target_url),
+        "DELETE" => request.delete(target_url),
+        "HEAD" => request.head(target_url),
+        "PATCH" => request.patch(target_url),
+        _ => request.get(target_url),
+    }.headers(headers))
+}
+
+///
+/// Answer `Expect: 100-continue` of proxy request, return false if the client connection is broken
+/// 
+async fn reply_100_continue(proxy_request: &mut LopxyProxyRequest<'_>) -> bool {
+    if proxy_request.body_length() == BodyLength::Empty || !header_has_token(&proxy_request.request_header.headers, "expect", "100-continue") {
+        return true;
+    }
+
+    proxy_request.client.reply(b"HTTP/1.1 100 Continue\r\n\r\n").await.is_ok()
+}
+
+///
+/// Report the upstream response and relay it to proxy client
+/// 
+/// # Notes
+/// Return whether the client connection can be reused
+async fn relay_upstream_response(proxy_request: &mut LopxyProxyRequest<'_>, request_url: &str, response: reqwest::Result<reqwest::Response>) -> bool {
+    let response = match response {
         Ok(resp) => resp,
         Err(err) => {
             eprintln!("execute proxy redirect request failed : {}", err);
             proxy_request.report_connection_error(&proxy_request.host, Some(request_url.to_string()), &err);
-            return;
+            proxy_request.client.reply_502().await;
+            return true;
         }
     };
 
     // report response status
     proxy_request.report_proxy_request_status(request_url, response.status().as_u16());
 
-    // build response bytes
-    let raw_response_bytes: Vec<u8> = match build_raw_response_bytes(response).await {
-        Ok(bytes) => bytes,
+    // send response to lopxy proxy client
+    match relay_response_by_rules(proxy_request, request_url, response).await {
+        Ok(reusable) => reusable,
+        Err(err) => {
+            eprintln!("relay proxy redirect response failed : {}", err);
+            false
+        }
+    }
+}
+
+///
+/// Handle redirect request
+/// 
+/// # Notes
+/// Both request body and response body are streamed through lopxy piece by piece,
+/// the request body is pumped through a bounded channel so that a slow upstream slows down the client
+async fn handle_redirect_request(mut proxy_request: LopxyProxyRequest<'_>, request_url: &str, proxy_redirect: Option<ProxyItem>) -> bool {
+    // request url
+    let request_url = match proxy_redirect {
+        Some(ref item) => item.proxy_resource_url(),
+        None => request_url
+    };
+
+    let request_builder = match build_upstream_request(&proxy_request, proxy_redirect.as_ref(), request_url) {
+        Ok(request_builder) => request_builder,
         Err(err) => {
-            eprintln!("build raw response bytes failed : {}", err);
-            return;
+            eprintln!("build request failed : {}", err);
+            proxy_request.client.reply_502().await;
+            return true;
         }
     };
 
-    // send response to lopxy proxy client
-    match proxy_request.client.reply(&raw_response_bytes).await { _ => {} };
-}
\ No newline at end of file
+    // lopxy answers `Expect: 100-continue` itself, because the body is pumped to upstream by lopxy
+    if !reply_100_continue(&mut proxy_request).await {
+        return false;
+    }
+
+    let body_length = proxy_request.body_length();
+    let (body_sender, body_receiver) = futures::channel::mpsc::channel::<std::io::Result<Vec<u8>>>(REQUEST_BODY_CHANNEL_CAPACITY);
+    let client = &mut *proxy_request.client;
+    let body_pump = async move {
+        pump_request_body(client, body_sender).await
+    };
+
+    let request_builder = match body_length {
+        BodyLength::Empty => request_builder,
+        _ => request_builder.body(reqwest::Body::wrap_stream(body_receiver))
+    };
+
+    // execute request
+    let (pump_result, response) = futures::join!(body_pump, request_builder.send());
+
+    if let Err(err) = pump_result {
+        eprintln!("read proxy request body failed : {}", err);
+        if err.status().is_some() {
+            proxy_request.client.reply_400().await;
+        }
+        return false;
+    }
+
+    relay_upstream_response(&mut proxy_request, request_url, response).await
+}
+
+///
+/// Handle redirect request through the offline cache
+/// 
+/// # Notes
+/// A fresh copy is served without requesting upstream, the concurrent requests of the same url wait for
+/// the first one. When upstream fails, answers 5xx or times out, the stale copy is served if there is one
+async fn handle_cached_request(mut proxy_request: LopxyProxyRequest<'_>, request_url: &str, proxy_redirect: Option<ProxyItem>, cache: std::sync::Arc<ResponseCache>) -> bool {
+    // GET request body is useless
+    if let Err(err) = proxy_request.client.reader.skip_body(&mut proxy_request.client.stream).await {
+        eprintln!("read proxy request body failed : {}", err);
+        return false;
+    }
+
+    let target_url = match proxy_redirect {
+        Some(ref item) => item.proxy_resource_url(),
+        None => request_url
+    };
+
+    // the cached variant is chosen by the headers sent to upstream
+    let request_headers = proxy_request.headers();
+
+    // the client asks for revalidation by `no-cache`
+    let headers = &proxy_request.request_header.headers;
+    let revalidate = header_has_token(headers, "cache-control", "no-cache") || header_has_token(headers, "pragma", "no-cache");
+
+    if !revalidate {
+        if let Some(raw_response_bytes) = cache.load(target_url, true, &request_headers).await {
+            return send_cached_response(&mut proxy_request, request_url, raw_response_bytes, "hit").await;
+        }
+    }
+
+    // only one request fetches the url, the others are answered by the copy it stores
+    let _fetch_guard = match cache.begin_fetch(target_url) {
+        CacheFetch::Leader(guard) => Some(guard),
+        CacheFetch::Follower(receiver) => {
+            let _ = receiver.await;
+
+            if !revalidate {
+                if let Some(raw_response_bytes) = cache.load(target_url, true, &request_headers).await {
+                    return send_cached_response(&mut proxy_request, request_url, raw_response_bytes, "hit").await;
+                }
+            }
+            None
+        }
+    };
+
+    let request_builder = match build_upstream_request(&proxy_request, proxy_redirect.as_ref(), target_url) {
+        Ok(request_builder) => request_builder,
+        Err(err) => {
+            eprintln!("build request failed : {}", err);
+            proxy_request.client.reply_502().await;
+            return true;
+        }
+    };
+
+    // upstream is only waited for the timeout when there is a copy to fall back to
+    let response = match cache.contains(target_url) {
+        true => async_std::future::timeout(cache.timeout(), request_builder.send()).await.ok(),
+        false => Some(request_builder.send().await)
+    };
+
+    let stale_reason = match &response {
+        Some(Ok(resp)) if resp.status().is_server_error() => Some(format!("upstream status {}", resp.status().as_u16())),
+        Some(Ok(_)) => None,
+        Some(Err(err)) => Some(err.to_string()),
+        None => Some("upstream timeout".to_string())
+    };
+
+    if let Some(reason) = stale_reason {
+        if let Some(raw_response_bytes) = cache.load(target_url, false, &request_headers).await {
+            proxy_request.report_cache(request_url, format!("serve stale copy, {}", reason));
+            return send_cached_response(&mut proxy_request, request_url, raw_response_bytes, "stale").await;
+        }
+
+        if response.is_none() {
+            proxy_request.report_cache(request_url, format!("no cached copy, {}", reason));
+            proxy_request.client.reply_502().await;
+            return true;
+        }
+    }
+
+    match response {
+        Some(Ok(response)) => {
+            proxy_request.report_proxy_request_status(request_url, response.status().as_u16());
+
+            match relay_caching_response(&mut proxy_request, request_url, target_url, response, &cache, &request_headers).await {
+                Ok(reusable) => reusable,
+                Err(err) => {
+                    eprintln!("relay proxy redirect response failed : {}", err);
+                    false
+                }
+            }
+        },
+        Some(response) => relay_upstream_response(&mut proxy_request, request_url, response).await,
+        None => {
+            proxy_request.client.reply_502().await;
+            true
+        }
+    }
+}
+
+///
+/// Buffer upstream response, store it in cache and send it to proxy client
+///
+/// # Notes
+/// The response is relayed without storing if it's not cacheable, private to the client or larger than
+/// the max entry size, return whether the client connection can be reused
+async fn relay_caching_response(proxy_request: &mut LopxyProxyRequest<'_>, request_url: &str, target_url: &str, mut response: reqwest::Response, cache: &ResponseCache, request_headers: &reqwest::header::HeaderMap) -> Result<bool, Box<dyn std::error::Error>> {
+    let freshness = match cache.freshness(request_headers, response.headers()) {
+        Some(freshness) if response.status() == reqwest::StatusCode::OK => freshness,
+        _ => {
+            return relay_response_by_rules(proxy_request, request_url, response).await;
+        }
+    };
+
+    if response.content_length().is_some_and(|length: u64| length > cache.max_entry_size()) {
+        return relay_response_by_rules(proxy_request, request_url, response).await;
+    }
+
+    let (raw_response_head, framing) = build_raw_response_head(&response, proxy_request.method(), proxy_request.version());
+    let status = response.status();
+    let headers = response.headers().clone();
+
+    let mut received: Vec<u8> = vec![];
+    while let Some(chunk) = response.chunk().await? {
+        received.extend_from_slice(&chunk);
+
+        // the rest of body is streamed after the received part
+        if received.len() as u64 > cache.max_entry_size() {
+            return relay_response_rest(proxy_request, response, &raw_response_head, framing, &received).await;
+        }
+    }
+
+    let raw_response_bytes = super::super::cache::build_cached_response(status, &headers, &received);
+    cache.store(target_url, &raw_response_bytes, freshness, super::super::cache::vary_selection(request_headers, &headers)).await;
+
+    send_raw_response(proxy_request, request_url, raw_response_bytes).await
+}
+
+///
+/// Send a response served from cache, `X-Lopxy-Cache` tells whether the copy is fresh
+///
+async fn send_cached_response(proxy_request: &mut LopxyProxyRequest<'_>, request_url: &str, raw_response_bytes: Vec<u8>, state: &str) -> bool {
+    proxy_request.report_cache(request_url, state.to_string());
+
+    let raw_response_bytes = match HeaderRule::parse(&format!("add X-Lopxy-Cache: {}", state)) {
+        Some(rule) => {
+            let head_end = raw_response_bytes.windows(4).position(|window: &[u8]| window == b"\r\n\r\n").map_or(raw_response_bytes.len(), |pos: usize| pos + 4);
+            let mut rewritten = header::rewrite_response_head(&raw_response_bytes[..head_end], &[rule]);
+            rewritten.extend_from_slice(&raw_response_bytes[head_end..]);
+            rewritten
+        },
+        None => raw_response_bytes
+    };
+
+    match send_raw_response(proxy_request, request_url, raw_response_bytes).await {
+        Ok(reusable) => reusable,
+        Err(err) => {
+            eprintln!("send cached response failed : {}", err);
+            false
+        }
+    }
+}
+
+///
+/// Send a whole raw response to proxy client, the body is rewritten if any body rule applies
+///
+async fn send_raw_response(proxy_request: &mut LopxyProxyRequest<'_>, request_url: &str, raw_response_bytes: Vec<u8>) -> Result<bool, Box<dyn std::error::Error>> {
+    let raw_response_bytes = match proxy_request.body_rules.is_empty() {
+        true => raw_response_bytes,
+        false => apply_body_rules(proxy_request, request_url, raw_response_bytes)
+    };
+
+    let mut stream = proxy_request.response_stream();
+    stream.write_all(&raw_response_bytes).await?;
+    stream.flush().await?;
+
+    Ok(true)
+}
+
+///
+/// Read the request body into memory, so that it can be sent more than once
+/// 
+/// # Notes
+/// Return `Ok(None)` if the body is too large to buffer, `Err(())` if the client connection is broken
+async fn buffer_request_body(proxy_request: &mut LopxyProxyRequest<'_>) -> Result<Option<Vec<u8>>, ()> {
+    match proxy_request.body_length() {
+        BodyLength::Empty => Ok(Some(vec![])),
+        BodyLength::Length(length) if length <= BUFFERED_BODY_LIMIT => {
+            if !reply_100_continue(proxy_request).await {
+                return Err(());
+            }
+
+            match proxy_request.body().await {
+                Ok(body) => Ok(Some(body)),
+                Err(err) => {
+                    eprintln!("read proxy request body failed : {}", err);
+                    if err.status().is_some() {
+                        proxy_request.client.reply_400().await;
+                    }
+                    Err(())
+                }
+            }
+        },
+        _ => Ok(None)
+    }
+}
+
+///
+/// Attach the buffered request body to upstream request
+/// 
+fn with_body(request_builder: reqwest::RequestBuilder, body: &[u8]) -> reqwest::RequestBuilder {
+    if body.is_empty() { request_builder } else { request_builder.body(body.to_vec()) }
+}
+
+///
+/// Handle request of fallback mode proxy item
+/// 
+/// # Notes
+/// The original resource is requested first, the proxy resource is used only when the original one fails
+/// to connect, times out or responds with a fallback status, the request body is buffered because it may
+/// be sent twice
+async fn handle_fallback_request(mut proxy_request: LopxyProxyRequest<'_>, request_url: &str, proxy_item: ProxyItem) -> bool {
+    let body = match buffer_request_body(&mut proxy_request).await {
+        Ok(Some(body)) => body,
+        Ok(None) => {
+            // too large to buffer, only request the original resource
+            return handle_redirect_request(proxy_request, request_url, None).await;
+        },
+        Err(_) => {
+            return false;
+        }
+    };
+
+    //
+    // original resource
+    //
+
+    let request_builder = match build_upstream_request(&proxy_request, None, request_url) {
+        Ok(request_builder) => request_builder,
+        Err(err) => {
+            eprintln!("build request failed : {}", err);
+            proxy_request.client.reply_502().await;
+            return true;
+        }
+    };
+
+    let fallback_timeout = std::time::Duration::from_secs(proxy_item.fallback_timeout());
+    let reason = match async_std::future::timeout(fallback_timeout, with_body(request_builder, &body).send()).await {
+        Ok(Ok(response)) if !proxy_item.is_fallback_status(response.status().as_u16()) => {
+            proxy_request.report_fallback(request_url, None);
+            return relay_upstream_response(&mut proxy_request, request_url, Ok(response)).await;
+        },
+        Ok(Ok(response)) => format!("original responds {}", response.status()),
+        Ok(Err(err)) => format!("original request failed, {}", err),
+        Err(_) => format!("original request timeout after {}s", proxy_item.fallback_timeout()),
+    };
+
+    proxy_request.report_fallback(request_url, Some(format!("{} => {}", reason, proxy_item.proxy_resource_url())));
+
+    //
+    // proxy resource
+    //
+
+    if proxy_item.mirror_urls().is_empty() || proxy_item.mock_response().is_some() {
+        return request_proxy_target(proxy_request, proxy_item, &body).await;
+    }
+
+    request_proxy_targets(proxy_request, proxy_item, &body).await
+}
+
+///
+/// Request the proxy resource of proxy item with the buffered request body
+/// 
+async fn request_proxy_target(mut proxy_request: LopxyProxyRequest<'_>, proxy_item: ProxyItem, body: &[u8]) -> bool {
+    if proxy_item.mock_response().is_some() {
+        let request_url = proxy_request.request_url();
+        return handle_mock_request(proxy_request, &request_url, proxy_item).await;
+    }
+
+    if let Some(scheme) = get_uri_scheme(proxy_item.proxy_resource_url()) {
+        if scheme.eq_ignore_ascii_case("file") {
+            return handle_local_file_request(proxy_request, proxy_item).await;
+        }
+
+        if scheme.eq_ignore_ascii_case("ftp") {
+            let route = proxy_request.client.upstream_route(Some(&proxy_item), proxy_item.proxy_resource_url());
+            let resource = super::ftp::open_ftp_resource(proxy_item.proxy_resource_url(), !proxy_request.method().eq_ignore_ascii_case("HEAD"), &route).await;
+            return super::ftp::send_ftp_resource(&mut proxy_request, proxy_item.proxy_resource_url(), resource, proxy_item.content_type()).await;
+        }
+    }
+
+    let request_builder = match build_upstream_request(&proxy_request, Some(&proxy_item), proxy_item.proxy_resource_url()) {
+        Ok(request_builder) => request_builder,
+        Err(err) => {
+            eprintln!("build request failed : {}", err);
+            proxy_request.client.reply_502().await;
+            return true;
+        }
+    };
+
+    let response = with_body(request_builder, body).send().await;
+    relay_upstream_response(&mut proxy_request, proxy_item.proxy_resource_url(), response).await
+}
+
+///
+/// Handle request of proxy item with mirrors
+/// 
+/// # Notes
+/// The request body is buffered because it may be sent to more than one target,
+/// a body too large to buffer is only sent to the first target
+async fn handle_mirror_request(mut proxy_request: LopxyProxyRequest<'_>, proxy_item: ProxyItem) -> bool {
+    let body = match buffer_request_body(&mut proxy_request).await {
+        Ok(Some(body)) => body,
+        Ok(None) => {
+            let target = &proxy_request.order_proxy_targets(&proxy_item)[0];
+            let proxy_item = proxy_item.with_target(target);
+            if get_uri_scheme(&target.url).filter(|scheme: &String| scheme.eq_ignore_ascii_case("file")).is_some() {
+                return handle_local_file_request(proxy_request, proxy_item).await;
+            }
+            if is_uri_scheme(&target.url, "ftp") {
+                let request_url = proxy_request.request_url();
+                return super::ftp::handle_ftp_request(proxy_request, &request_url, &target.url, proxy_item.content_type(), Some(&proxy_item)).await;
+            }
+            return handle_redirect_request(proxy_request, &target.url, Some(proxy_item)).await;
+        },
+        Err(_) => {
+            return false;
+        }
+    };
+
+    request_proxy_targets(proxy_request, proxy_item, &body).await
+}
+
+///
+/// Request the targets of proxy item in order until one of them works
+/// 
+/// # Notes
+/// A target which fails to connect or responds 5xx is reported unhealthy and the next one is tried,
+/// a missing local file is skipped the same way. The last target is always answered to the client
+async fn request_proxy_targets(mut proxy_request: LopxyProxyRequest<'_>, proxy_item: ProxyItem, body: &[u8]) -> bool {
+    let targets = proxy_request.order_proxy_targets(&proxy_item);
+    let last_index = targets.len() - 1;
+
+    for (index, target) in targets.iter().enumerate() {
+        let is_last = index == last_index;
+
+        // local target
+        if let Some(scheme) = get_uri_scheme(&target.url) {
+            if scheme.eq_ignore_ascii_case("file") {
+                let exists = super::super::local::resolve_local_file_path(&target.url).
+                    map(|path: std::path::PathBuf| path.exists()).
+                    unwrap_or(false);
+
+                if !exists && !is_last {
+                    proxy_request.report_target_state(target, false, None, Some("local file not found".to_string()));
+                    continue;
+                }
+
+                return handle_local_file_request(proxy_request, proxy_item.with_target(target)).await;
+            }
+
+            if scheme.eq_ignore_ascii_case("ftp") {
+                let started = std::time::Instant::now();
+                let route = proxy_request.client.upstream_route(Some(&proxy_item), &target.url);
+                let resource = super::ftp::open_ftp_resource(&target.url, !proxy_request.method().eq_ignore_ascii_case("HEAD"), &route).await;
+                let latency = started.elapsed().as_millis() as u64;
+
+                match &resource {
+                    Ok(_) => proxy_request.report_target_state(target, true, Some(latency), None),
+                    Err(err) => {
+                        proxy_request.report_target_state(target, false, None, Some(format!("{}", err)));
+                        if !is_last {
+                            eprintln!("request proxy target {} failed, try the next one : {}", target.url, err);
+                            continue;
+                        }
+                    }
+                }
+
+                return super::ftp::send_ftp_resource(&mut proxy_request, &target.url, resource, proxy_item.content_type()).await;
+            }
+        }
+
+        // remote target
+        let request_builder = match build_upstream_request(&proxy_request, Some(&proxy_item), &target.url) {
+            Ok(request_builder) => request_builder,
+            Err(err) => {
+                eprintln!("build request failed : {}", err);
+                proxy_request.client.reply_502().await;
+                return true;
+            }
+        };
+
+        let started = std::time::Instant::now();
+        let response = with_body(request_builder, body).send().await;
+        let latency = started.elapsed().as_millis() as u64;
+
+        match response {
+            Ok(response) if response.status().is_server_error() => {
+                proxy_request.report_target_state(target, false, None, Some(format!("responds {}", response.status())));
+                if !is_last {
+                    continue;
+                }
+                return relay_upstream_response(&mut proxy_request, &target.url, Ok(response)).await;
+            },
+            Ok(response) => {
+                proxy_request.report_target_state(target, true, Some(latency), None);
+                return relay_upstream_response(&mut proxy_request, &target.url, Ok(response)).await;
+            },
+            Err(err) => {
+                proxy_request.report_target_state(target, false, None, Some(format!("{}", err)));
+                if !is_last {
+                    eprintln!("request proxy target {} failed, try the next one : {}", target.url, err);
+                    continue;
+                }
+                return relay_upstream_response(&mut proxy_request, &target.url, Err(err)).await;
+            }
+        }
+    }
+
+    proxy_request.client.reply_502().await;
+    true
+}
+
+///
+/// Read request body from proxy client and send it to the body channel piece by piece
+/// 
+async fn pump_request_body(client: &mut super::super::ProxyClient, mut body_sender: futures::channel::mpsc::Sender<std::io::Result<Vec<u8>>>) -> Result<(), HttpStreamError> {
+    loop {
+        match client.reader.read_body_chunk(&mut client.stream).await {
+            Ok(Some(chunk)) => {
+                // upstream stops receiving body
+                if body_sender.send(Ok(chunk)).await.is_err() {
+                    return Ok(());
+                }
+            }
+
+            Ok(None) => {
+                return Ok(());
+            }
+
+            Err(err) => {
+                let _ = body_sender.send(Err(std::io::Error::other(err.to_string()))).await;
+                return Err(err);
+            }
+        }
+    }
+}
+
+///
+/// Stream upstream response to proxy client
+/// 
+/// # Notes
+/// Return whether the client connection can be reused
+async fn relay_response(proxy_request: &mut LopxyProxyRequest<'_>, response: reqwest::Response) -> Result<bool, Box<dyn std::error::Error>> {
+    let (raw_response_head, framing) = build_raw_response_head(&response, proxy_request.method(), proxy_request.version());
+    relay_response_rest(proxy_request, response, &raw_response_head, framing, &[]).await
+}
+
+///
+/// Stream the response head, the body received already and the rest of upstream response to proxy client
+/// 
+/// # Notes
+/// Return whether the client connection can be reused
+async fn relay_response_rest(proxy_request: &mut LopxyProxyRequest<'_>, mut response: reqwest::Response, raw_response_head: &[u8], framing: ResponseBodyFraming, received: &[u8]) -> Result<bool, Box<dyn std::error::Error>> {
+    let mut stream = proxy_request.response_stream();
+    stream.write_all(raw_response_head).await?;
+
+    if framing != ResponseBodyFraming::Empty {
+        if !received.is_empty() {
+            match framing {
+                ResponseBodyFraming::Chunked => stream.write_all(&encode_chunk(received)).await?,
+                _ => stream.write_all(received).await?
+            }
+        }
+
+        while let Some(chunk) = response.chunk().await? {
+            if chunk.is_empty() {
+                continue;
+            }
+
+            match framing {
+                ResponseBodyFraming::Chunked => stream.write_all(&encode_chunk(&chunk)).await?,
+                _ => stream.write_all(&chunk).await?
+            }
+        }
+
+        if framing == ResponseBodyFraming::Chunked {
+            stream.write_all(&encode_last_chunk(&[])).await?;
+        }
+    }
+
+    stream.flush().await?;
+
+    Ok(framing != ResponseBodyFraming::UntilClose)
+}
+
+///
+/// Stream upstream response to proxy client, the body is rewritten if any body rule applies
+///
+/// # Notes
+/// Return whether the client connection can be reused
+async fn relay_response_by_rules(proxy_request: &mut LopxyProxyRequest<'_>, request_url: &str, response: reqwest::Response) -> Result<bool, Box<dyn std::error::Error>> {
+    match is_body_rewritable(proxy_request, &response) {
+        true => relay_rewritten_response(proxy_request, request_url, response).await,
+        false => relay_response(proxy_request, response).await
+    }
+}
+
+///
+/// Rewrite the body of a whole raw response by the body rules and report the result,
+/// the response is returned as is if no rule applies
+///
+fn apply_body_rules(proxy_request: &LopxyProxyRequest<'_>, request_url: &str, raw_response_bytes: Vec<u8>) -> Vec<u8> {
+    match body::rewrite_raw_response(&raw_response_bytes, &proxy_request.body_rules) {
+        Ok(Some((rewritten, changes))) => {
+            proxy_request.report_body_rewrite(request_url, format!("{} changes", changes));
+            rewritten
+        },
+        Ok(None) => raw_response_bytes,
+        Err(reason) => {
+            proxy_request.report_body_rewrite(request_url, format!("failed, {}", reason));
+            raw_response_bytes
+        }
+    }
+}
+
+///
+/// Check whether a body rule may apply to the upstream response, so that its body is buffered for rewriting
+///
+fn is_body_rewritable(proxy_request: &LopxyProxyRequest<'_>, response: &reqwest::Response) -> bool {
+    let status = response.status().as_u16();
+    let content_type = response.headers().get(reqwest::header::CONTENT_TYPE).and_then(|value| value.to_str().ok()).unwrap_or("");
+
+    !proxy_request.body_rules.is_empty() &&
+        !proxy_request.method().eq_ignore_ascii_case("HEAD") &&
+        (200..300).contains(&status) && status != 204 && status != 206 &&
+        body::is_rewritable(&proxy_request.body_rules, content_type)
+}
+
+///
+/// Buffer upstream response, rewrite its body by the body rules and send it to proxy client
+///
+/// # Notes
+/// The response is relayed as is if the body is larger than the limit or can't be rewritten,
+/// return whether the client connection can be reused
+async fn relay_rewritten_response(proxy_request: &mut LopxyProxyRequest<'_>, request_url: &str, mut response: reqwest::Response) -> Result<bool, Box<dyn std::error::Error>> {
+    let (raw_response_head, framing) = build_raw_response_head(&response, proxy_request.method(), proxy_request.version());
+
+    if response.content_length().is_some_and(|length: u64| length > body::REWRITE_BODY_LIMIT as u64) {
+        proxy_request.report_body_rewrite(request_url, "skipped, body is too large".to_string());
+        return relay_response_rest(proxy_request, response, &raw_response_head, framing, &[]).await;
+    }
+
+    let mut received: Vec<u8> = vec![];
+    while let Some(chunk) = response.chunk().await? {
+        received.extend_from_slice(&chunk);
+
+        // the rest of body is streamed after the received part
+        if received.len() > body::REWRITE_BODY_LIMIT {
+            proxy_request.report_body_rewrite(request_url, "skipped, body is too large".to_string());
+            return relay_response_rest(proxy_request, response, &raw_response_head, framing, &received).await;
+        }
+    }
+
+    let mut raw_response_bytes = raw_response_head.clone();
+    raw_response_bytes.extend_from_slice(&received);
+
+    match body::rewrite_raw_response(&raw_response_bytes, &proxy_request.body_rules) {
+        Ok(Some((rewritten, changes))) => {
+            proxy_request.report_body_rewrite(request_url, format!("{} changes", changes));
+
+            let mut stream = proxy_request.response_stream();
+            stream.write_all(&rewritten).await?;
+            stream.flush().await?;
+
+            Ok(true)
+        },
+        Ok(None) => relay_response_rest(proxy_request, response, &raw_response_head, framing, &received).await,
+        Err(reason) => {
+            proxy_request.report_body_rewrite(request_url, format!("failed, {}", reason));
+            relay_response_rest(proxy_request, response, &raw_response_head, framing, &received).await
+        }
+    }
+}
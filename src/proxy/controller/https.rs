@@ -1,21 +1,83 @@
 use async_std::io::WriteExt;
-use async_std::net::TcpStream;
-
-use futures::FutureExt;
 
 use super::super::request::LopxyProxyRequest;
+use super::super::upstream;
+
+///
+/// Split host and port of CONNECT request, the port is 443 if absent
+///
+fn split_host_port(host: &str) -> (String, u16) {
+    let port_pos = match host.rfind(']') {
+        Some(pos) => host[pos..].rfind(':').map(|p| p + pos),
+        None => host.rfind(':')
+    };
+
+    match port_pos {
+        Some(pos) => (host[..pos].to_string(), host[pos + 1..].parse::<u16>().unwrap_or(443)),
+        None => (host.to_string(), 443)
+    }
+}
 
 ///
 /// Handle https proxy request
-/// 
+///
 /// # Notes
-/// The local proxy adapter function is not implemented temporarily, forward directly at present
-/// 
-pub async fn handle_proxy_request(mut proxy_request: LopxyProxyRequest<'_>) {
+/// Hosts which may be matched by proxy items are intercepted when https interception is enabled,
+/// the others are tunneled directly. Return whether the client connection should be kept, an
+/// intercepted connection goes on with the decrypted requests
+pub async fn handle_proxy_request(proxy_request: LopxyProxyRequest<'_>) -> bool {
+    // CONNECT inside an intercepted or socks5 connection
+    if proxy_request.client.tunnel_host.is_some() {
+        proxy_request.client.reply_400().await;
+        return false;
+    }
+
+    let (host, port) = split_host_port(&proxy_request.host);
+
+    // bytes sent right after the CONNECT request can't be fed to the TLS acceptor
+    let server_config = match proxy_request.client.reader.buffered().is_empty() {
+        true => proxy_request.client.https_interception(&host, port),
+        false => None
+    };
+
+    match server_config {
+        Some(server_config) => handle_intercepted_request(proxy_request, server_config).await,
+        None => {
+            handle_tunnel_request(proxy_request).await;
+            false
+        }
+    }
+}
+
+///
+/// Decrypt the https connection with a leaf certificate minted by lopxy CA
+///
+async fn handle_intercepted_request(proxy_request: LopxyProxyRequest<'_>, server_config: std::sync::Arc<rustls::ServerConfig>) -> bool {
+    // response connection established
+    if let Err(err) = proxy_request.client.stream.write_all(b"HTTP/1.1 200 Connection Established\r\n\r\n").await {
+        eprintln!("reply https connection established failed : {}", err);
+        return false;
+    }
+
+    if let Err(err) = proxy_request.client.stream.accept_tls(server_config).await {
+        eprintln!("https interception handshake failed : {}", err);
+        proxy_request.report_connection_error(&proxy_request.host, proxy_request.try_request_url(), &err);
+        return false;
+    }
+
+    proxy_request.client.tunnel_host = Some(proxy_request.host.clone());
+    true
+}
+
+///
+/// Tunnel the https connection to remote host, through the upstream proxy if any
+///
+async fn handle_tunnel_request(proxy_request: LopxyProxyRequest<'_>) {
+    let route = proxy_request.client.upstream_route(None, &format!("https://{}", proxy_request.host));
     let client_stream = &mut proxy_request.client.stream;
 
     // connect remote host
-    let server_stream = match TcpStream::connect(&proxy_request.host).await {
+    let server_stream = match upstream::connect(&route, &proxy_request.host).await {
         Ok(stream) => stream,
         Err(err) => {
             eprintln!("connect remote https server failed : {}", err);
@@ -32,27 +94,16 @@ pub async fn handle_proxy_request(mut proxy_request: LopxyProxyRequest<'_>) {
             return;
         }
     }
-   
-    //
-    // tunnel
-    //
-
-    let (client_receiver, client_sender) = &mut (&proxy_request.client.stream, &proxy_request.client.stream);
-    let (server_receiver, server_sender) = &mut (&server_stream, &server_stream);
- 
-    let cf1 = async_std::io::copy(client_receiver, server_sender);
-    let cf2 = async_std::io::copy(server_receiver, client_sender);
-
-    let waits = move || async move {
-        futures::select! {
-           r1 = cf1.fuse() => r1,
-           r2 = cf2.fuse() => r2,
-        }
-    };
 
-    match proxy_request.client.shutdown.wrap_cancel(waits()).await {
-        Some(Ok(_)) => {},
-        Some(Err(err)) => eprintln!("https tunnel failed : {}", err),
-        None => eprintln!("proxy server shutdown triggered, closing connection"),
+    // bytes sent by client right after the CONNECT request
+    let client_buffered = proxy_request.client.reader.take_buffered();
+    if !client_buffered.is_empty() {
+        if let Err(err) = (&server_stream).write_all(&client_buffered).await {
+            eprintln!("https tunnel failed : {}", err);
+            return;
+        }
     }
-}
\ No newline at end of file
+
+    // tunnel
+    super::tunnel_transmit(proxy_request.client, &server_stream, "https tunnel").await;
+}
@@ -1,9 +1,15 @@
+pub mod ftp;
 pub mod http;
 pub mod https;
+pub mod sock;
 
 use std::sync::Arc;
 use std::sync::Mutex;
 
+use async_std::net::TcpStream;
+use async_std::io::WriteExt;
+use futures::FutureExt;
+
 use super::ProxyClient;
 use super::item::*;
 
@@ -12,75 +18,140 @@ use super::super::util::netstat;
 pub type LopxyProxyServerControllerArc = Arc<Mutex<dyn LopxyProxyServerController + Send>>;
 
 pub trait LopxyProxyServerController {
-    fn proxy_redirect(&mut self, resource_url: &str) -> Option<ProxyItem>;
+    fn proxy_redirect(&mut self, resource_url: &str, username: Option<&str>) -> Option<ProxyItem>;
+
+    fn report_proxy_request_status(&mut self, request_url: &str, status: u16, pid: u32, username: Option<&str>);
+    fn report_connection_error(&mut self, host: &str, request_url: Option<String>, err: &dyn std::error::Error, pid: u32, username: Option<&str>);
+    fn report_fallback(&mut self, request_url: &str, reason: Option<String>, pid: u32, username: Option<&str>);
+    fn report_network_condition(&mut self, request_url: &str, action: String, pid: u32, username: Option<&str>);
+    fn report_body_rewrite(&mut self, request_url: &str, result: String, pid: u32, username: Option<&str>);
+    fn report_cache(&mut self, request_url: &str, event: String, pid: u32, username: Option<&str>);
+
+    fn mirrored_proxy_items(&mut self) -> Vec<ProxyItem>;
+    fn order_proxy_targets(&mut self, item: &ProxyItem) -> Vec<ProxyTarget>;
+    fn report_target_state(&mut self, target: &str, healthy: bool, latency: Option<u64>, error: Option<String>);
+    fn retain_target_states(&mut self, targets: &std::collections::HashSet<String>);
+
+    fn https_interception(&mut self, host: &str, port: u16) -> Option<Arc<rustls::ServerConfig>>;
+    fn may_handle_host(&mut self, host: &str, port: u16) -> bool;
+    fn socks_credential(&mut self) -> Option<(String, String)>;
+    fn proxy_auth_required(&mut self) -> bool;
+    fn authenticate_proxy_user(&mut self, username: &str, password: &str) -> bool;
+    fn is_client_allowed(&mut self, address: &std::net::IpAddr) -> bool;
 
-    fn report_proxy_request_status(&mut self, request_url: &str, status: u16, pid: u32);
-    fn report_connection_error(&mut self, host: &str, request_url: Option<String>, err: &dyn std::error::Error, pid: u32);
+    fn header_rules(&mut self, url: &str, item: Option<&ProxyItem>) -> super::header::HeaderRules;
+    fn upstream_route(&mut self, item: Option<&ProxyItem>, url: &str) -> Option<super::upstream::UpstreamRoute>;
+    fn response_cache(&mut self, url: &str) -> Option<Arc<super::cache::ResponseCache>>;
 }
 
+///
+/// Handle lopxy proxy client connection
+/// 
+/// # Notes
+/// Every request on a persistent(Keep-Alive) connection is parsed and dispatched in turn,
+/// until the client closes the connection or a request asks to close it
 pub async fn handle_lopxy_proxy_client(mut client: ProxyClient) {
-    // collect request raw buffer
-    let request_buffer = match super::stream::collect_tcp_stream_buffer(&mut client.stream).await {
-        Ok(buf) => buf,
-        Err(err) => {
-            eprintln!("collect proxy client request stream failed : {}", err);
-            return;
+    // client port to process id
+    let client_port = client.addr.port();
+    let pid = client_pid(client_port);
+
+    handle_lopxy_proxy_client_requests(&mut client, client_port, pid).await;
+}
+
+///
+/// Get process id of proxy client by its port, 0 if not found
+///
+fn client_pid(client_port: u16) -> u32 {
+    match netstat::tcp_port_to_pid(client_port) {
+        Some(pid) => pid, 
+        None => {
+            eprintln!("get proxy request client pid failed");
+            0
         }
-    };
+    }
+}
 
-    // parse request
-    let mut headers = [httparse::EMPTY_HEADER; 64];
-    let mut req = httparse::Request::new(&mut headers);
-    match req.parse(&request_buffer[..]) {
-        Ok(status) => {
-            if status.is_partial() {
-                eprintln!("the request is partial");
-                // return;
-            }
+///
+/// Handle requests of lopxy proxy client connection until it should be closed
+///
+async fn handle_lopxy_proxy_client_requests(client: &mut ProxyClient, client_port: u16, pid: u32) {
+    loop {
+        if !handle_lopxy_proxy_client_request(client, client_port, pid).await {
+            break;
         }
-        Err(err) => {
-            eprintln!("parse proxy request failed : {}", err);
-            return;
+
+        // the response written to an intercepted connection may still be buffered in the TLS session
+        if client.stream.is_tls() && client.stream.flush().await.is_err() {
+            break;
         }
     }
+}
 
-    // get host from request
-    let host = match super::request::get_host_from_request(&mut req) {
-        Some(h) => h,
-        None => {
-            eprintln!("get proxy request host failed");
-            return;
+///
+/// Handle one request of lopxy proxy client connection
+/// 
+/// # Notes
+/// Return whether the connection should be kept for the next request
+async fn handle_lopxy_proxy_client_request(client: &mut ProxyClient, client_port: u16, pid: u32) -> bool {
+    // read and parse request head
+    let mut request_header = match client.reader.read_request_head(&mut client.stream).await {
+        Ok(Some(head)) => head,
+        Ok(None) => {
+            // connection closed by client
+            return false;
+        }
+        Err(err) => {
+            eprintln!("parse proxy request failed : {}", err);
+            match err.status() {
+                Some(431) => client.reply_431().await,
+                Some(_) => client.reply_400().await,
+                None => {}
+            }
+            return false;
         }
     };
 
-    // get request method
-    let method = match req.method {
-        Some(method) => method,
-        None => {
-            eprintln!("get proxy request method failed");
-            return;
+    // proxy authentication, requests inside a tunnel belong to the user who opened it
+    if client.tunnel_host.is_none() {
+        if !client.authenticate(&request_header) {
+            client.reply_407().await;
+            return false;
         }
-    };
 
-    // client port to process id
-    let client_port = client.addr.port();
-    let pid = match netstat::tcp_port_to_pid(client_port) {
-        Some(pid) => pid, 
-        None => {
-            eprintln!("get proxy request client pid failed");
-            0
+        request_header.remove_header("proxy-authorization");
+    }
+
+    // request of an intercepted https connection or a socks5 connection is in origin form
+    if let Some(tunnel_host) = &client.tunnel_host {
+        if request_header.path.starts_with('/') {
+            request_header.path = super::request::get_origin_form_request_url(client.stream.is_tls(), tunnel_host, &request_header);
+        }
+    }
+
+    // get host from request
+    let host = match &client.tunnel_host {
+        Some(tunnel_host) => tunnel_host.clone(),
+        None => match super::request::get_host_from_request(&request_header) {
+            Some(h) => h,
+            None => {
+                eprintln!("get proxy request host failed");
+                client.reply_400().await;
+                return false;
+            }
         }
     };
 
     // build lopxy proxy request parameter
     let proxy_request = super::request::LopxyProxyRequest {
-        https: method.eq("CONNECT"),
+        https: request_header.method.eq("CONNECT"),
         host: host,
-        method: method.to_string(),
+        method: request_header.method.clone(),
         client_port: client_port,
         pid,
-        request_bytes: &request_buffer,
-        request_header: req,
+        request_header,
+        bandwidth: 0,
+        header_rules: super::header::HeaderRules::default(),
+        body_rules: vec![],
         client: client
     };
 
@@ -89,4 +160,30 @@ pub async fn handle_lopxy_proxy_client(mut client: ProxyClient) {
         false => self::http::handle_proxy_request(proxy_request).await,
         true => self::https::handle_proxy_request(proxy_request).await
     }
-}
\ No newline at end of file
+}
+
+///
+/// Tunnel bytes between proxy client and remote server until one side closes
+/// 
+/// # Notes
+/// The raw tcp stream of client is used, so it must not be called on an intercepted connection
+pub async fn tunnel_transmit(client: &ProxyClient, server_stream: &TcpStream, desc: &str) {
+    let (client_receiver, client_sender) = &mut (client.stream.tcp(), client.stream.tcp());
+    let (server_receiver, server_sender) = &mut (server_stream, server_stream);
+ 
+    let cf1 = async_std::io::copy(client_receiver, server_sender);
+    let cf2 = async_std::io::copy(server_receiver, client_sender);
+
+    let waits = move || async move {
+        futures::select! {
+           r1 = cf1.fuse() => r1,
+           r2 = cf2.fuse() => r2,
+        }
+    };
+
+    match client.shutdown.wrap_cancel(waits()).await {
+        Some(Ok(_)) => {},
+        Some(Err(err)) => eprintln!("{} failed : {}", desc, err),
+        None => eprintln!("proxy server shutdown triggered, closing connection"),
+    }
+}
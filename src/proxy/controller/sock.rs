@@ -0,0 +1,316 @@
+use async_std::io::{Read, ReadExt, Write, WriteExt};
+use async_std::net::TcpStream;
+
+use super::super::ProxyClient;
+use super::LopxyProxyServerControllerArc;
+use super::super::stream::sniff_http_request;
+use super::super::upstream;
+
+const SOCKS_VERSION: u8 = 0x05;
+const SOCKS_AUTH_VERSION: u8 = 0x01;
+
+const METHOD_NO_AUTH: u8 = 0x00;
+const METHOD_USERNAME_PASSWORD: u8 = 0x02;
+const METHOD_NOT_ACCEPTABLE: u8 = 0xff;
+
+const COMMAND_CONNECT: u8 = 0x01;
+
+const ADDRESS_IPV4: u8 = 0x01;
+const ADDRESS_DOMAIN: u8 = 0x03;
+const ADDRESS_IPV6: u8 = 0x04;
+
+const REPLY_SUCCEEDED: u8 = 0x00;
+const REPLY_HOST_UNREACHABLE: u8 = 0x04;
+const REPLY_COMMAND_NOT_SUPPORTED: u8 = 0x07;
+const REPLY_ADDRESS_TYPE_NOT_SUPPORTED: u8 = 0x08;
+
+/// How long to wait for the first bytes of socks5 client to tell whether it speaks http
+const HTTP_SNIFF_TIMEOUT: std::time::Duration = std::time::Duration::from_millis(500);
+
+fn socks_error(desc: &str) -> std::io::Error {
+    std::io::Error::new(std::io::ErrorKind::InvalidData, desc)
+}
+
+///
+/// How socks5 clients authenticate
+///
+enum SocksAuth {
+    /// No authentication
+    Anonymous,
+    /// The socks credential of lopxy config
+    Credential(String, String),
+    /// The proxy users of lopxy config, the requests of the tunnel belong to the user
+    ProxyUsers(LopxyProxyServerControllerArc),
+}
+
+impl SocksAuth {
+    ///
+    /// Check the username and password, return the proxy user if accepted
+    ///
+    fn verify(&self, username: &str, password: &str) -> Option<Option<String>> {
+        match self {
+            SocksAuth::Anonymous => Some(None),
+            SocksAuth::Credential(expected_username, expected_password) => {
+                // the password is compared in constant time
+                let expected = expected_password.as_bytes();
+                let provided = password.as_bytes();
+                let password_matches = expected.len() == provided.len() &&
+                    expected.iter().zip(provided.iter()).fold(0u8, |diff: u8, (a, b): (&u8, &u8)| diff | (a ^ b)) == 0;
+
+                match username == expected_username && password_matches {
+                    true => Some(None),
+                    false => None
+                }
+            },
+            SocksAuth::ProxyUsers(controller) => {
+                match controller.lock().unwrap().authenticate_proxy_user(username, password) {
+                    true => Some(Some(username.to_string())),
+                    false => None
+                }
+            }
+        }
+    }
+}
+
+///
+/// Handle lopxy socks5 proxy client connection
+///
+/// # Notes
+/// Only CONNECT command is supported. The tunneled connection which speaks plain http is handled
+/// like a http proxy connection, so its requests are matched by proxy items, the others are
+/// tunneled to the target host directly.
+///
+/// The target is connected before replying unless its requests may be handled by proxy items,
+/// host header rules or offline cache, an unreachable target is replied with host unreachable.
+/// Such a target is connected lazily since redirected requests never reach it, so the failure of
+/// a non-http tunnel to it can only be reported by closing the connection
+pub async fn handle_socks_proxy_client(mut client: ProxyClient) {
+    // client port to process id
+    let client_port = client.addr.port();
+    let pid = super::client_pid(client_port);
+
+    // proxy users take the place of socks credential, so that the proxy can't be used without them
+    let auth = {
+        let mut controller = client.controller.lock().unwrap();
+        match (controller.proxy_auth_required(), controller.socks_credential()) {
+            (true, _) => SocksAuth::ProxyUsers(client.controller.clone()),
+            (false, Some((username, password))) => SocksAuth::Credential(username, password),
+            (false, None) => SocksAuth::Anonymous
+        }
+    };
+
+    let target_host = match socks_handshake(&mut client.stream, &auth).await {
+        Ok(Some((host, username))) => {
+            client.username = username;
+            host
+        },
+        Ok(None) => {
+            return;
+        }
+        Err(err) => {
+            eprintln!("socks5 handshake failed : {}", err);
+            return;
+        }
+    };
+
+    let may_handle = match target_host.rsplit_once(':') {
+        Some((host, port)) => client.controller.lock().unwrap().may_handle_host(host, port.parse::<u16>().unwrap_or(0)),
+        None => false
+    };
+
+    // requests redirected by proxy items never reach the target, connect it lazily in that case
+    let mut server_stream = None;
+    if !may_handle {
+        match connect_target(&client, &target_host, pid).await {
+            Some(stream) => server_stream = Some(stream),
+            None => {
+                if let Err(err) = reply(&mut client.stream, REPLY_HOST_UNREACHABLE).await {
+                    eprintln!("reply socks5 host unreachable failed : {}", err);
+                }
+                return;
+            }
+        }
+    }
+
+    if let Err(err) = reply(&mut client.stream, REPLY_SUCCEEDED).await {
+        eprintln!("reply socks5 connection established failed : {}", err);
+        return;
+    }
+
+    let is_http = loop {
+        if let Some(is_http) = sniff_http_request(client.reader.buffered()) {
+            break is_http;
+        }
+
+        match async_std::future::timeout(HTTP_SNIFF_TIMEOUT, client.reader.fill(&mut client.stream)).await {
+            Ok(Ok(0)) => {
+                return;
+            }
+            Ok(Ok(_)) => {}
+            Ok(Err(err)) => {
+                eprintln!("read socks5 client failed : {}", err);
+                return;
+            }
+            // the client waits for the server to speak first
+            Err(_) => break false
+        }
+    };
+
+    if is_http {
+        client.tunnel_host = Some(target_host);
+        super::handle_lopxy_proxy_client_requests(&mut client, client_port, pid).await;
+        return;
+    }
+
+    handle_tunnel_request(client, &target_host, server_stream, pid).await;
+}
+
+///
+/// Negotiate the authentication method and read the CONNECT request of socks5 client
+///
+/// # Notes
+/// Return the target `host:port` and the proxy user, None if the request is rejected and the connection should be closed
+async fn socks_handshake<S: Read + Write + Unpin>(stream: &mut S, auth: &SocksAuth) -> std::io::Result<Option<(String, Option<String>)>> {
+    // greeting
+    let mut greeting = [0u8; 2];
+    stream.read_exact(&mut greeting).await?;
+    if greeting[0] != SOCKS_VERSION {
+        return Err(socks_error("unsupported socks version"));
+    }
+
+    let mut methods = vec![0u8; greeting[1] as usize];
+    stream.read_exact(&mut methods).await?;
+
+    let method = match auth {
+        SocksAuth::Anonymous => METHOD_NO_AUTH,
+        _ => METHOD_USERNAME_PASSWORD
+    };
+    if !methods.contains(&method) {
+        stream.write_all(&[SOCKS_VERSION, METHOD_NOT_ACCEPTABLE]).await?;
+        return Ok(None);
+    }
+    stream.write_all(&[SOCKS_VERSION, method]).await?;
+
+    // username/password authentication
+    let mut username = None;
+    if method == METHOD_USERNAME_PASSWORD {
+        let mut auth_head = [0u8; 2];
+        stream.read_exact(&mut auth_head).await?;
+        let mut request_username = vec![0u8; auth_head[1] as usize];
+        stream.read_exact(&mut request_username).await?;
+
+        let mut password_length = [0u8; 1];
+        stream.read_exact(&mut password_length).await?;
+        let mut request_password = vec![0u8; password_length[0] as usize];
+        stream.read_exact(&mut request_password).await?;
+
+        let request_username = String::from_utf8_lossy(&request_username);
+        let accepted = match auth_head[0] == SOCKS_AUTH_VERSION {
+            true => auth.verify(&request_username, &String::from_utf8_lossy(&request_password)),
+            false => None
+        };
+
+        stream.write_all(&[SOCKS_AUTH_VERSION, if accepted.is_some() { 0x00 } else { 0x01 }]).await?;
+        match accepted {
+            Some(user) => username = user,
+            None => {
+                eprintln!("socks5 authentication failed with user {}", request_username);
+                return Ok(None);
+            }
+        }
+    }
+
+    // request
+    let mut request = [0u8; 4];
+    stream.read_exact(&mut request).await?;
+    if request[0] != SOCKS_VERSION {
+        return Err(socks_error("unsupported socks version"));
+    }
+
+    let host = match request[3] {
+        ADDRESS_IPV4 => {
+            let mut address = [0u8; 4];
+            stream.read_exact(&mut address).await?;
+            std::net::Ipv4Addr::from(address).to_string()
+        },
+        ADDRESS_DOMAIN => {
+            let mut domain_length = [0u8; 1];
+            stream.read_exact(&mut domain_length).await?;
+            let mut domain = vec![0u8; domain_length[0] as usize];
+            stream.read_exact(&mut domain).await?;
+            String::from_utf8(domain).map_err(|_| socks_error("invalid socks5 domain"))?
+        },
+        ADDRESS_IPV6 => {
+            let mut address = [0u8; 16];
+            stream.read_exact(&mut address).await?;
+            format!("[{}]", std::net::Ipv6Addr::from(address))
+        },
+        _ => {
+            reply(stream, REPLY_ADDRESS_TYPE_NOT_SUPPORTED).await?;
+            return Ok(None);
+        }
+    };
+
+    let mut port = [0u8; 2];
+    stream.read_exact(&mut port).await?;
+
+    if request[1] != COMMAND_CONNECT {
+        reply(stream, REPLY_COMMAND_NOT_SUPPORTED).await?;
+        return Ok(None);
+    }
+
+    Ok(Some((format!("{}:{}", host, u16::from_be_bytes(port)), username)))
+}
+
+///
+/// Reply socks5 request, the bound address is always reported as unspecified
+///
+async fn reply<S: Write + Unpin>(stream: &mut S, reply_code: u8) -> std::io::Result<()> {
+    stream.write_all(&[SOCKS_VERSION, reply_code, 0x00, ADDRESS_IPV4, 0, 0, 0, 0, 0, 0]).await
+}
+
+///
+/// Connect socks5 target host, through the upstream proxy if any
+///
+/// # Notes
+/// The protocol of tunnel is unknown, it's routed like a https tunnel. The failure is reported
+/// and None is returned
+async fn connect_target(client: &ProxyClient, target_host: &str, pid: u32) -> Option<TcpStream> {
+    let route = client.upstream_route(None, &format!("https://{}", target_host));
+
+    match upstream::connect(&route, target_host).await {
+        Ok(stream) => Some(stream),
+        Err(err) => {
+            eprintln!("connect socks5 target failed : {}", err);
+            client.controller.lock().unwrap().report_connection_error(target_host, None, &err, pid, client.username.as_deref());
+            None
+        }
+    }
+}
+
+///
+/// Tunnel the socks5 connection to target host, the target is connected now if it was not
+///
+async fn handle_tunnel_request(mut client: ProxyClient, target_host: &str, server_stream: Option<TcpStream>, pid: u32) {
+    let server_stream = match server_stream {
+        Some(stream) => stream,
+        None => match connect_target(&client, target_host, pid).await {
+            Some(stream) => stream,
+            None => {
+                return;
+            }
+        }
+    };
+
+    // bytes read while sniffing the protocol
+    let client_buffered = client.reader.take_buffered();
+    if !client_buffered.is_empty() {
+        if let Err(err) = (&server_stream).write_all(&client_buffered).await {
+            eprintln!("socks5 tunnel failed : {}", err);
+            return;
+        }
+    }
+
+    // tunnel
+    super::tunnel_transmit(&client, &server_stream, "socks5 tunnel").await;
+}
@@ -0,0 +1,357 @@
+use std::pin::Pin;
+use std::task::{Context, Poll};
+
+use async_std::io::Write;
+use serde_derive::{Serialize, Deserialize};
+
+use super::bypass::BypassList;
+
+///
+/// How a header rule changes the headers named by it
+///
+#[derive(Debug, Clone)]
+pub enum HeaderAction {
+    /// Append a header even if there are headers of the same name
+    Add(String),
+    /// Replace all headers of the same name with one header
+    Set(String),
+    /// Remove all headers of the same name
+    Remove,
+    /// Replace the matches of regex in the values with the replacement, `$1` refers to the captured groups
+    Replace(regex::Regex, String),
+}
+
+///
+/// Rule which rewrites request or response headers, written as text in config :
+///
+/// `add Name: value`, `set Name: value`, `remove Name` or `replace Name: regex => replacement`
+///
+#[derive(Debug, Clone)]
+pub struct HeaderRule {
+    pub name: String,
+    pub action: HeaderAction,
+}
+
+impl HeaderRule {
+    ///
+    /// Parse a header rule, None if it's invalid
+    ///
+    /// # Notes
+    /// Framing headers like `content-length` are maintained by lopxy, the rules of them are invalid
+    pub fn parse(rule: &str) -> Option<HeaderRule> {
+        let (action, rest) = rule.trim().split_once(char::is_whitespace)?;
+        let rest = rest.trim();
+
+        let (name, value) = match action.to_lowercase().as_str() {
+            "remove" => (rest.trim_end_matches(':').trim(), None),
+            _ => {
+                let (name, value) = rest.split_once(':')?;
+                (name.trim(), Some(value.trim()))
+            }
+        };
+
+        if name.is_empty() || reqwest::header::HeaderName::from_bytes(name.as_bytes()).is_err() || is_framing_header(name) {
+            return None;
+        }
+
+        let action = match (action.to_lowercase().as_str(), value) {
+            ("add", Some(value)) => HeaderAction::Add(value.to_string()),
+            ("set", Some(value)) => HeaderAction::Set(value.to_string()),
+            ("remove", None) => HeaderAction::Remove,
+            ("replace", Some(value)) => {
+                let (pattern, replacement) = value.split_once("=>")?;
+                HeaderAction::Replace(regex::Regex::new(pattern.trim()).ok()?, replacement.trim().to_string())
+            },
+            _ => {
+                return None;
+            }
+        };
+
+        if let HeaderAction::Add(value) | HeaderAction::Set(value) = &action {
+            reqwest::header::HeaderValue::from_str(value).ok()?;
+        }
+
+        Some(HeaderRule {
+            name: name.to_string(),
+            action
+        })
+    }
+
+    ///
+    /// Parse all header rules, None if any of them is invalid
+    ///
+    pub fn parse_all<S: AsRef<str>>(rules: &[S]) -> Option<Vec<HeaderRule>> {
+        rules.iter().map(|rule: &S| HeaderRule::parse(rule.as_ref())).collect()
+    }
+
+    ///
+    /// Apply the rule to headers in order
+    ///
+    pub fn apply(&self, headers: &mut Vec<(String, String)>) {
+        let named = |header_name: &String| header_name.eq_ignore_ascii_case(&self.name);
+
+        match &self.action {
+            HeaderAction::Add(value) => headers.push((self.name.clone(), value.clone())),
+            HeaderAction::Set(value) => {
+                headers.retain(|(name, _): &(String, String)| !named(name));
+                headers.push((self.name.clone(), value.clone()));
+            },
+            HeaderAction::Remove => headers.retain(|(name, _): &(String, String)| !named(name)),
+            HeaderAction::Replace(pattern, replacement) => {
+                headers.iter_mut().
+                    filter(|(name, _): &&mut (String, String)| named(name)).
+                    for_each(|(_, value): &mut (String, String)| *value = pattern.replace_all(value, replacement.as_str()).to_string());
+            }
+        }
+    }
+}
+
+///
+/// Check whether the header is maintained by lopxy, rules can't change it
+///
+pub fn is_framing_header(name: &str) -> bool {
+    ["host", "content-length", "transfer-encoding", "connection", "proxy-connection", "keep-alive"].iter().
+        any(|framing: &&str| framing.eq_ignore_ascii_case(name))
+}
+
+///
+/// Header rules applied to a request, the rules of matching hosts go before the ones of proxy item
+///
+#[derive(Debug, Clone, Default)]
+pub struct HeaderRules {
+    pub request: Vec<HeaderRule>,
+    pub response: Vec<HeaderRule>,
+}
+
+impl HeaderRules {
+    pub fn is_empty(&self) -> bool {
+        self.request.is_empty() && self.response.is_empty()
+    }
+
+    ///
+    /// Check whether a request rule names the header, so that the header of proxy client is kept
+    ///
+    pub fn names_request_header(&self, name: &str) -> bool {
+        self.request.iter().any(|rule: &HeaderRule| rule.name.eq_ignore_ascii_case(name))
+    }
+
+    pub fn apply_request(&self, headers: &mut Vec<(String, String)>) {
+        self.request.iter().for_each(|rule: &HeaderRule| rule.apply(headers));
+    }
+}
+
+///
+/// Header rules of the hosts in config, hosts are written like bypass entries, e.g. `*.example.com`
+///
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
+pub struct HostHeaderRules {
+    pub hosts: Vec<String>,
+    #[serde(default)]
+    pub request_headers: Vec<String>,
+    #[serde(default)]
+    pub response_headers: Vec<String>,
+}
+
+impl HostHeaderRules {
+    pub fn verify(&self) -> bool {
+        !BypassList::from_entries(&self.hosts).is_empty() &&
+            HeaderRule::parse_all(&self.request_headers).is_some() &&
+            HeaderRule::parse_all(&self.response_headers).is_some()
+    }
+
+    pub fn matches(&self, host: &str, port: Option<u16>) -> bool {
+        BypassList::from_entries(&self.hosts).matches(host, port)
+    }
+
+    pub fn matches_url(&self, url: &str) -> bool {
+        BypassList::from_entries(&self.hosts).matches_url(url)
+    }
+}
+
+///
+/// Rewrite the headers of raw response head by the rules, the head is returned as is if it can't be parsed
+///
+pub fn rewrite_response_head(raw_response_head: &[u8], rules: &[HeaderRule]) -> Vec<u8> {
+    if rules.is_empty() {
+        return raw_response_head.to_vec();
+    }
+
+    let head = String::from_utf8_lossy(raw_response_head);
+    let mut lines = head.trim_end_matches("\r\n").split("\r\n");
+    let status_line = match lines.next() {
+        Some(status_line) => status_line,
+        None => {
+            return raw_response_head.to_vec();
+        }
+    };
+
+    let mut headers: Vec<(String, String)> = vec![];
+    for line in lines {
+        match line.split_once(':') {
+            Some((name, value)) => headers.push((name.trim().to_string(), value.trim().to_string())),
+            None => {
+                return raw_response_head.to_vec();
+            }
+        }
+    }
+
+    rules.iter().for_each(|rule: &HeaderRule| rule.apply(&mut headers));
+
+    let mut rewritten = format!("{}\r\n", status_line);
+    for (name, value) in headers.iter() {
+        rewritten.push_str(&format!("{}: {}\r\n", name, value));
+    }
+    rewritten.push_str("\r\n");
+
+    rewritten.into_bytes()
+}
+
+///
+/// Stream which rewrites the headers of the first response head written to it, the rest is written through
+///
+/// # Notes
+/// The head is held until the empty line is written, flush the stream after the response is written
+pub struct HeaderRewriteStream<'a, W> {
+    inner: W,
+    rules: &'a [HeaderRule],
+    head: Vec<u8>,
+    rewritten: Option<(Vec<u8>, usize)>,
+    passthrough: bool,
+}
+
+impl<'a, W: Write + Unpin> HeaderRewriteStream<'a, W> {
+    pub fn new(inner: W, rules: &'a [HeaderRule]) -> HeaderRewriteStream<'a, W> {
+        HeaderRewriteStream {
+            inner,
+            rules,
+            head: vec![],
+            rewritten: None,
+            passthrough: rules.is_empty()
+        }
+    }
+
+    ///
+    /// Write the rewritten head to the inner stream
+    ///
+    fn poll_write_head(&mut self, cx: &mut Context<'_>) -> Poll<std::io::Result<()>> {
+        while let Some((head, written)) = self.rewritten.as_mut() {
+            if *written >= head.len() {
+                self.rewritten = None;
+                self.passthrough = true;
+                break;
+            }
+
+            match Pin::new(&mut self.inner).poll_write(cx, &head[*written..]) {
+                Poll::Ready(Ok(0)) => {
+                    return Poll::Ready(Err(std::io::Error::from(std::io::ErrorKind::WriteZero)));
+                },
+                Poll::Ready(Ok(size)) => *written += size,
+                Poll::Ready(Err(err)) => {
+                    return Poll::Ready(Err(err));
+                },
+                Poll::Pending => {
+                    return Poll::Pending;
+                }
+            }
+        }
+
+        Poll::Ready(Ok(()))
+    }
+}
+
+impl<W: Write + Unpin> Write for HeaderRewriteStream<'_, W> {
+    fn poll_write(self: Pin<&mut Self>, cx: &mut Context<'_>, buf: &[u8]) -> Poll<std::io::Result<usize>> {
+        let this = self.get_mut();
+
+        match this.poll_write_head(cx) {
+            Poll::Ready(Ok(())) => {},
+            Poll::Ready(Err(err)) => {
+                return Poll::Ready(Err(err));
+            },
+            Poll::Pending => {
+                return Poll::Pending;
+            }
+        }
+
+        if this.passthrough {
+            return Pin::new(&mut this.inner).poll_write(cx, buf);
+        }
+
+        // collect the head until the empty line, the bytes after it are left for the next write
+        let searched = this.head.len().saturating_sub(3);
+        this.head.extend_from_slice(buf);
+
+        let end = match this.head[searched..].windows(4).position(|window: &[u8]| window == b"\r\n\r\n") {
+            Some(pos) => searched + pos + 4,
+            None => {
+                return Poll::Ready(Ok(buf.len()));
+            }
+        };
+
+        let consumed = buf.len() - (this.head.len() - end);
+        this.head.truncate(end);
+        this.rewritten = Some((rewrite_response_head(&this.head, this.rules), 0));
+
+        Poll::Ready(Ok(consumed))
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<std::io::Result<()>> {
+        let this = self.get_mut();
+
+        match this.poll_write_head(cx) {
+            Poll::Ready(Ok(())) => Pin::new(&mut this.inner).poll_flush(cx),
+            other => other
+        }
+    }
+
+    fn poll_close(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<std::io::Result<()>> {
+        let this = self.get_mut();
+
+        match this.poll_write_head(cx) {
+            Poll::Ready(Ok(())) => Pin::new(&mut this.inner).poll_close(cx),
+            other => other
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn apply_header_rules() {
+        let rules = HeaderRule::parse_all(&[
+            "add Access-Control-Allow-Origin: *",
+            "set Cache-Control: no-store",
+            "remove Set-Cookie",
+            "replace Location: ^http://old\\.com/(.*) => https://new.com/$1",
+        ]).unwrap();
+
+        let raw_response_head = b"HTTP/1.1 302 Found\r\ncache-control: max-age=3600\r\nset-cookie: a=1\r\nset-cookie: b=2\r\nlocation: http://old.com/x\r\n\r\n";
+        assert_eq!(
+            String::from_utf8(rewrite_response_head(raw_response_head, &rules)).unwrap(),
+            "HTTP/1.1 302 Found\r\nlocation: https://new.com/x\r\nAccess-Control-Allow-Origin: *\r\nCache-Control: no-store\r\n\r\n"
+        );
+
+        assert!(HeaderRule::parse("set Content-Length: 1").is_none());
+        assert!(HeaderRule::parse("set User-Agent").is_none());
+        assert!(HeaderRule::parse("replace Cookie: ( => x").is_none());
+        assert!(HeaderRule::parse("remove Cookie").is_some());
+
+        async_std::task::block_on(async {
+            let mut sink: Vec<u8> = vec![];
+
+            {
+                let mut stream = HeaderRewriteStream::new(&mut sink, &rules);
+                async_std::io::WriteExt::write_all(&mut stream, b"HTTP/1.1 200 OK\r\nset-cookie: a=1\r\n").await.unwrap();
+                async_std::io::WriteExt::write_all(&mut stream, b"\r\nbody").await.unwrap();
+                async_std::io::WriteExt::flush(&mut stream).await.unwrap();
+            }
+
+            assert_eq!(
+                String::from_utf8(sink).unwrap(),
+                "HTTP/1.1 200 OK\r\nAccess-Control-Allow-Origin: *\r\nCache-Control: no-store\r\n\r\nbody"
+            );
+        });
+    }
+}
@@ -0,0 +1,242 @@
+use std::collections::{HashMap, HashSet};
+
+use chrono::prelude::*;
+use serde_derive::{Serialize, Deserialize};
+
+use super::item::*;
+use super::controller::LopxyProxyServerControllerArc;
+use super::upstream::UpstreamRoute;
+
+/// Interval between two rounds of health check
+pub const HEALTH_CHECK_INTERVAL: std::time::Duration = std::time::Duration::from_secs(30);
+
+/// How long to wait for the response head of a health probe
+pub const HEALTH_PROBE_TIMEOUT: std::time::Duration = std::time::Duration::from_secs(5);
+
+///
+/// Health state of a proxy target, `target` is the configured url of it
+///
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct TargetState {
+    pub target: String,
+    pub healthy: bool,
+    pub latency: Option<u64>,
+    pub checked_timestamp: i64,
+    pub error: Option<String>,
+}
+
+///
+/// Health states of all proxy targets which have mirrors
+///
+/// # Notes
+/// A target never checked is treated as healthy
+#[derive(Default)]
+pub struct TargetHealthRegistry {
+    states: HashMap<String, TargetState>,
+}
+
+impl TargetHealthRegistry {
+    pub fn new() -> TargetHealthRegistry {
+        TargetHealthRegistry {
+            states: HashMap::new()
+        }
+    }
+
+    ///
+    /// Update the health state of target, `latency` in milliseconds
+    ///
+    pub fn update(&mut self, target: &str, healthy: bool, latency: Option<u64>, error: Option<String>) {
+        let latency = match latency {
+            Some(latency) => Some(latency),
+            None => self.states.get(target).and_then(|state: &TargetState| state.latency)
+        };
+
+        self.states.insert(target.to_string(), TargetState {
+            target: target.to_string(),
+            healthy,
+            latency,
+            checked_timestamp: Local::now().timestamp_millis(),
+            error
+        });
+    }
+
+    ///
+    /// Forget the targets which are not configured any more
+    ///
+    pub fn retain(&mut self, targets: &HashSet<String>) {
+        self.states.retain(|target: &String, _| targets.contains(target));
+    }
+
+    pub fn is_healthy(&self, target: &str) -> bool {
+        self.states.get(target).map(|state: &TargetState| state.healthy).unwrap_or(true)
+    }
+
+    ///
+    /// Order targets for a request, healthy targets come first
+    ///
+    /// # Notes
+    /// `Ordered` keeps the config order, `Latency` sorts healthy targets by the last measured latency,
+    /// targets without latency come after the measured ones. Unhealthy targets are kept at the end
+    /// in config order, so that they are still tried when everything else fails
+    pub fn order(&self, mut targets: Vec<ProxyTarget>, strategy: MirrorStrategy) -> Vec<ProxyTarget> {
+        targets.sort_by_key(|target: &ProxyTarget| {
+            let healthy = self.is_healthy(&target.template);
+            let latency = match strategy {
+                MirrorStrategy::Ordered => 0,
+                MirrorStrategy::Latency if healthy => {
+                    self.states.get(&target.template).and_then(|state: &TargetState| state.latency).unwrap_or(u64::MAX)
+                },
+                MirrorStrategy::Latency => 0
+            };
+
+            (!healthy, latency)
+        });
+
+        targets
+    }
+
+    ///
+    /// Get all target states sorted by target
+    ///
+    pub fn states(&self) -> Vec<TargetState> {
+        let mut states: Vec<TargetState> = self.states.values().cloned().collect();
+        states.sort_by(|a: &TargetState, b: &TargetState| a.target.cmp(&b.target));
+        states
+    }
+}
+
+///
+/// Probe a proxy target, return the latency in milliseconds or the error
+///
+/// # Notes
+/// `probe_url` is the fixed part of the target, the target of a pattern item (`strict` is false)
+/// usually doesn't exist itself, so any response below 500 means the server is alive. Local targets
+/// are healthy when the file or directory exists. Remote targets are probed through `route`
+pub async fn probe_target(route: &UpstreamRoute, probe_url: &str, strict: bool) -> Result<u64, String> {
+    let started = std::time::Instant::now();
+
+    if let Some(scheme) = super::request::get_uri_scheme(probe_url) {
+        if scheme.eq_ignore_ascii_case("file") {
+            return match super::local::resolve_local_file_path(probe_url) {
+                Some(path) if path.exists() => Ok(started.elapsed().as_millis() as u64),
+                _ => Err("local file not found".to_string())
+            };
+        }
+
+        if scheme.eq_ignore_ascii_case("ftp") {
+            let probe = async {
+                match strict {
+                    true => super::controller::ftp::open_ftp_resource(probe_url, false, route).await?.finish().await,
+                    false => super::controller::ftp::probe_ftp_server(probe_url, route).await
+                }
+            };
+
+            return match async_std::future::timeout(HEALTH_PROBE_TIMEOUT, probe).await {
+                Ok(Ok(_)) => Ok(started.elapsed().as_millis() as u64),
+                Ok(Err(err)) => Err(format!("{}", err)),
+                Err(_) => Err(format!("timeout after {}s", HEALTH_PROBE_TIMEOUT.as_secs()))
+            };
+        }
+    }
+
+    let client = match route.apply(reqwest::Client::builder().redirect(reqwest::redirect::Policy::none())).and_then(|builder| builder.build()) {
+        Ok(client) => client,
+        Err(err) => {
+            return Err(format!("build health check client failed, {}", err));
+        }
+    };
+
+    let response = match async_std::future::timeout(HEALTH_PROBE_TIMEOUT, client.head(probe_url).send()).await {
+        Ok(Ok(response)) => response,
+        Ok(Err(err)) => {
+            return Err(format!("{}", err));
+        },
+        Err(_) => {
+            return Err(format!("timeout after {}s", HEALTH_PROBE_TIMEOUT.as_secs()));
+        }
+    };
+
+    let latency = started.elapsed().as_millis() as u64;
+    let status = response.status().as_u16();
+    let healthy = match strict {
+        true => status < 400 || status == 405,
+        false => status < 500
+    };
+
+    match healthy {
+        true => Ok(latency),
+        false => Err(format!("responds {}", response.status()))
+    }
+}
+
+///
+/// Check the health of all proxy targets which have mirrors, until shutdown
+///
+pub async fn run_health_checker(controller: LopxyProxyServerControllerArc, shutdown: super::async_shutdown::Shutdown) {
+    loop {
+        let proxy_items = controller.lock().unwrap().mirrored_proxy_items();
+
+        let mut checked = HashSet::new();
+        for item in proxy_items.iter() {
+            for target in item.targets() {
+                if !checked.insert(target.template.clone()) {
+                    continue;
+                }
+
+                let probe_url = item.with_target(&target).proxy_resource_root_url();
+                let strict = item.match_kind() == MatchKind::Exact;
+                let route = controller.lock().unwrap().upstream_route(Some(item), &probe_url).unwrap_or(UpstreamRoute::Direct);
+
+                match probe_target(&route, &probe_url, strict).await {
+                    Ok(latency) => controller.lock().unwrap().report_target_state(&target.template, true, Some(latency), None),
+                    Err(err) => controller.lock().unwrap().report_target_state(&target.template, false, None, Some(err))
+                }
+            }
+        }
+
+        controller.lock().unwrap().retain_target_states(&checked);
+
+        if shutdown.wrap_cancel(async_std::task::sleep(HEALTH_CHECK_INTERVAL)).await.is_none() {
+            break;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn target(url: &str) -> ProxyTarget {
+        ProxyTarget {
+            template: url.to_string(),
+            url: url.to_string()
+        }
+    }
+
+    #[test]
+    fn order_targets_by_health() {
+        let targets = vec![target("http://a/"), target("http://b/"), target("http://c/"), target("http://d/")];
+
+        let mut registry = TargetHealthRegistry::new();
+        registry.update("http://a/", false, None, Some("responds 502".to_string()));
+        registry.update("http://b/", true, Some(300), None);
+        registry.update("http://c/", true, Some(20), None);
+
+        let ordered: Vec<String> = registry.order(targets.clone(), MirrorStrategy::Ordered).into_iter().map(|t| t.template).collect();
+        assert_eq!(ordered, vec!["http://b/", "http://c/", "http://d/", "http://a/"]);
+
+        let ordered: Vec<String> = registry.order(targets.clone(), MirrorStrategy::Latency).into_iter().map(|t| t.template).collect();
+        assert_eq!(ordered, vec!["http://c/", "http://b/", "http://d/", "http://a/"]);
+
+        // failure keeps the last measured latency
+        registry.update("http://c/", false, None, None);
+        assert_eq!(registry.states()[2].latency, Some(20));
+        assert!(!registry.is_healthy("http://c/"));
+        assert!(registry.is_healthy("http://unknown/"));
+
+        let mut configured = HashSet::new();
+        configured.insert("http://b/".to_string());
+        registry.retain(&configured);
+        assert_eq!(registry.states().len(), 1);
+    }
+}
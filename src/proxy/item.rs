@@ -1,10 +1,283 @@
 use serde_derive::{Serialize, Deserialize};
 
+use super::condition::{BlockKind, NetworkCondition};
+use super::mock::{MockBodyEncoding, MockResponse, TargetKind};
+
+///
+/// How `resource_url` of a proxy item matches the request url
+///
+/// # Notes
+/// When several proxy items match the same request url, the precedence is :
+/// exact > prefix(the longest prefix wins) > glob > regex, items of the same kind are tried in config order
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum MatchKind {
+    /// `resource_url` equals the request url
+    #[default]
+    Exact,
+    /// `resource_url` is a prefix of the request url, the rest of the url is `$1`
+    Prefix,
+    /// `*` matches any characters except `/`, `**` matches any characters, each wildcard is a capture group
+    Glob,
+    /// `resource_url` is a regex which should match the whole request url
+    Regex,
+}
+
+impl std::str::FromStr for MatchKind {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<MatchKind, String> {
+        match s.to_lowercase().as_str() {
+            "exact" => Ok(MatchKind::Exact),
+            "prefix" => Ok(MatchKind::Prefix),
+            "glob" => Ok(MatchKind::Glob),
+            "regex" => Ok(MatchKind::Regex),
+            _ => Err(format!("unknown match kind : {}", s))
+        }
+    }
+}
+
+impl std::fmt::Display for MatchKind {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", match self {
+            MatchKind::Exact => "exact",
+            MatchKind::Prefix => "prefix",
+            MatchKind::Glob => "glob",
+            MatchKind::Regex => "regex",
+        })
+    }
+}
+
+///
+/// How the proxy item takes over the matched request
+///
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum ProxyMode {
+    /// Always answer with the proxy resource
+    #[default]
+    Replace,
+    /// Request the original resource first, answer with the proxy resource only when it fails
+    Fallback,
+    /// Answer a redirect to the proxy resource, the client fetches it directly
+    Redirect,
+}
+
+impl std::str::FromStr for ProxyMode {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<ProxyMode, String> {
+        match s.to_lowercase().as_str() {
+            "replace" => Ok(ProxyMode::Replace),
+            "fallback" => Ok(ProxyMode::Fallback),
+            "redirect" => Ok(ProxyMode::Redirect),
+            _ => Err(format!("unknown proxy mode : {}", s))
+        }
+    }
+}
+
+impl std::fmt::Display for ProxyMode {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", match self {
+            ProxyMode::Replace => "replace",
+            ProxyMode::Fallback => "fallback",
+            ProxyMode::Redirect => "redirect",
+        })
+    }
+}
+
+///
+/// How to choose a target from the proxy resource and its mirrors
+///
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum MirrorStrategy {
+    /// Try healthy targets in config order
+    #[default]
+    Ordered,
+    /// Try healthy targets from the lowest latency one
+    Latency,
+}
+
+impl std::str::FromStr for MirrorStrategy {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<MirrorStrategy, String> {
+        match s.to_lowercase().as_str() {
+            "ordered" => Ok(MirrorStrategy::Ordered),
+            "latency" => Ok(MirrorStrategy::Latency),
+            _ => Err(format!("unknown mirror strategy : {}", s))
+        }
+    }
+}
+
+impl std::fmt::Display for MirrorStrategy {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", match self {
+            MirrorStrategy::Ordered => "ordered",
+            MirrorStrategy::Latency => "latency",
+        })
+    }
+}
+
+///
+/// A target of proxy item, `template` is the configured url and `url` is the one resolved for the request
+///
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ProxyTarget {
+    pub template: String,
+    pub url: String,
+}
+
+/// Default status codes of the original resource which trigger fallback
+pub const DEFAULT_FALLBACK_STATUS: &str = "404,5xx";
+
+/// Default seconds to wait for the response head of the original resource
+pub const DEFAULT_FALLBACK_TIMEOUT: u64 = 10;
+
+/// Default status code of redirect mode
+pub const DEFAULT_REDIRECT_STATUS: u16 = 302;
+
+fn default_fallback_status() -> String {
+    DEFAULT_FALLBACK_STATUS.to_string()
+}
+
+fn default_fallback_timeout() -> u64 {
+    DEFAULT_FALLBACK_TIMEOUT
+}
+
+fn default_redirect_status() -> u16 {
+    DEFAULT_REDIRECT_STATUS
+}
+
+///
+/// Check whether the status code can be answered by redirect mode
+///
+pub fn is_redirect_status(status: u16) -> bool {
+    [301, 302, 303, 307, 308].contains(&status)
+}
+
+///
+/// Append the query of request url to the redirect location
+///
+/// # Notes
+/// The query is joined with the query of location by `&`, nothing is appended if the location carries it already
+pub fn append_request_query(location: &str, request_url: &str) -> String {
+    let query = match url::Url::parse(request_url).ok().and_then(|url: url::Url| url.query().map(|query: &str| query.to_string())) {
+        Some(query) if !query.is_empty() => query,
+        _ => {
+            return location.to_string();
+        }
+    };
+
+    let (location, fragment) = match location.split_once('#') {
+        Some((location, fragment)) => (location, format!("#{}", fragment)),
+        None => (location, String::new())
+    };
+
+    match location.split_once('?') {
+        Some((_, location_query)) if location_query == query || location_query.ends_with(&format!("&{}", query)) => format!("{}{}", location, fragment),
+        Some((_, location_query)) => format!("{}{}{}{}", location, if location_query.is_empty() { "" } else { "&" }, query, fragment),
+        None => format!("{}?{}{}", location, query, fragment)
+    }
+}
+
+///
+/// Match status code against a comma separated status list, like `404,5xx`
+///
+/// # Notes
+/// Return None if the status list is invalid
+pub fn match_status_list(status_list: &str, status: u16) -> Option<bool> {
+    let mut matched = false;
+
+    for pattern in status_list.split(',').map(|pattern: &str| pattern.trim()).filter(|pattern: &&str| !pattern.is_empty()) {
+        let pattern = pattern.to_lowercase();
+
+        if pattern.len() == 3 && pattern.ends_with("xx") {
+            let class: u16 = pattern[..1].parse().ok()?;
+            matched |= status / 100 == class;
+        } else {
+            matched |= pattern.parse::<u16>().ok()? == status;
+        }
+    }
+
+    Some(matched)
+}
+
+///
+/// Translate a glob pattern to an anchored regex, every wildcard becomes a capture group
+///
+/// # Notes
+/// `?` is not a wildcard, because it is the query separator of url
+pub fn glob_to_regex(glob: &str) -> String {
+    let mut pattern = String::from("^");
+    let mut chars = glob.chars().peekable();
+
+    while let Some(c) = chars.next() {
+        match c {
+            '*' => {
+                if chars.peek() == Some(&'*') {
+                    chars.next();
+                    pattern.push_str("(.*)");
+                } else {
+                    pattern.push_str("([^/]*)");
+                }
+            },
+            c => {
+                pattern.push_str(&regex::escape(&c.to_string()));
+            }
+        }
+    }
+
+    pattern.push('$');
+    pattern
+}
+
 #[derive(Serialize, Deserialize, Debug, Clone)]
 pub struct ProxyItem {
     resource_url: String,
     proxy_resource_url: String,
     content_type: String,
+    #[serde(default)]
+    match_kind: MatchKind,
+    #[serde(default)]
+    directory_index: bool,
+    #[serde(default)]
+    directory_listing: bool,
+    #[serde(default)]
+    mode: ProxyMode,
+    #[serde(default = "default_fallback_status")]
+    fallback_status: String,
+    #[serde(default = "default_fallback_timeout")]
+    fallback_timeout: u64,
+    #[serde(default = "default_redirect_status")]
+    redirect_status: u16,
+    #[serde(default)]
+    preserve_query: bool,
+    #[serde(default)]
+    mirror_urls: Vec<String>,
+    #[serde(default)]
+    mirror_strategy: MirrorStrategy,
+    #[serde(default)]
+    upstream_proxy: Option<String>,
+    #[serde(default)]
+    rule_set: Option<String>,
+    #[serde(default)]
+    mock_response: Option<MockResponse>,
+    #[serde(default)]
+    network_condition: Option<NetworkCondition>,
+    #[serde(default)]
+    request_headers: Vec<String>,
+    #[serde(default)]
+    response_headers: Vec<String>,
+    #[serde(default)]
+    body_rules: Vec<String>,
+    #[serde(skip)]
+    matcher: Option<regex::Regex>,
+    #[serde(skip)]
+    proxy_resource_template: Option<String>,
+    #[serde(skip)]
+    mirror_templates: Option<Vec<String>>,
 }
 
 impl ProxyItem {
@@ -12,7 +285,27 @@ impl ProxyItem {
         ProxyItem {
             resource_url: resource_url.to_string(),
             proxy_resource_url: proxy_resource_url.to_string(),
-            content_type: content_type.to_string()
+            content_type: content_type.to_string(),
+            match_kind: MatchKind::Exact,
+            directory_index: false,
+            directory_listing: false,
+            mode: ProxyMode::Replace,
+            fallback_status: default_fallback_status(),
+            fallback_timeout: default_fallback_timeout(),
+            redirect_status: default_redirect_status(),
+            preserve_query: false,
+            mirror_urls: vec![],
+            mirror_strategy: MirrorStrategy::Ordered,
+            upstream_proxy: None,
+            rule_set: None,
+            mock_response: None,
+            network_condition: None,
+            request_headers: vec![],
+            response_headers: vec![],
+            body_rules: vec![],
+            matcher: None,
+            proxy_resource_template: None,
+            mirror_templates: None
         }
     }
 
@@ -28,6 +321,199 @@ impl ProxyItem {
         &self.content_type
     }
 
+    pub fn match_kind(&self) -> MatchKind {
+        self.match_kind
+    }
+
+    ///
+    /// Whether serve `index.html` for directory requests of local directory mapping
+    ///
+    pub fn directory_index(&self) -> bool {
+        self.directory_index
+    }
+
+    ///
+    /// Whether generate a listing page for directory requests of local directory mapping
+    ///
+    pub fn directory_listing(&self) -> bool {
+        self.directory_listing
+    }
+
+    pub fn mode(&self) -> ProxyMode {
+        self.mode
+    }
+
+    ///
+    /// Comma separated status codes of the original resource which trigger fallback, `5xx` matches a whole class
+    ///
+    pub fn fallback_status(&self) -> &str {
+        &self.fallback_status
+    }
+
+    ///
+    /// Seconds to wait for the response head of the original resource before fallback
+    ///
+    pub fn fallback_timeout(&self) -> u64 {
+        self.fallback_timeout
+    }
+
+    ///
+    /// Status code answered by redirect mode, one of 301, 302, 303, 307 and 308
+    ///
+    pub fn redirect_status(&self) -> u16 {
+        self.redirect_status
+    }
+
+    ///
+    /// Whether redirect mode appends the query of request url to the location
+    ///
+    pub fn preserve_query(&self) -> bool {
+        self.preserve_query
+    }
+
+    pub fn is_fallback_status(&self, status: u16) -> bool {
+        match_status_list(&self.fallback_status, status).unwrap_or(false)
+    }
+
+    ///
+    /// Mirrors tried after `proxy_resource_url` when it fails
+    ///
+    pub fn mirror_urls<'a>(&'a self) -> &'a Vec<String> {
+        &self.mirror_urls
+    }
+
+    pub fn mirror_strategy(&self) -> MirrorStrategy {
+        self.mirror_strategy
+    }
+
+    ///
+    /// Upstream proxy url or `direct` used to request the targets, None uses the global upstream proxy
+    ///
+    pub fn upstream_proxy(&self) -> Option<&str> {
+        self.upstream_proxy.as_deref()
+    }
+
+    pub fn upstream_route(&self) -> Option<super::upstream::UpstreamRoute> {
+        super::upstream::UpstreamRoute::parse(self.upstream_proxy.as_deref()?)
+    }
+
+    ///
+    /// Get the target kind of proxy item, it's mock if the item has a mock response
+    ///
+    pub fn target_kind(&self) -> TargetKind {
+        match self.mock_response {
+            Some(_) => TargetKind::Mock,
+            None => TargetKind::Url
+        }
+    }
+
+    ///
+    /// Response answered directly by the item, `proxy_resource_url` and mirrors are not requested if it's set
+    ///
+    pub fn mock_response(&self) -> Option<&MockResponse> {
+        self.mock_response.as_ref()
+    }
+
+    ///
+    /// Network condition simulated for the requests of proxy item, an empty `proxy_resource_url`
+    /// requests the original resource under the condition
+    ///
+    pub fn network_condition(&self) -> Option<&NetworkCondition> {
+        self.network_condition.as_ref()
+    }
+
+    ///
+    /// Header rules like `set User-Agent: lopxy` applied to the request sent to the targets
+    ///
+    pub fn request_headers<'a>(&'a self) -> &'a Vec<String> {
+        &self.request_headers
+    }
+
+    ///
+    /// Header rules like `add Access-Control-Allow-Origin: *` applied to the response answered to the client
+    ///
+    pub fn response_headers<'a>(&'a self) -> &'a Vec<String> {
+        &self.response_headers
+    }
+
+    ///
+    /// Body rules like `text http://old.com/ => https://new.com/` applied to the successful text responses
+    ///
+    pub fn body_rules<'a>(&'a self) -> &'a Vec<String> {
+        &self.body_rules
+    }
+
+    ///
+    /// Rule set of proxy item, only the proxy users bound to it can use the item, None for all users
+    ///
+    pub fn rule_set(&self) -> Option<&str> {
+        self.rule_set.as_deref()
+    }
+
+    ///
+    /// Get all targets in config order, the proxy resource is the first one
+    ///
+    pub fn targets(&self) -> Vec<ProxyTarget> {
+        let mut targets = vec![ProxyTarget {
+            template: self.proxy_resource_template.clone().unwrap_or(self.proxy_resource_url.clone()),
+            url: self.proxy_resource_url.clone()
+        }];
+
+        let mirror_templates = self.mirror_templates.as_ref().unwrap_or(&self.mirror_urls);
+        for (template, url) in mirror_templates.iter().zip(self.mirror_urls.iter()) {
+            targets.push(ProxyTarget {
+                template: template.clone(),
+                url: url.clone()
+            });
+        }
+
+        targets
+    }
+
+    ///
+    /// Get the proxy item which uses `target` as its proxy resource
+    ///
+    pub fn with_target(&self, target: &ProxyTarget) -> ProxyItem {
+        let mut item = self.clone();
+        item.proxy_resource_url = target.url.clone();
+        item.proxy_resource_template = Some(target.template.clone());
+        item
+    }
+
+    ///
+    /// Get the root url of the proxy resource, resolved files must not go outside of it
+    ///
+    /// # Notes
+    /// For a resolved proxy item, the root is the part of unresolved `proxy_resource_url` before
+    /// the first capture group reference, cut at the last `/`
+    pub fn proxy_resource_root_url(&self) -> String {
+        let template = match &self.proxy_resource_template {
+            Some(template) => template,
+            None => {
+                return self.proxy_resource_url.clone();
+            }
+        };
+
+        let fixed = match template.find('$') {
+            Some(pos) => &template[..pos],
+            None => template
+        };
+
+        if fixed.len() == template.len() {
+            return fixed.to_string();
+        }
+
+        match fixed.rfind('/') {
+            Some(pos) => fixed[..pos + 1].to_string(),
+            None => fixed.to_string()
+        }
+    }
+
+    pub fn update_resource_url(&mut self, resource_url: &str) {
+        self.resource_url = resource_url.to_string();
+        self.matcher = None;
+    }
+
     pub fn update_proxy_resource_url(&mut self, proxy_resource_url: &str) {
         self.proxy_resource_url = proxy_resource_url.to_string()
     }
@@ -35,4 +521,540 @@ impl ProxyItem {
     pub fn update_resource_content_type(&mut self, content_type: &str) {
         self.content_type = content_type.to_string()
     }
-}
\ No newline at end of file
+
+    pub fn update_directory_options(&mut self, directory_index: bool, directory_listing: bool) {
+        self.directory_index = directory_index;
+        self.directory_listing = directory_listing;
+    }
+
+    pub fn update_mode(&mut self, mode: ProxyMode) {
+        self.mode = mode;
+    }
+
+    pub fn update_fallback_options(&mut self, fallback_status: &str, fallback_timeout: u64) {
+        self.fallback_status = fallback_status.to_string();
+        self.fallback_timeout = fallback_timeout;
+    }
+
+    pub fn update_redirect_options(&mut self, redirect_status: u16, preserve_query: bool) {
+        self.redirect_status = redirect_status;
+        self.preserve_query = preserve_query;
+    }
+
+    pub fn update_mirrors(&mut self, mirror_urls: Vec<String>, mirror_strategy: MirrorStrategy) {
+        self.mirror_urls = mirror_urls;
+        self.mirror_strategy = mirror_strategy;
+    }
+
+    ///
+    /// Update the upstream proxy of proxy item, an empty one is removed
+    ///
+    pub fn update_upstream_proxy(&mut self, upstream_proxy: Option<String>) {
+        self.upstream_proxy = upstream_proxy.filter(|upstream_proxy: &String| !upstream_proxy.trim().is_empty());
+    }
+
+    ///
+    /// Update the rule set of proxy item, an empty one is removed
+    ///
+    pub fn update_rule_set(&mut self, rule_set: Option<String>) {
+        self.rule_set = rule_set.map(|rule_set: String| rule_set.trim().to_string()).filter(|rule_set: &String| !rule_set.is_empty());
+    }
+
+    pub fn update_mock_response(&mut self, mock_response: Option<MockResponse>) {
+        self.mock_response = mock_response;
+    }
+
+    ///
+    /// Update the network condition of proxy item, a condition which changes nothing is removed
+    ///
+    pub fn update_network_condition(&mut self, network_condition: Option<NetworkCondition>) {
+        self.network_condition = network_condition.filter(|condition: &NetworkCondition| !condition.is_empty());
+    }
+
+    pub fn update_header_rules(&mut self, request_headers: Vec<String>, response_headers: Vec<String>) {
+        self.request_headers = request_headers;
+        self.response_headers = response_headers;
+    }
+
+    ///
+    /// Check whether the item requests the original resource, an empty target of item with network
+    /// condition or body rules is the original resource
+    ///
+    pub fn requests_original_resource(&self) -> bool {
+        self.proxy_resource_url.is_empty() && self.mock_response.is_none() && (self.network_condition.is_some() || !self.body_rules.is_empty())
+    }
+
+    pub fn update_body_rules(&mut self, body_rules: Vec<String>) {
+        self.body_rules = body_rules;
+    }
+
+    pub fn update_match_kind(&mut self, match_kind: MatchKind) {
+        self.match_kind = match_kind;
+        self.matcher = None;
+    }
+
+    ///
+    /// Compile the matcher of pattern based proxy item, return false if the pattern is invalid
+    ///
+    pub fn compile_matcher(&mut self) -> bool {
+        let pattern = match self.match_kind {
+            MatchKind::Exact => {
+                self.matcher = None;
+                return true;
+            },
+            MatchKind::Prefix => format!("^{}(.*)$", regex::escape(&self.resource_url)),
+            MatchKind::Glob => glob_to_regex(&self.resource_url),
+            MatchKind::Regex => format!("^(?:{})$", self.resource_url),
+        };
+
+        self.matcher = match regex::Regex::new(&pattern) {
+            Ok(matcher) => Some(matcher),
+            Err(_) => {
+                return false;
+            }
+        };
+
+        true
+    }
+
+    ///
+    /// Check whether the item may match https urls of `host`, hosts without any of such items are not intercepted
+    ///
+    /// # Notes
+    /// Exact and prefix items are checked exactly and the authority of glob item is matched as glob.
+    /// Regex item is checked by its literal `https://host` prefix, it may match any host if there is no such prefix
+    pub fn may_match_https_host(&self, host: &str, port: u16) -> bool {
+        let host = host.to_lowercase();
+        let authority = if port == 443 { host.clone() } else { format!("{}:{}", host, port) };
+
+        match self.match_kind {
+            MatchKind::Exact | MatchKind::Prefix => {
+                let url = match url::Url::parse(&self.resource_url) {
+                    Ok(url) => url,
+                    Err(_) => {
+                        return false;
+                    }
+                };
+
+                url.scheme().eq("https") && url.host_str() == Some(&host[..]) && url.port_or_known_default() == Some(port)
+            },
+            MatchKind::Glob => {
+                let (scheme, rest) = match self.resource_url.split_once("://") {
+                    Some(parts) => parts,
+                    None => {
+                        return false;
+                    }
+                };
+
+                let glob_matches = |glob: &str, text: &str| -> bool {
+                    regex::Regex::new(&glob_to_regex(&glob.to_lowercase())).map(|matcher: regex::Regex| matcher.is_match(text)).unwrap_or(false)
+                };
+
+                glob_matches(scheme, "https") && glob_matches(rest.split('/').next().unwrap_or(""), &authority)
+            },
+            MatchKind::Regex => {
+                let pattern = self.resource_url.trim_start_matches('^');
+                let rest = match pattern.strip_prefix("https://").or(pattern.strip_prefix(r"https:\/\/")) {
+                    Some(rest) => rest,
+                    None => {
+                        return !pattern.starts_with("http://") && !pattern.starts_with(r"http:\/\/");
+                    }
+                };
+
+                let literal_authority = rest.split('/').next().unwrap_or("").trim_end_matches('\\').replace(r"\.", ".");
+                if literal_authority.is_empty() || literal_authority.contains(|c: char| "\\()[]{}*+?|^$".contains(c)) {
+                    return true;
+                }
+
+                literal_authority.to_lowercase().eq(&authority)
+            }
+        }
+    }
+
+    ///
+    /// Get the shell expression of hosts the item may match, used by the generated PAC file
+    ///
+    /// # Notes
+    /// Ports are ignored, glob authority keeps its wildcards and a regex item without literal host
+    /// gets `*` which matches any host
+    pub fn host_pattern(&self) -> Option<String> {
+        let authority = match self.match_kind {
+            MatchKind::Exact | MatchKind::Prefix => {
+                return url::Url::parse(&self.resource_url).ok()?.host_str().map(|host: &str| host.trim_start_matches('[').trim_end_matches(']').to_lowercase());
+            },
+            MatchKind::Glob => self.resource_url.split_once("://")?.1.split('/').next().unwrap_or("").replace("**", "*"),
+            MatchKind::Regex => {
+                let pattern = self.resource_url.trim_start_matches('^');
+                let rest = ["https://", r"https:\/\/", "http://", r"http:\/\/", "ftp://", r"ftp:\/\/"].iter().
+                    find_map(|scheme: &&str| pattern.strip_prefix(*scheme));
+
+                let literal_authority = rest.map(|rest: &str| rest.split('/').next().unwrap_or("").trim_end_matches('\\').replace(r"\.", "."));
+                match literal_authority {
+                    Some(literal_authority) if !literal_authority.contains(|c: char| "\\()[]{}*+?|^$".contains(c)) => literal_authority,
+                    _ => "*".to_string()
+                }
+            }
+        };
+
+        // strip userinfo and port, bracketed ipv6 address keeps its colons
+        let authority = authority.rsplit('@').next().unwrap_or("").to_lowercase();
+        let host = match authority.strip_prefix('[') {
+            Some(rest) => rest.split(']').next().unwrap_or(""),
+            None => authority.split(':').next().unwrap_or("")
+        };
+
+        match host {
+            "" => Some("*".to_string()),
+            host => Some(host.to_string())
+        }
+    }
+
+    ///
+    /// Substitute the captured groups into target
+    ///
+    /// # Notes
+    /// `$1`, `${1}` and `${name}` in target are replaced by the captured groups, a prefix
+    /// item without any `$` reference appends the rest of the request url to target
+    fn expand_target(&self, captures: &regex::Captures<'_>, template: &str) -> String {
+        if self.match_kind == MatchKind::Prefix && !template.contains('$') {
+            return format!("{}{}", template, captures.get(1).map(|m| m.as_str()).unwrap_or(""));
+        }
+
+        let mut target = String::new();
+        captures.expand(template, &mut target);
+
+        target
+    }
+
+    ///
+    /// Match the request url, return the proxy resource url with captured groups substituted
+    ///
+    pub fn match_url(&self, url: &str) -> Option<String> {
+        self.resolve(url).map(|item: ProxyItem| item.proxy_resource_url)
+    }
+
+    ///
+    /// Resolve the proxy item for the request url, the resolved item's `proxy_resource_url` and
+    /// `mirror_urls` are the final targets
+    ///
+    pub fn resolve(&self, url: &str) -> Option<ProxyItem> {
+        let captures = match self.match_kind {
+            MatchKind::Exact => {
+                if self.resource_url.ne(url) {
+                    return None;
+                }
+                None
+            },
+            _ => {
+                let compiled;
+                let matcher = match &self.matcher {
+                    Some(matcher) => matcher,
+                    None => {
+                        let mut item = self.clone();
+                        if !item.compile_matcher() {
+                            return None;
+                        }
+                        compiled = item.matcher?;
+                        &compiled
+                    }
+                };
+
+                Some(matcher.captures(url)?)
+            }
+        };
+
+        let expand = |template: &String| -> String {
+            match &captures {
+                Some(captures) => self.expand_target(captures, template),
+                None => template.clone()
+            }
+        };
+
+        let mut item = self.clone();
+        item.proxy_resource_template = Some(self.proxy_resource_url.clone());
+        item.proxy_resource_url = match self.requests_original_resource() {
+            true => url.to_string(),
+            false => expand(&self.proxy_resource_url)
+        };
+        item.mirror_templates = Some(self.mirror_urls.clone());
+        item.mirror_urls = self.mirror_urls.iter().map(expand).collect();
+
+        Some(item)
+    }
+}
+
+///
+/// Modification of a proxy item, `None` fields keep the values of the existing proxy item
+///
+#[derive(Debug, Clone, Default)]
+pub struct ProxyItemModification {
+    pub resource_url: String,
+    pub proxy_resource_url: String,
+    pub content_type: String,
+    pub match_kind: Option<MatchKind>,
+    pub directory_index: Option<bool>,
+    pub directory_listing: Option<bool>,
+    pub mode: Option<ProxyMode>,
+    pub fallback_status: Option<String>,
+    pub fallback_timeout: Option<u64>,
+    pub redirect_status: Option<u16>,
+    pub preserve_query: Option<bool>,
+    pub mirror_urls: Option<Vec<String>>,
+    pub mirror_strategy: Option<MirrorStrategy>,
+    pub upstream_proxy: Option<String>,
+    pub rule_set: Option<String>,
+    pub target_kind: Option<TargetKind>,
+    pub mock_status: Option<u16>,
+    pub mock_headers: Option<Vec<String>>,
+    pub mock_body: Option<String>,
+    pub mock_body_encoding: Option<MockBodyEncoding>,
+    /// `Some(None)` removes the current block
+    pub block: Option<Option<BlockKind>>,
+    pub block_status: Option<u16>,
+    pub failure_rate: Option<u8>,
+    pub latency: Option<u64>,
+    pub bandwidth: Option<u64>,
+    pub request_headers: Option<Vec<String>>,
+    pub response_headers: Option<Vec<String>>,
+    pub body_rules: Option<Vec<String>>,
+}
+
+impl ProxyItemModification {
+    ///
+    /// Apply the modification to the existing proxy item, or to a new one if not exists
+    ///
+    pub fn apply(&self, existing: Option<ProxyItem>) -> ProxyItem {
+        let mut item = match existing {
+            Some(mut item) => {
+                item.update_proxy_resource_url(&self.proxy_resource_url);
+                item.update_resource_content_type(&self.content_type);
+                item
+            },
+            None => ProxyItem::new(&self.resource_url, &self.proxy_resource_url, &self.content_type)
+        };
+
+        if let Some(match_kind) = self.match_kind {
+            item.update_match_kind(match_kind);
+        }
+
+        item.update_directory_options(
+            self.directory_index.unwrap_or(item.directory_index()),
+            self.directory_listing.unwrap_or(item.directory_listing())
+        );
+
+        if let Some(mode) = self.mode {
+            item.update_mode(mode);
+        }
+
+        let fallback_status = self.fallback_status.clone().unwrap_or(item.fallback_status().to_string());
+        item.update_fallback_options(&fallback_status, self.fallback_timeout.unwrap_or(item.fallback_timeout()));
+
+        item.update_redirect_options(
+            self.redirect_status.unwrap_or(item.redirect_status()),
+            self.preserve_query.unwrap_or(item.preserve_query())
+        );
+
+        let mirror_urls = self.mirror_urls.clone().unwrap_or(item.mirror_urls().clone());
+        item.update_mirrors(mirror_urls, self.mirror_strategy.unwrap_or(item.mirror_strategy()));
+
+        // an empty upstream proxy removes the current one
+        if let Some(upstream_proxy) = &self.upstream_proxy {
+            item.update_upstream_proxy(Some(upstream_proxy.clone()));
+        }
+
+        // an empty rule set makes the item available to all users
+        if let Some(rule_set) = &self.rule_set {
+            item.update_rule_set(Some(rule_set.clone()));
+        }
+
+        // the mock fields only apply to mock target, the current mock response is dropped by url target
+        match self.target_kind {
+            Some(TargetKind::Url) => item.update_mock_response(None),
+            Some(TargetKind::Mock) if item.mock_response().is_none() => item.update_mock_response(Some(MockResponse::default())),
+            _ => {}
+        }
+
+        if let Some(mut mock_response) = item.mock_response().cloned() {
+            mock_response.status = self.mock_status.unwrap_or(mock_response.status);
+            mock_response.headers = self.mock_headers.clone().unwrap_or(mock_response.headers);
+            mock_response.body = self.mock_body.clone().unwrap_or(mock_response.body);
+            mock_response.body_encoding = self.mock_body_encoding.unwrap_or(mock_response.body_encoding);
+            item.update_mock_response(Some(mock_response));
+        }
+
+        // an empty block removes the current one, the condition is dropped when nothing is left
+        let mut network_condition = item.network_condition().cloned().unwrap_or_default();
+        if let Some(block) = self.block {
+            network_condition.block = block;
+        }
+        network_condition.block_status = self.block_status.unwrap_or(network_condition.block_status);
+        network_condition.failure_rate = self.failure_rate.unwrap_or(network_condition.failure_rate);
+        network_condition.latency = self.latency.unwrap_or(network_condition.latency);
+        network_condition.bandwidth = self.bandwidth.unwrap_or(network_condition.bandwidth);
+        item.update_network_condition(Some(network_condition));
+
+        let request_headers = self.request_headers.clone().unwrap_or(item.request_headers().clone());
+        let response_headers = self.response_headers.clone().unwrap_or(item.response_headers().clone());
+        item.update_header_rules(request_headers, response_headers);
+
+        if let Some(body_rules) = &self.body_rules {
+            item.update_body_rules(body_rules.clone());
+        }
+
+        item
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn item(match_kind: MatchKind, resource_url: &str, proxy_resource_url: &str) -> ProxyItem {
+        let mut item = ProxyItem::new(resource_url, proxy_resource_url, "application/octet-stream");
+        item.update_match_kind(match_kind);
+        assert!(item.compile_matcher());
+        item
+    }
+
+    #[test]
+    fn match_pattern_items() {
+        let prefix = item(MatchKind::Prefix, "http://cdn.old.com/libs/", "http://mirror.internal/libs/");
+        assert_eq!(prefix.match_url("http://cdn.old.com/libs/a/b.js").as_deref(), Some("http://mirror.internal/libs/a/b.js"));
+        assert_eq!(prefix.match_url("http://cdn.old.com/other/b.js"), None);
+
+        let glob = item(MatchKind::Glob, "http://cdn.old.com/*/v*/**", "http://mirror.internal/$1/$3");
+        assert_eq!(glob.match_url("http://cdn.old.com/jquery/v3/dist/jquery.js").as_deref(), Some("http://mirror.internal/jquery/dist/jquery.js"));
+        assert_eq!(glob.match_url("http://cdn.old.com/a/b/v3/x.js"), None);
+
+        let regex = item(MatchKind::Regex, r"http://cdn\.old\.com/libs/(.*)", "http://mirror.internal/libs/$1");
+        assert_eq!(regex.match_url("http://cdn.old.com/libs/x.js").as_deref(), Some("http://mirror.internal/libs/x.js"));
+        assert_eq!(regex.match_url("https://cdn.old.com/libs/x.js"), None);
+
+        let named = item(MatchKind::Regex, r"http://(?P<host>[^/]+)/(.*)", "http://mirror.internal/${host}/${2}");
+        assert_eq!(named.match_url("http://a.com/b/c").as_deref(), Some("http://mirror.internal/a.com/b/c"));
+    }
+
+    #[test]
+    fn match_fallback_status_list() {
+        assert_eq!(match_status_list("404,5xx", 503), Some(true));
+        assert_eq!(match_status_list("404,5xx", 403), Some(false));
+        assert_eq!(match_status_list("404,abc", 404), None);
+    }
+
+    #[test]
+    fn resolve_mirror_targets() {
+        let mut mirrored = item(MatchKind::Prefix, "http://cdn.old.com/libs/", "http://mirror.internal/libs/");
+        mirrored.update_mirrors(vec!["http://backup.internal/libs/".to_string()], MirrorStrategy::Ordered);
+        let targets = mirrored.resolve("http://cdn.old.com/libs/a.js").unwrap().targets();
+        assert_eq!(targets.iter().map(|t| t.url.as_str()).collect::<Vec<_>>(), vec!["http://mirror.internal/libs/a.js", "http://backup.internal/libs/a.js"]);
+        assert_eq!(targets[1].template, "http://backup.internal/libs/");
+    }
+
+    #[test]
+    fn match_intercepted_https_hosts() {
+        assert!(item(MatchKind::Exact, "https://www.test.com/a.js", "").may_match_https_host("WWW.test.com", 443));
+        assert!(!item(MatchKind::Prefix, "http://www.test.com/", "").may_match_https_host("www.test.com", 443));
+        assert!(item(MatchKind::Glob, "*://*.test.com:8443/**", "").may_match_https_host("cdn.test.com", 8443));
+        assert!(!item(MatchKind::Glob, "https://*.test.com/**", "").may_match_https_host("cdn.other.com", 443));
+        assert!(item(MatchKind::Regex, r"https://cdn\.test\.com/(.*)", "").may_match_https_host("cdn.test.com", 443));
+        assert!(!item(MatchKind::Regex, r"https:\/\/cdn\.test\.com\/(.*)", "").may_match_https_host("www.test.com", 443));
+        assert!(item(MatchKind::Regex, r"https?://(.*)", "").may_match_https_host("www.test.com", 443));
+        assert!(!item(MatchKind::Regex, r"http://(.*)", "").may_match_https_host("www.test.com", 443));
+    }
+
+    #[test]
+    fn apply_mock_modification() {
+        let mock = ProxyItemModification {
+            resource_url: "http://a.com/api".to_string(),
+            content_type: "application/json".to_string(),
+            target_kind: Some(TargetKind::Mock),
+            mock_status: Some(201),
+            mock_body: Some("{}".to_string()),
+            ..ProxyItemModification::default()
+        }.apply(None);
+        assert_eq!(mock.target_kind(), TargetKind::Mock);
+        assert_eq!(mock.mock_response().unwrap().status, 201);
+
+        let kept = ProxyItemModification {
+            resource_url: "http://a.com/api".to_string(),
+            content_type: "application/json".to_string(),
+            mock_headers: Some(vec!["x-mock: 1".to_string()]),
+            ..ProxyItemModification::default()
+        }.apply(Some(mock.clone()));
+        assert_eq!(kept.mock_response().unwrap().body, "{}");
+        assert_eq!(kept.mock_response().unwrap().headers, vec!["x-mock: 1".to_string()]);
+
+        let url = ProxyItemModification {
+            resource_url: "http://a.com/api".to_string(),
+            proxy_resource_url: "http://b.com/api".to_string(),
+            target_kind: Some(TargetKind::Url),
+            ..ProxyItemModification::default()
+        }.apply(Some(kept));
+        assert!(url.mock_response().is_none());
+    }
+
+    #[test]
+    fn apply_network_condition_modification() {
+        let slow = ProxyItemModification {
+            resource_url: "http://a.com/".to_string(),
+            content_type: "auto".to_string(),
+            match_kind: Some(MatchKind::Prefix),
+            latency: Some(500),
+            ..ProxyItemModification::default()
+        }.apply(None);
+        assert_eq!(slow.network_condition().unwrap().latency, 500);
+        assert_eq!(slow.resolve("http://a.com/b.js").unwrap().proxy_resource_url(), "http://a.com/b.js");
+
+        let blocked = ProxyItemModification {
+            resource_url: "http://a.com/".to_string(),
+            content_type: "auto".to_string(),
+            block: Some(Some(BlockKind::Status)),
+            block_status: Some(404),
+            latency: Some(0),
+            ..ProxyItemModification::default()
+        }.apply(Some(slow));
+        assert_eq!(blocked.network_condition().unwrap().block, Some(BlockKind::Status));
+        assert_eq!(blocked.network_condition().unwrap().block_status, 404);
+
+        let cleared = ProxyItemModification {
+            resource_url: "http://a.com/".to_string(),
+            content_type: "auto".to_string(),
+            block: Some(None),
+            ..ProxyItemModification::default()
+        }.apply(Some(blocked));
+        assert!(cleared.network_condition().is_none());
+    }
+
+    #[test]
+    fn apply_body_rules_modification() {
+        let rewritten = ProxyItemModification {
+            resource_url: "http://a.com/app.js".to_string(),
+            content_type: "auto".to_string(),
+            body_rules: Some(vec!["text http://dead.com/ => http://live.com/".to_string()]),
+            ..ProxyItemModification::default()
+        }.apply(None);
+        assert!(rewritten.requests_original_resource());
+        assert_eq!(rewritten.resolve("http://a.com/app.js").unwrap().proxy_resource_url(), "http://a.com/app.js");
+
+        let cleared = ProxyItemModification {
+            resource_url: "http://a.com/app.js".to_string(),
+            content_type: "auto".to_string(),
+            body_rules: Some(vec![]),
+            ..ProxyItemModification::default()
+        }.apply(Some(rewritten));
+        assert!(cleared.body_rules().is_empty());
+        assert!(!cleared.requests_original_resource());
+    }
+
+    #[test]
+    fn append_redirect_query() {
+        assert_eq!(append_request_query("https://b.com/x", "http://a.com/x?v=1"), "https://b.com/x?v=1");
+        assert_eq!(append_request_query("https://b.com/x?from=lopxy", "http://a.com/x?v=1"), "https://b.com/x?from=lopxy&v=1");
+        assert_eq!(append_request_query("https://b.com/x?v=1", "http://a.com/x?v=1"), "https://b.com/x?v=1");
+        assert_eq!(append_request_query("https://b.com/x#top", "http://a.com/x?v=1"), "https://b.com/x?v=1#top");
+        assert_eq!(append_request_query("https://b.com/x", "http://a.com/x"), "https://b.com/x");
+
+        assert!(is_redirect_status(307));
+        assert!(!is_redirect_status(304));
+    }
+}
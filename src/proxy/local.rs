@@ -0,0 +1,601 @@
+use async_std::io::{ReadExt, SeekExt, Write, WriteExt};
+use async_std::stream::StreamExt;
+use chrono::prelude::*;
+
+use super::stream::*;
+
+/// Size of a single read from the local file
+const FILE_BUFFER_SIZE: usize = 64 * 1024;
+
+/// Max count of ranges served in one multipart response
+const MAX_RANGE_COUNT: usize = 16;
+
+/// Boundary of multipart/byteranges response
+const MULTIPART_BOUNDARY: &str = "LOPXY_BYTERANGES_BOUNDARY";
+
+///
+/// Resolve the local file path of a `file://` uri
+///
+pub fn resolve_local_file_path(local_file_uri: &str) -> Option<std::path::PathBuf> {
+    if local_file_uri.is_empty() {
+        return None;
+    }
+
+    let file_path = super::request::get_uri_path(local_file_uri)?;
+
+    // decode urlencoding str
+    let mut file_path = match urlencoding::decode(&file_path) {
+        Ok(c) => c.to_string(),
+        Err(_) => {
+            return None;
+        }
+    };
+
+    if cfg!(target_os = "windows") {
+        file_path = file_path[1..].to_string().replace("/", "\\")
+    }
+
+    Some(std::path::PathBuf::from(file_path))
+}
+
+///
+/// Validators of a local file
+///
+#[derive(Debug, Clone)]
+pub struct LocalFileMeta {
+    pub len: u64,
+    pub modified: Option<DateTime<Utc>>,
+}
+
+impl LocalFileMeta {
+    pub fn from_metadata(metadata: &std::fs::Metadata) -> LocalFileMeta {
+        LocalFileMeta {
+            len: metadata.len(),
+            // http date has only second precision
+            modified: metadata.modified().ok().and_then(|modified| DateTime::<Utc>::from(modified).with_nanosecond(0))
+        }
+    }
+
+    ///
+    /// Strong entity tag built from modified time and length
+    ///
+    pub fn etag(&self) -> String {
+        format!("\"{:x}-{:x}\"", self.modified.map(|m| m.timestamp()).unwrap_or(0), self.len)
+    }
+
+    pub fn last_modified(&self) -> Option<String> {
+        self.modified.map(format_http_date)
+    }
+}
+
+pub fn format_http_date(date: DateTime<Utc>) -> String {
+    date.format("%a, %d %b %Y %H:%M:%S GMT").to_string()
+}
+
+pub fn parse_http_date(date: &str) -> Option<DateTime<Utc>> {
+    match DateTime::parse_from_rfc2822(date.trim()) {
+        Ok(date) => Some(date.with_timezone(&Utc)),
+        Err(_) => None
+    }
+}
+
+///
+/// Check whether an `If-None-Match`/`If-Match` style entity tag list matches `etag`
+///
+/// # Notes
+/// `weak` selects the weak comparison function, which ignores the `W/` prefix
+pub fn etag_list_matches(list: &str, etag: &str, weak: bool) -> bool {
+    let strip = |tag: &str| -> String {
+        let tag = tag.trim();
+        if weak {
+            tag.trim_start_matches("W/").to_string()
+        } else {
+            tag.to_string()
+        }
+    };
+
+    list.split(',').any(|tag| {
+        let tag = tag.trim();
+        tag == "*" || ((weak || !tag.starts_with("W/")) && strip(tag) == strip(etag))
+    })
+}
+
+///
+/// Evaluate `If-None-Match`/`If-Modified-Since`, return true when `304 Not Modified` should be replied
+///
+pub fn is_not_modified(headers: &[HttpHeader], meta: &LocalFileMeta) -> bool {
+    if let Some(if_none_match) = find_header(headers, "if-none-match") {
+        return etag_list_matches(&if_none_match.value_str(), &meta.etag(), true);
+    }
+
+    if let Some(if_modified_since) = find_header(headers, "if-modified-since") {
+        if let (Some(since), Some(modified)) = (parse_http_date(&if_modified_since.value_str()), meta.modified) {
+            return modified <= since;
+        }
+    }
+
+    false
+}
+
+///
+/// Evaluate `If-Range`, return true when the `Range` header should be applied
+///
+pub fn is_range_applicable(headers: &[HttpHeader], meta: &LocalFileMeta) -> bool {
+    let if_range = match find_header(headers, "if-range") {
+        Some(h) => h.value_str().trim().to_string(),
+        None => {
+            return true;
+        }
+    };
+
+    if if_range.starts_with('"') || if_range.starts_with("W/") {
+        return etag_list_matches(&if_range, &meta.etag(), false);
+    }
+
+    match (parse_http_date(&if_range), meta.modified) {
+        (Some(date), Some(modified)) => date == modified,
+        _ => false
+    }
+}
+
+#[derive(Debug, PartialEq, Eq)]
+pub enum RangeRequest {
+    /// serve the whole file
+    Full,
+    /// serve the inclusive byte ranges
+    Partial(Vec<(u64, u64)>),
+    /// none of the ranges overlaps the file
+    Unsatisfiable,
+}
+
+///
+/// Parse `Range` header value for a file with `len` bytes
+///
+/// # Notes
+/// Syntactically invalid or unsupported ranges are ignored and the whole file is served
+pub fn parse_range(range: &str, len: u64) -> RangeRequest {
+    let specs = match range.trim().strip_prefix("bytes=") {
+        Some(specs) => specs,
+        None => {
+            return RangeRequest::Full;
+        }
+    };
+
+    let mut ranges: Vec<(u64, u64)> = vec![];
+
+    for spec in specs.split(',') {
+        let spec = spec.trim();
+        if spec.is_empty() {
+            continue;
+        }
+
+        let (start, end) = match spec.split_once('-') {
+            Some(pair) => pair,
+            None => {
+                return RangeRequest::Full;
+            }
+        };
+
+        let parse = |v: &str| -> Option<u64> { if v.is_empty() { None } else { v.trim().parse().ok() } };
+
+        let range = match (start.is_empty(), parse(start), parse(end)) {
+            // suffix range
+            (true, _, Some(suffix)) => {
+                if suffix == 0 || len == 0 {
+                    None
+                } else {
+                    Some((len.saturating_sub(suffix), len - 1))
+                }
+            },
+            (false, Some(start), None) if end.trim().is_empty() => {
+                if start >= len { None } else { Some((start, len - 1)) }
+            },
+            (false, Some(start), Some(end)) => {
+                if start > end {
+                    return RangeRequest::Full;
+                }
+
+                if start >= len { None } else { Some((start, std::cmp::min(end, len - 1))) }
+            },
+            _ => {
+                return RangeRequest::Full;
+            }
+        };
+
+        if let Some(range) = range {
+            ranges.push(range);
+        }
+    }
+
+    if ranges.is_empty() {
+        return RangeRequest::Unsatisfiable;
+    }
+
+    if ranges.len() > MAX_RANGE_COUNT {
+        return RangeRequest::Full;
+    }
+
+    RangeRequest::Partial(ranges)
+}
+
+fn multipart_part_head(content_type: &str, range: (u64, u64), len: u64) -> String {
+    format!("\r\n--{}\r\ncontent-type: {}\r\ncontent-range: bytes {}-{}/{}\r\n\r\n", MULTIPART_BOUNDARY, content_type, range.0, range.1, len)
+}
+
+fn multipart_tail() -> String {
+    format!("\r\n--{}--\r\n", MULTIPART_BOUNDARY)
+}
+
+async fn send_file_range<W: Write + Unpin>(stream: &mut W, file: &mut async_std::fs::File, range: (u64, u64)) -> std::io::Result<()> {
+    file.seek(std::io::SeekFrom::Start(range.0)).await?;
+
+    let mut remaining = range.1 - range.0 + 1;
+    let mut buf = vec![0; FILE_BUFFER_SIZE];
+
+    while remaining > 0 {
+        let read_size = std::cmp::min(remaining, FILE_BUFFER_SIZE as u64) as usize;
+        let read_byte_count = file.read(&mut buf[..read_size]).await?;
+        if read_byte_count == 0 {
+            return Err(std::io::Error::from(std::io::ErrorKind::UnexpectedEof));
+        }
+
+        stream.write_all(&buf[..read_byte_count]).await?;
+        remaining -= read_byte_count as u64;
+    }
+
+    Ok(())
+}
+
+///
+/// Send a local file as the response of `request_header`
+///
+/// # Notes
+/// The file is streamed piece by piece, `Range`/`If-Range`, `If-None-Match`/`If-Modified-Since` and
+/// `HEAD` are supported, return the replied status code
+pub async fn send_local_file<W: Write + Unpin>(stream: &mut W, request_header: &HttpRequestHead, local_file_uri: &str, content_type: &str) -> std::io::Result<u16> {
+    let file_path = match resolve_local_file_path(local_file_uri) {
+        Some(fp) => fp,
+        None => {
+            stream.write_all(&super::response::build_404_response()).await?;
+            return Ok(404);
+        }
+    };
+
+    send_local_file_path(stream, request_header, &file_path, content_type).await
+}
+
+///
+/// Sniff the content type of local file from its head bytes
+///
+async fn sniff_local_file(file: &mut async_std::fs::File) -> std::io::Result<String> {
+    let mut head = vec![0; super::content_type::SNIFF_SIZE];
+    let mut head_size = 0;
+
+    while head_size < head.len() {
+        let read_byte_count = file.read(&mut head[head_size..]).await?;
+        if read_byte_count == 0 {
+            break;
+        }
+        head_size += read_byte_count;
+    }
+
+    Ok(super::content_type::sniff(&head[..head_size]).unwrap_or(super::content_type::DEFAULT_CONTENT_TYPE).to_string())
+}
+
+///
+/// Send the local file at `file_path` as the response of `request_header`
+///
+pub async fn send_local_file_path<W: Write + Unpin>(stream: &mut W, request_header: &HttpRequestHead, file_path: &std::path::Path, content_type: &str) -> std::io::Result<u16> {
+    let mut file = match async_std::fs::File::open(file_path).await {
+        Ok(file) => file,
+        Err(_) => {
+            stream.write_all(&super::response::build_404_response()).await?;
+            return Ok(404);
+        }
+    };
+
+    let meta = match file.metadata().await {
+        Ok(metadata) if metadata.is_file() => LocalFileMeta::from_metadata(&metadata),
+        _ => {
+            stream.write_all(&super::response::build_404_response()).await?;
+            return Ok(404);
+        }
+    };
+
+    let content_type = if super::content_type::is_auto_content_type(content_type) {
+        sniff_local_file(&mut file).await?
+    } else {
+        content_type.to_string()
+    };
+    let content_type = content_type.as_str();
+
+    let head_only = request_header.method.eq_ignore_ascii_case("HEAD");
+    let headers = &request_header.headers;
+
+    //
+    // validators
+    //
+
+    let mut validators = format!("accept-ranges: bytes\r\netag: {}\r\n", meta.etag());
+    if let Some(last_modified) = meta.last_modified() {
+        validators.push_str(&format!("last-modified: {}\r\n", last_modified));
+    }
+
+    if is_not_modified(headers, &meta) {
+        stream.write_all(format!("HTTP/1.1 304 Not Modified\r\n{}\r\n", validators).as_bytes()).await?;
+        return Ok(304);
+    }
+
+    //
+    // ranges
+    //
+
+    let range = match find_header(headers, "range") {
+        Some(range) if request_header.method.eq_ignore_ascii_case("GET") && is_range_applicable(headers, &meta) => {
+            parse_range(&range.value_str(), meta.len)
+        },
+        _ => RangeRequest::Full
+    };
+
+    match range {
+        RangeRequest::Full => {
+            stream.write_all(
+                format!("HTTP/1.1 200 OK\r\ncontent-length: {}\r\ncontent-type: {}\r\n{}\r\n", meta.len, content_type, validators).
+                as_bytes()).await?;
+
+            if !head_only && meta.len > 0 {
+                send_file_range(stream, &mut file, (0, meta.len - 1)).await?;
+            }
+
+            Ok(200)
+        }
+
+        RangeRequest::Unsatisfiable => {
+            stream.write_all(
+                format!("HTTP/1.1 416 Range Not Satisfiable\r\ncontent-length: 0\r\ncontent-range: bytes */{}\r\n{}\r\n", meta.len, validators).
+                as_bytes()).await?;
+
+            Ok(416)
+        }
+
+        RangeRequest::Partial(ranges) if ranges.len() == 1 => {
+            let range = ranges[0];
+            stream.write_all(
+                format!(
+                    "HTTP/1.1 206 Partial Content\r\ncontent-length: {}\r\ncontent-type: {}\r\ncontent-range: bytes {}-{}/{}\r\n{}\r\n",
+                    range.1 - range.0 + 1, content_type, range.0, range.1, meta.len, validators
+                ).as_bytes()).await?;
+
+            send_file_range(stream, &mut file, range).await?;
+
+            Ok(206)
+        }
+
+        RangeRequest::Partial(ranges) => {
+            let content_length: u64 = ranges.iter().
+                map(|range| multipart_part_head(content_type, *range, meta.len).len() as u64 + range.1 - range.0 + 1).
+                sum::<u64>() + multipart_tail().len() as u64;
+
+            stream.write_all(
+                format!(
+                    "HTTP/1.1 206 Partial Content\r\ncontent-length: {}\r\ncontent-type: multipart/byteranges; boundary={}\r\n{}\r\n",
+                    content_length, MULTIPART_BOUNDARY, validators
+                ).as_bytes()).await?;
+
+            for range in ranges {
+                stream.write_all(multipart_part_head(content_type, range, meta.len).as_bytes()).await?;
+                send_file_range(stream, &mut file, range).await?;
+            }
+
+            stream.write_all(multipart_tail().as_bytes()).await?;
+
+            Ok(206)
+        }
+    }
+}
+
+///
+/// Check whether `path` is inside of `root` lexically
+///
+/// # Notes
+/// Any `..` component is rejected, so a percent-encoded `../` in the request url can't escape from the root
+fn is_path_lexically_inside(path: &std::path::Path, root: &std::path::Path) -> bool {
+    if path.components().any(|component| component == std::path::Component::ParentDir) {
+        return false;
+    }
+
+    path.starts_with(root)
+}
+
+///
+/// Check whether `path` is inside of `root`, both lexically and after symlinks are resolved
+///
+/// # Notes
+/// A symlink under the root which points outside of it is rejected, so is a path which can't be
+/// canonicalized, e.g. a missing file
+pub fn is_path_inside(path: &std::path::Path, root: &std::path::Path) -> bool {
+    if !is_path_lexically_inside(path, root) {
+        return false;
+    }
+
+    match (path.canonicalize(), root.canonicalize()) {
+        (Ok(path), Ok(root)) => path.starts_with(root),
+        _ => false
+    }
+}
+
+fn escape_html(s: &str) -> String {
+    s.replace('&', "&amp;").replace('<', "&lt;").replace('>', "&gt;").replace('"', "&quot;")
+}
+
+///
+/// Generate the listing page of a local directory
+///
+async fn build_directory_listing(dir_path: &std::path::Path, request_path: &str) -> std::io::Result<String> {
+    let mut entries: Vec<String> = vec![];
+
+    let mut dir = async_std::fs::read_dir(dir_path).await?;
+    while let Some(entry) = dir.next().await {
+        let entry = entry?;
+        let mut name = entry.file_name().to_string_lossy().to_string();
+        if entry.file_type().await.map(|file_type| file_type.is_dir()).unwrap_or(false) {
+            name.push('/');
+        }
+        entries.push(name);
+    }
+
+    entries.sort();
+
+    let title = escape_html(&urlencoding::decode(request_path).map(|path| path.to_string()).unwrap_or(request_path.to_string()));
+    let mut listing = format!("<html><head><meta charset=\"utf-8\"><title>Index of {0}</title></head><body><h1>Index of {0}</h1><hr><pre><a href=\"../\">../</a>\n", title);
+
+    for name in entries {
+        listing.push_str(&format!("<a href=\"{}\">{}</a>\n", urlencoding::encode(&name).replace("%2F", "/"), escape_html(&name)));
+    }
+
+    listing.push_str("</pre><hr></body></html>");
+
+    Ok(listing)
+}
+
+///
+/// Send the local resource of a resolved `file://` proxy item as the response of `request_header`
+///
+/// # Notes
+/// The resolved file must be inside of the root of the proxy item, a directory is answered with a redirect
+/// to the url with trailing `/`, its `index.html` or a listing page, depending on the directory options
+pub async fn send_local_resource<W: Write + Unpin>(stream: &mut W, request_header: &HttpRequestHead, proxy_item: &super::item::ProxyItem) -> std::io::Result<u16> {
+    let (file_path, root_path) = match (resolve_local_file_path(proxy_item.proxy_resource_url()), resolve_local_file_path(&proxy_item.proxy_resource_root_url())) {
+        (Some(file_path), Some(root_path)) => (file_path, root_path),
+        _ => {
+            stream.write_all(&super::response::build_404_response()).await?;
+            return Ok(404);
+        }
+    };
+
+    if !is_path_inside(&file_path, &root_path) {
+        // a missing file can't be canonicalized, it's not found rather than forbidden
+        if is_path_lexically_inside(&file_path, &root_path) && async_std::fs::metadata(&file_path).await.is_err() {
+            stream.write_all(&super::response::build_404_response()).await?;
+            return Ok(404);
+        }
+
+        stream.write_all(&super::response::build_403_response()).await?;
+        return Ok(403);
+    }
+
+    let is_dir = match async_std::fs::metadata(&file_path).await {
+        Ok(metadata) => metadata.is_dir(),
+        Err(_) => false
+    };
+
+    if !is_dir {
+        return send_local_file_path(stream, request_header, &file_path, proxy_item.content_type()).await;
+    }
+
+    let (request_path, query) = match request_header.path.find('?') {
+        Some(pos) => request_header.path.split_at(pos),
+        None => (request_header.path.as_str(), "")
+    };
+
+    if !request_path.ends_with('/') {
+        stream.write_all(
+            format!("HTTP/1.1 301 Moved Permanently\r\nlocation: {}/{}\r\ncontent-length: 0\r\n\r\n", request_path, query).
+            as_bytes()).await?;
+        return Ok(301);
+    }
+
+    if proxy_item.directory_index() {
+        let index_path = file_path.join("index.html");
+        if async_std::fs::metadata(&index_path).await.map(|metadata| metadata.is_file()).unwrap_or(false) {
+            return send_local_file_path(stream, request_header, &index_path, "text/html; charset=utf-8").await;
+        }
+    }
+
+    if !proxy_item.directory_listing() {
+        stream.write_all(&super::response::build_404_response()).await?;
+        return Ok(404);
+    }
+
+    let listing = match build_directory_listing(&file_path, super::request::get_uri_path(request_path).as_deref().unwrap_or(request_path)).await {
+        Ok(listing) => listing,
+        Err(_) => {
+            stream.write_all(&super::response::build_404_response()).await?;
+            return Ok(404);
+        }
+    };
+
+    stream.write_all(
+        format!("HTTP/1.1 200 OK\r\ncontent-length: {}\r\ncontent-type: text/html; charset=utf-8\r\n\r\n", listing.len()).
+        as_bytes()).await?;
+
+    if !request_header.method.eq_ignore_ascii_case("HEAD") {
+        stream.write_all(listing.as_bytes()).await?;
+    }
+
+    Ok(200)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_range_specs() {
+        assert_eq!(parse_range("bytes=0-99", 1000), RangeRequest::Partial(vec![(0, 99)]));
+        assert_eq!(parse_range("bytes=900-", 1000), RangeRequest::Partial(vec![(900, 999)]));
+        assert_eq!(parse_range("bytes=-100", 1000), RangeRequest::Partial(vec![(900, 999)]));
+        assert_eq!(parse_range("bytes=990-2000", 1000), RangeRequest::Partial(vec![(990, 999)]));
+        assert_eq!(parse_range("bytes=0-0, -1", 1000), RangeRequest::Partial(vec![(0, 0), (999, 999)]));
+        assert_eq!(parse_range("bytes=1000-", 1000), RangeRequest::Unsatisfiable);
+        assert_eq!(parse_range("bytes=5-1", 1000), RangeRequest::Full);
+        assert_eq!(parse_range("items=0-1", 1000), RangeRequest::Full);
+    }
+
+    #[test]
+    fn evaluate_conditional_headers() {
+        let meta = LocalFileMeta {
+            len: 10,
+            modified: parse_http_date("Sun, 06 Nov 1994 08:49:37 GMT")
+        };
+        let header = |name: &str, value: &str| vec![HttpHeader::new(name, value.as_bytes())];
+
+        assert!(is_not_modified(&header("If-None-Match", &format!("W/{}", meta.etag())), &meta));
+        assert!(!is_not_modified(&header("If-None-Match", "\"other\""), &meta));
+        assert!(is_not_modified(&header("If-Modified-Since", "Sun, 06 Nov 1994 08:49:37 GMT"), &meta));
+        assert!(!is_not_modified(&header("If-Modified-Since", "Sat, 05 Nov 1994 08:49:37 GMT"), &meta));
+
+        assert!(is_range_applicable(&header("If-Range", &meta.etag()), &meta));
+        assert!(!is_range_applicable(&header("If-Range", &format!("W/{}", meta.etag())), &meta));
+        assert!(!is_range_applicable(&header("If-Range", "Mon, 07 Nov 1994 08:49:37 GMT"), &meta));
+    }
+
+    #[test]
+    fn guard_directory_mapping_root() {
+        let dir = std::env::temp_dir().join(format!("lopxy-local-test-{}", std::process::id()));
+        std::fs::create_dir_all(dir.join("assets/css")).unwrap();
+        std::fs::write(dir.join("assets/css/a.css"), "a {}").unwrap();
+
+        let mut item = super::super::item::ProxyItem::new("http://host/assets/", &format!("file://{}/assets/", dir.display()), "text/plain");
+        item.update_match_kind(super::super::item::MatchKind::Prefix);
+        assert!(item.compile_matcher());
+
+        let resolved = item.resolve("http://host/assets/css/a.css").unwrap();
+        let root = resolve_local_file_path(&resolved.proxy_resource_root_url()).unwrap();
+        assert!(is_path_inside(&resolve_local_file_path(resolved.proxy_resource_url()).unwrap(), &root));
+
+        let escaped = item.resolve("http://host/assets/..%2F..%2Fetc/passwd").unwrap();
+        assert!(!is_path_inside(&resolve_local_file_path(escaped.proxy_resource_url()).unwrap(), &root));
+
+        // a symlink pointing outside of the root escapes the mapping
+        #[cfg(unix)]
+        {
+            std::os::unix::fs::symlink("/etc", dir.join("assets/etc")).unwrap();
+            let linked = item.resolve("http://host/assets/etc/passwd").unwrap();
+            assert!(!is_path_inside(&resolve_local_file_path(linked.proxy_resource_url()).unwrap(), &root));
+        }
+
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+
+}
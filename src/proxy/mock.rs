@@ -0,0 +1,280 @@
+use serde_derive::{Serialize, Deserialize};
+
+use super::content_type;
+
+/// Default status code of mock response
+pub const DEFAULT_MOCK_STATUS: u16 = 200;
+
+fn default_mock_status() -> u16 {
+    DEFAULT_MOCK_STATUS
+}
+
+///
+/// What the target of proxy item is
+///
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum TargetKind {
+    /// `proxy_resource_url` and the mirrors are requested
+    #[default]
+    Url,
+    /// The mock response of proxy item is answered directly
+    Mock,
+}
+
+impl std::str::FromStr for TargetKind {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<TargetKind, String> {
+        match s.to_lowercase().as_str() {
+            "url" => Ok(TargetKind::Url),
+            "mock" => Ok(TargetKind::Mock),
+            _ => Err(format!("unknown target kind : {}", s))
+        }
+    }
+}
+
+impl std::fmt::Display for TargetKind {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", match self {
+            TargetKind::Url => "url",
+            TargetKind::Mock => "mock",
+        })
+    }
+}
+
+///
+/// How the body of mock response is written
+///
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, Default)]
+#[serde(rename_all = "kebab-case")]
+pub enum MockBodyEncoding {
+    /// The body is the text itself
+    #[default]
+    Text,
+    /// The body is base64 encoded
+    Base64,
+    /// The body is a `data:` url, its media type is used as content type if no `content-type` header is set
+    DataUrl,
+}
+
+impl std::str::FromStr for MockBodyEncoding {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<MockBodyEncoding, String> {
+        match s.to_lowercase().as_str() {
+            "text" => Ok(MockBodyEncoding::Text),
+            "base64" => Ok(MockBodyEncoding::Base64),
+            "data-url" => Ok(MockBodyEncoding::DataUrl),
+            _ => Err(format!("unknown mock body encoding : {}", s))
+        }
+    }
+}
+
+impl std::fmt::Display for MockBodyEncoding {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", match self {
+            MockBodyEncoding::Text => "text",
+            MockBodyEncoding::Base64 => "base64",
+            MockBodyEncoding::DataUrl => "data-url",
+        })
+    }
+}
+
+///
+/// Parse a `data:` url, return the media type and the decoded data
+///
+/// # Notes
+/// The media type is None if it's omitted, the data is percent decoded unless it's base64
+pub fn parse_data_url(url: &str) -> Option<(Option<String>, Vec<u8>)> {
+    let rest = url.trim();
+    if rest.len() < 5 || !rest[..5].eq_ignore_ascii_case("data:") {
+        return None;
+    }
+
+    let (meta, data) = rest[5..].split_once(',')?;
+    let (media_type, base64) = match meta.strip_suffix(";base64").or(meta.strip_suffix(";BASE64")) {
+        Some(media_type) => (media_type, true),
+        None => (meta, false)
+    };
+
+    let data = match base64 {
+        true => base64::decode(urlencoding::decode(data).ok()?.trim()).ok()?,
+        false => urlencoding::decode_binary(data.as_bytes()).into_owned()
+    };
+
+    let media_type = media_type.trim();
+    match media_type.is_empty() {
+        true => Some((None, data)),
+        false => Some((Some(media_type.to_string()), data))
+    }
+}
+
+///
+/// Response answered by a mock target, nothing is read from disk or network
+///
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct MockResponse {
+    #[serde(default = "default_mock_status")]
+    pub status: u16,
+    /// Headers like `Access-Control-Allow-Origin: *`, `content-length` is always computed by lopxy
+    #[serde(default)]
+    pub headers: Vec<String>,
+    #[serde(default)]
+    pub body: String,
+    #[serde(default)]
+    pub body_encoding: MockBodyEncoding,
+}
+
+impl Default for MockResponse {
+    fn default() -> MockResponse {
+        MockResponse {
+            status: DEFAULT_MOCK_STATUS,
+            headers: vec![],
+            body: String::new(),
+            body_encoding: MockBodyEncoding::Text
+        }
+    }
+}
+
+impl MockResponse {
+    ///
+    /// Parse the headers into name and value pairs, None if any of them is invalid
+    ///
+    pub fn header_pairs(&self) -> Option<Vec<(String, String)>> {
+        self.headers.iter().
+            map(|header: &String| {
+                let (name, value) = header.split_once(':')?;
+                let name = name.trim();
+                let valid = !name.is_empty() &&
+                    reqwest::header::HeaderName::from_bytes(name.as_bytes()).is_ok() &&
+                    reqwest::header::HeaderValue::from_str(value.trim()).is_ok();
+
+                match valid {
+                    true => Some((name.to_string(), value.trim().to_string())),
+                    false => None
+                }
+            }).
+            collect()
+    }
+
+    ///
+    /// Decode the body, return it with the media type of `data:` url if any
+    ///
+    pub fn decode_body(&self) -> Option<(Vec<u8>, Option<String>)> {
+        match self.body_encoding {
+            MockBodyEncoding::Text => Some((self.body.as_bytes().to_vec(), None)),
+            MockBodyEncoding::Base64 => Some((base64::decode(self.body.trim()).ok()?, None)),
+            MockBodyEncoding::DataUrl => parse_data_url(&self.body).map(|(media_type, data): (Option<String>, Vec<u8>)| (data, media_type))
+        }
+    }
+
+    ///
+    /// Check whether the status, headers and body are valid
+    ///
+    pub fn verify(&self) -> bool {
+        (100..1000).contains(&self.status) && self.header_pairs().is_some() && self.decode_body().is_some()
+    }
+
+    ///
+    /// Build the raw response bytes of mock response
+    ///
+    /// # Notes
+    /// Without a `content-type` header, the content type is the media type of `data:` url, then
+    /// `content_type` of proxy item unless it's `auto`, then the sniffed one. Framing headers are
+    /// replaced by `content-length`, the body is omitted for HEAD request
+    pub fn build_raw_response(&self, request_method: &str, item_content_type: &str) -> Option<Vec<u8>> {
+        let headers = self.header_pairs()?;
+        let (body, media_type) = self.decode_body()?;
+
+        let reason = reqwest::StatusCode::from_u16(self.status).ok().
+            and_then(|status: reqwest::StatusCode| status.canonical_reason()).
+            unwrap_or("Unknown");
+
+        let mut raw_response_bytes: Vec<u8> = format!("HTTP/1.1 {} {}\r\n", self.status, reason).into_bytes();
+
+        for (name, value) in headers.iter() {
+            if name.eq_ignore_ascii_case("content-length") ||
+                name.eq_ignore_ascii_case("transfer-encoding") ||
+                name.eq_ignore_ascii_case("connection") {
+                continue;
+            }
+
+            raw_response_bytes.extend_from_slice(format!("{}: {}\r\n", name, value).as_bytes());
+        }
+
+        let has_content_type = headers.iter().any(|(name, _): &(String, String)| name.eq_ignore_ascii_case("content-type"));
+        let content_type = match has_content_type || body.is_empty() {
+            true => None,
+            false => media_type.
+                or(Some(item_content_type.to_string()).filter(|content_type: &String| !content_type.is_empty() && !content_type::is_auto_content_type(content_type))).
+                or(content_type::sniff(&body[..std::cmp::min(body.len(), content_type::SNIFF_SIZE)]).map(String::from))
+        };
+
+        if let Some(content_type) = content_type {
+            raw_response_bytes.extend_from_slice(format!("content-type: {}\r\n", content_type).as_bytes());
+        }
+
+        let bodiless = (100..200).contains(&self.status) || self.status == 204 || self.status == 304;
+        if !bodiless {
+            raw_response_bytes.extend_from_slice(format!("content-length: {}\r\n", body.len()).as_bytes());
+        }
+
+        raw_response_bytes.extend_from_slice(b"\r\n");
+
+        if !bodiless && !request_method.eq_ignore_ascii_case("HEAD") {
+            raw_response_bytes.extend_from_slice(&body);
+        }
+
+        Some(raw_response_bytes)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn build_mock_responses() {
+        assert_eq!(parse_data_url("data:text/plain;charset=utf-8,hello%20world"), Some((Some("text/plain;charset=utf-8".to_string()), b"hello world".to_vec())));
+        assert_eq!(parse_data_url("data:;base64,aGk="), Some((None, b"hi".to_vec())));
+        assert_eq!(parse_data_url("http://a.com/"), None);
+
+        let no_content = MockResponse {
+            status: 204,
+            headers: vec!["Access-Control-Allow-Origin: *".to_string()],
+            ..MockResponse::default()
+        };
+        assert_eq!(
+            no_content.build_raw_response("GET", "auto").unwrap(),
+            b"HTTP/1.1 204 No Content\r\nAccess-Control-Allow-Origin: *\r\n\r\n".to_vec()
+        );
+
+        let json = MockResponse {
+            headers: vec!["Content-Length: 100".to_string()],
+            body: "eyJvayI6dHJ1ZX0=".to_string(),
+            body_encoding: MockBodyEncoding::Base64,
+            ..MockResponse::default()
+        };
+        assert_eq!(
+            json.build_raw_response("GET", "application/json").unwrap(),
+            b"HTTP/1.1 200 OK\r\ncontent-type: application/json\r\ncontent-length: 11\r\n\r\n{\"ok\":true}".to_vec()
+        );
+        assert_eq!(
+            json.build_raw_response("HEAD", "application/json").unwrap(),
+            b"HTTP/1.1 200 OK\r\ncontent-type: application/json\r\ncontent-length: 11\r\n\r\n".to_vec()
+        );
+
+        let module = MockResponse {
+            body: "data:text/javascript,export%20default%20%7B%7D".to_string(),
+            body_encoding: MockBodyEncoding::DataUrl,
+            ..MockResponse::default()
+        };
+        assert!(module.verify());
+        assert!(String::from_utf8(module.build_raw_response("GET", "auto").unwrap()).unwrap().ends_with("content-type: text/javascript\r\ncontent-length: 17\r\n\r\nexport default {}"));
+
+        assert!(!MockResponse { headers: vec!["no colon".to_string()], ..MockResponse::default() }.verify());
+        assert!(!MockResponse { status: 42, ..MockResponse::default() }.verify());
+        assert!(!MockResponse { body: "%%%".to_string(), body_encoding: MockBodyEncoding::Base64, ..MockResponse::default() }.verify());
+    }
+}
@@ -1,10 +1,25 @@
 #![allow(dead_code)]
 
 pub mod item;
+pub mod auth;
+pub mod body;
+pub mod bypass;
+pub mod ca;
+pub mod cache;
+pub mod canonical;
+pub mod condition;
+pub mod content_type;
 pub mod controller;
+pub mod header;
+pub mod health;
+pub mod local;
+pub mod mock;
+pub mod pac;
 pub mod request;
 pub mod response;
 pub mod stream;
+pub mod tls;
+pub mod upstream;
 
 pub use async_shutdown;
 
@@ -212,8 +227,11 @@ impl ProxyConfig {
 }
 
 pub struct ProxyClient {
-    pub stream: TcpStream,
+    pub stream: tls::ClientStream,
+    pub reader: stream::HttpMessageReader,
     pub addr: SocketAddr,
+    pub tunnel_host: Option<String>,
+    pub username: Option<String>,
     pub shutdown: async_shutdown::Shutdown,
     pub system_proxy_config: ProxyConfig,
     pub controller: LopxyProxyServerControllerArc
@@ -221,13 +239,90 @@ pub struct ProxyClient {
 
 impl ProxyClient {
     pub fn proxy_redirect(&self, url: &str) -> Option<item::ProxyItem> {
-        self.controller.lock().unwrap().proxy_redirect(url)
+        self.controller.lock().unwrap().proxy_redirect(url, self.username.as_deref())
+    }
+
+    ///
+    /// Get the header rules of request url and its proxy item
+    ///
+    pub fn header_rules(&self, url: &str, item: Option<&item::ProxyItem>) -> header::HeaderRules {
+        self.controller.lock().unwrap().header_rules(url, item)
+    }
+
+    ///
+    /// Get the offline cache if the url is in its scope
+    ///
+    pub fn response_cache(&self, url: &str) -> Option<std::sync::Arc<cache::ResponseCache>> {
+        self.controller.lock().unwrap().response_cache(url)
+    }
+
+    ///
+    /// Authenticate the client by `Proxy-Authorization` of request, return false if it should be rejected
+    ///
+    /// # Notes
+    /// The user is remembered for the following requests of the connection, loopback clients
+    /// without credential go on anonymously
+    pub fn authenticate(&mut self, request_header: &stream::HttpRequestHead) -> bool {
+        let credential = stream::find_header(&request_header.headers, "proxy-authorization").
+            and_then(|header: &stream::HttpHeader| auth::parse_basic_credential(&header.value_str()));
+
+        let mut controller = self.controller.lock().unwrap();
+        if !controller.proxy_auth_required() {
+            return true;
+        }
+
+        match credential {
+            Some((username, password)) => {
+                if !controller.authenticate_proxy_user(&username, &password) {
+                    eprintln!("proxy client {} authentication failed with user {}", self.addr, username);
+                    return false;
+                }
+
+                self.username = Some(username);
+                true
+            },
+            None => self.username.is_some() || super::util::network::canonical_ip(&self.addr.ip()).is_loopback()
+        }
+    }
+
+    ///
+    /// Get the TLS server config to intercept https connection to `host`, None if it should be tunneled
+    ///
+    pub fn https_interception(&self, host: &str, port: u16) -> Option<std::sync::Arc<rustls::ServerConfig>> {
+        self.controller.lock().unwrap().https_interception(host, port)
     }
 
     pub fn use_system_proxy(&self) -> bool {
         self.system_proxy_config.proxy_server.is_some() && self.system_proxy_config.enabled()
     }
 
+    ///
+    /// Get the route to `url`, `item` is the proxy item whose target is requested
+    ///
+    /// # Notes
+    /// The system proxy replaced by lopxy is used if neither the proxy item, lopxy config nor
+    /// environment variables set an upstream proxy, hosts in its proxy override go direct
+    pub fn upstream_route(&self, item: Option<&item::ProxyItem>, url: &str) -> upstream::UpstreamRoute {
+        if let Some(route) = self.controller.lock().unwrap().upstream_route(item, url) {
+            return route;
+        }
+
+        if !self.use_system_proxy() {
+            return upstream::UpstreamRoute::Direct;
+        }
+
+        // proxy override of the replaced system proxy, e.g. `<local>;*.internal.com`
+        let bypass_list = bypass::BypassList::parse(self.system_proxy_config.proxy_override.as_deref().unwrap_or(""));
+        if bypass_list.matches_url(url) {
+            return upstream::UpstreamRoute::Direct;
+        }
+
+        self.system_proxy_config.proxy_server.as_deref().
+            and_then(upstream::UpstreamProxy::parse).
+            map(upstream::UpstreamRoute::Proxy).
+            unwrap_or(upstream::UpstreamRoute::Direct)
+    }
+
     pub async fn reply(&mut self, raw_response_bytes: &[u8]) -> std::io::Result<()> {
         match self.stream.write_all(raw_response_bytes).await {
             Ok(_) => Ok(()),
@@ -238,68 +333,118 @@ impl ProxyClient {
         }
     }
 
+    pub async fn reply_400(&mut self) {
+        let raw_response_bytes = response::build_400_response();
+        let _ = self.reply(&raw_response_bytes).await;
+    }
+
     pub async fn reply_404(&mut self) {
         let raw_response_bytes = response::build_404_response();
         match self.reply(&raw_response_bytes).await { _ => {} };
     }
 
+    pub async fn reply_407(&mut self) {
+        let raw_response_bytes = response::build_407_response(auth::PROXY_AUTH_REALM);
+        let _ = self.reply(&raw_response_bytes).await;
+    }
+
+    pub async fn reply_431(&mut self) {
+        let raw_response_bytes = response::build_431_response();
+        let _ = self.reply(&raw_response_bytes).await;
+    }
+
     pub async fn reply_502(&mut self) {
         let raw_response_bytes = response::build_502_response();
         match self.reply(&raw_response_bytes).await { _ => {} };
     }
 }
 
+#[derive(Clone)]
 pub struct Proxy {
     pub system_proxy_config: ProxyConfig,
     pub proxy_config: ProxyConfig,
+    pub proxy_servers: Vec<String>,
+    pub socks_servers: Vec<String>,
     pub shutdown: async_shutdown::Shutdown,
     pub controller: LopxyProxyServerControllerArc
 }
 
 impl Proxy {
-    fn build(system_proxy_config: ProxyConfig, proxy_config: ProxyConfig, shutdown: async_shutdown::Shutdown, controller: LopxyProxyServerControllerArc) -> Proxy {
+    fn build(system_proxy_config: ProxyConfig, proxy_config: ProxyConfig, proxy_servers: Vec<String>, socks_servers: Vec<String>, shutdown: async_shutdown::Shutdown, controller: LopxyProxyServerControllerArc) -> Proxy {
         Proxy {
             system_proxy_config,
             proxy_config,
+            proxy_servers,
+            socks_servers,
             shutdown: shutdown,
             controller
         }
     }
 
-    async fn launch(mut proxy: Proxy) -> std::io::Result<()> {
-        let proxy_server_addr = proxy.proxy_config.proxy_server.as_ref().expect("invalid proxy server address");
-        let server = TcpListener::bind(proxy_server_addr).await.expect("bind proxy server failed");
-    
-        println!("lopxy proxy server binding in {}", proxy_server_addr);
-    
-        let proxy_server_shutdown = proxy.shutdown.clone();
-        let server_accept_loop = std::thread::spawn(move || async move {
+    ///
+    /// Accept clients of `server`, clients out of the allowlist are closed immediately
+    ///
+    fn spawn_accept_loop(mut proxy: Proxy, server: TcpListener, socks: bool) -> std::thread::JoinHandle<impl futures::Future<Output = ()>> {
+        let server_name = if socks { "socks5 proxy server" } else { "proxy server" };
+
+        std::thread::spawn(move || async move {
             while let Some(connection) = proxy.shutdown.wrap_cancel(server.accept()).await {
                 match connection {
                     Ok((stream, addr)) => {
+                        if !proxy.controller.lock().unwrap().is_client_allowed(&addr.ip()) {
+                            eprintln!("lopxy {} reject client {}", server_name, addr);
+                            continue;
+                        }
+
                         let client = proxy.build_client(stream, addr);
                         tokio::task::spawn(async move {
-                            controller::handle_lopxy_proxy_client(client).await;
+                            match socks {
+                                true => controller::sock::handle_socks_proxy_client(client).await,
+                                false => controller::handle_lopxy_proxy_client(client).await
+                            }
                         });
                     }
-    
+
                     Err(err) => {
-                        eprintln!("lopxy proxy server encountered IO error : {}", err);
+                        eprintln!("lopxy {} encountered IO error : {}", server_name, err);
                     }
                 }
             }
-        });
-    
-        proxy_server_shutdown.wait_shutdown_complete().await;
-        server_accept_loop.join().expect("lopxy server accept loop join exception").await;
+        })
+    }
+
+    async fn launch(proxy: Proxy) -> std::io::Result<()> {
+        let mut accept_loops = vec![];
+
+        for proxy_server_addr in &proxy.proxy_servers {
+            let server = TcpListener::bind(proxy_server_addr).await.expect("bind proxy server failed");
+            println!("lopxy proxy server binding in {}", proxy_server_addr);
+            accept_loops.push(Proxy::spawn_accept_loop(proxy.clone(), server, false));
+        }
+
+        for socks_server_addr in &proxy.socks_servers {
+            let socks_server = TcpListener::bind(socks_server_addr).await.expect("bind socks5 proxy server failed");
+            println!("lopxy socks5 proxy server binding in {}", socks_server_addr);
+            accept_loops.push(Proxy::spawn_accept_loop(proxy.clone(), socks_server, true));
+        }
+
+        let health_checker = tokio::task::spawn(health::run_health_checker(proxy.controller.clone(), proxy.shutdown.clone()));
+
+        proxy.shutdown.wait_shutdown_complete().await;
+        let _ = health_checker.await;
+        for accept_loop in accept_loops {
+            accept_loop.join().expect("lopxy server accept loop join exception").await;
+        }
         Ok(())
     }
 
-    pub async fn start(system_proxy_config: ProxyConfig, proxy_config: ProxyConfig, shutdown: async_shutdown::Shutdown, controller: LopxyProxyServerControllerArc) -> tokio::task::JoinHandle<()> {
+    pub async fn start(system_proxy_config: ProxyConfig, proxy_config: ProxyConfig, proxy_servers: Vec<String>, socks_servers: Vec<String>, shutdown: async_shutdown::Shutdown, controller: LopxyProxyServerControllerArc) -> tokio::task::JoinHandle<()> {
         tokio::task::spawn(async move {
             Proxy::launch(Proxy::build(
                 system_proxy_config,
                 proxy_config,
+                proxy_servers,
+                socks_servers,
                 shutdown,
                 controller
             )).await.expect("proxy server launch failed");
@@ -308,8 +453,11 @@ impl Proxy {
 
     pub fn build_client(&mut self, stream: TcpStream, addr: SocketAddr) -> ProxyClient {
         ProxyClient {
-            stream,
+            stream: tls::ClientStream::Plain(stream),
+            reader: stream::HttpMessageReader::new(),
             addr,
+            tunnel_host: None,
+            username: None,
             shutdown: self.shutdown.clone(),
             system_proxy_config: self.system_proxy_config.clone(),
             controller: self.controller.clone()
@@ -0,0 +1,88 @@
+use super::item::ProxyItem;
+
+/// Content type of proxy auto-config file
+pub const PAC_CONTENT_TYPE: (&str, &str) = ("application", "x-ns-proxy-autoconfig");
+
+///
+/// Collect the host patterns of proxy items, a single `*` if any item may match all hosts
+///
+pub fn host_patterns(items: &[ProxyItem]) -> Vec<String> {
+    let mut patterns: Vec<String> = items.iter().filter_map(|item: &ProxyItem| item.host_pattern()).collect();
+
+    if patterns.iter().any(|pattern: &String| pattern == "*") {
+        return vec!["*".to_string()];
+    }
+
+    patterns.sort();
+    patterns.dedup();
+    patterns
+}
+
+///
+/// Generate proxy auto-config script which sends hosts of proxy items to lopxy and the others DIRECT
+///
+/// # Notes
+/// `proxy_address` is like `127.0.0.1:7237`
+pub fn generate_pac(items: &[ProxyItem], proxy_address: &str) -> String {
+    let patterns = host_patterns(items);
+    let proxy = format!("PROXY {}", proxy_address);
+
+    format!(
+r#"// generated by lopxy, hosts with proxy items go through lopxy and the others go DIRECT
+var lopxyHostPatterns = {};
+
+function FindProxyForURL(url, host) {{
+    host = host.toLowerCase();
+    if (host.charAt(0) == "[") {{
+        host = host.substring(1, host.length - 1);
+    }}
+
+    for (var i = 0; i < lopxyHostPatterns.length; i++) {{
+        if (shExpMatch(host, lopxyHostPatterns[i])) {{
+            return {};
+        }}
+    }}
+
+    return "DIRECT";
+}}
+"#,
+        serde_json::to_string(&patterns).unwrap_or("[]".to_string()),
+        serde_json::to_string(&proxy).unwrap_or_default()
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use super::super::item::MatchKind;
+
+    fn proxy_item(match_kind: MatchKind, resource_url: &str) -> ProxyItem {
+        let mut item = ProxyItem::new(resource_url, "file:///srv/file", "auto");
+        item.update_match_kind(match_kind);
+        item
+    }
+
+    #[test]
+    fn collect_host_patterns() {
+        let items = vec![
+            proxy_item(MatchKind::Exact, "http://www.resource.com/file"),
+            proxy_item(MatchKind::Prefix, "https://www.resource.com:8443/assets/"),
+            proxy_item(MatchKind::Glob, "http*://*.cdn.com:*/libs/**"),
+            proxy_item(MatchKind::Regex, r"https://api\.example\.com/v(\d+)/.*"),
+            proxy_item(MatchKind::Exact, "http://[::1]:8080/file")
+        ];
+
+        assert_eq!(host_patterns(&items), vec!["*.cdn.com", "::1", "api.example.com", "www.resource.com"]);
+
+        let items = vec![
+            proxy_item(MatchKind::Exact, "http://www.resource.com/file"),
+            proxy_item(MatchKind::Regex, r"https?://(.*)\.example\.com/.*")
+        ];
+
+        assert_eq!(host_patterns(&items), vec!["*"]);
+
+        let pac = generate_pac(&items, "127.0.0.1:7237");
+        assert!(pac.contains(r#"var lopxyHostPatterns = ["*"];"#));
+        assert!(pac.contains(r#"return "PROXY 127.0.0.1:7237";"#));
+    }
+}
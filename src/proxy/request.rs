@@ -1,3 +1,13 @@
+use super::stream::*;
+use super::item::{ProxyItem, ProxyTarget};
+use super::body::BodyRule;
+use super::condition::ThrottledStream;
+use super::header::{HeaderRewriteStream, HeaderRules};
+use super::tls::ClientStream;
+
+/// Request headers which aren't sent to upstream unless a request header rule names them
+const DROPPED_REQUEST_HEADERS: [&str; 6] = ["postman-token", "user-agent", "accept", "accept-encoding", "cache-control", "proxy-authorization"];
+
 #[allow(dead_code)]
 pub struct LopxyProxyRequest<'a> {
     pub https: bool,
@@ -5,9 +15,14 @@ pub struct LopxyProxyRequest<'a> {
     pub method: String,
     pub client_port: u16,
     pub pid: u32,
-    pub request_bytes: &'a Vec<u8>,
-    pub request_header: httparse::Request<'a, 'a>,
-    pub client: super::ProxyClient,
+    pub request_header: HttpRequestHead,
+    /// Bytes per second of the response stream, 0 means unlimited
+    pub bandwidth: u64,
+    /// Header rules of the request url and its proxy item
+    pub header_rules: HeaderRules,
+    /// Body rules of the proxy item, applied to the successful text responses
+    pub body_rules: Vec<BodyRule>,
+    pub client: &'a mut super::ProxyClient,
 }
 
 impl LopxyProxyRequest<'_> {
@@ -16,18 +31,19 @@ impl LopxyProxyRequest<'_> {
     /// Try to get request url
     /// 
     pub fn try_request_url(&self) -> Option<String> {
-        match self.request_header.path {
-            Some(url) => Some(url.to_string()),
-            None => None
+        if self.request_header.path.is_empty() {
+            return None;
         }
+
+        Some(self.request_header.path.clone())
     }
 
     ///
     /// Get request url
     /// 
     pub fn request_url(&self) -> String {
-        if let Some(url) = self.request_header.path {
-            return url.to_string();
+        if let Some(url) = self.try_request_url() {
+            return url;
         }
 
         return format!("http{}://{}", if self.https { "s" } else { "" }, self.host)
@@ -41,88 +57,169 @@ impl LopxyProxyRequest<'_> {
     }
 
     ///
-    /// Get request body length
+    /// Get request http version, 0 for HTTP/1.0 and 1 for HTTP/1.1
     /// 
-    pub fn content_length(&self) -> usize {
-        for header in self.request_header.headers.iter() {
-            if header.name.eq_ignore_ascii_case("content-length") {
-                return String::from_utf8_lossy(header.value).parse().unwrap_or(0);
-            }
-        }
-
-        0
+    pub fn version(&self) -> u8 {
+        self.request_header.version
     }
 
     ///
-    /// Copy request body
+    /// Check whether the client wants to keep the connection for subsequent requests
     /// 
-    pub fn body(&self) -> Vec<u8> {
-        let mut body = vec![0; 0];
-        let content_length = self.content_length();
+    /// # Notes
+    /// HTTP/1.1 connection is persistent by default, HTTP/1.0 connection only when `keep-alive` is requested
+    pub fn keep_alive(&self) -> bool {
+        let headers = &self.request_header.headers;
 
-        if content_length == 0 || content_length >= self.request_bytes.len() {
-            return body;
+        if header_has_token(headers, "connection", "close") || header_has_token(headers, "proxy-connection", "close") {
+            return false;
         }
 
-        body.extend_from_slice(&self.request_bytes.as_slice()[self.request_bytes.len() - content_length..]);
-        body
+        self.version() >= 1 ||
+            header_has_token(headers, "connection", "keep-alive") ||
+            header_has_token(headers, "proxy-connection", "keep-alive")
+    }
+
+    ///
+    /// Get request body length
+    /// 
+    pub fn body_length(&self) -> BodyLength {
+        self.client.reader.body_length()
+    }
+
+    ///
+    /// Read the entire request body
+    /// 
+    pub async fn body(&mut self) -> Result<Vec<u8>, HttpStreamError> {
+        self.client.reader.read_body(&mut self.client.stream).await
     }
     
     ///
     /// Collect request headers, ignore `host` header
     /// 
+    /// # Notes
+    /// The framing headers are always dropped, the other dropped headers like `user-agent` are kept if
+    /// a request header rule names them, the rules are applied at last
     pub fn headers(&self) -> reqwest::header::HeaderMap {
         let mut headers = reqwest::header::HeaderMap::new();
 
-        let check_header = |name: &str| -> bool {
-            !{
-                name.eq_ignore_ascii_case("host") ||
-                name.eq_ignore_ascii_case("postman-token") ||
-                name.eq_ignore_ascii_case("user-agent") ||
-                name.eq_ignore_ascii_case("accept") ||
-                name.eq_ignore_ascii_case("accept-encoding") ||
-                name.eq_ignore_ascii_case("cache-control") ||
-                name.eq_ignore_ascii_case("connection")
-            }
+        let is_dropped = |name: &str| -> bool {
+            super::header::is_framing_header(name) || (
+                DROPPED_REQUEST_HEADERS.iter().any(|dropped: &&str| dropped.eq_ignore_ascii_case(name)) &&
+                    !self.header_rules.names_request_header(name)
+            )
         };
-        
+
         for header in self.request_header.headers.iter() {
-            if !check_header(header.name) {
+            if is_dropped(&header.name) {
                 continue;
             }
 
-            let name = match reqwest::header::HeaderName::from_bytes(header.name.as_ref()) {
+            let name = match reqwest::header::HeaderName::from_bytes(header.name.as_bytes()) {
                 Ok(name) => name,
                 Err(_) => {
                     continue;
                 }
             };
 
-            let value = match reqwest::header::HeaderValue::from_bytes(header.value) {
+            let value = match reqwest::header::HeaderValue::from_bytes(&header.value) {
                 Ok(value) => value,
                 Err(_) => {
                     continue;
                 }
             };
 
-            headers.insert(name, value);
+            headers.append(name, value);
+        }
+
+        if self.header_rules.request.is_empty() {
+            return headers;
+        }
+
+        let mut header_pairs: Vec<(String, String)> = headers.iter().
+            map(|(name, value)| (name.as_str().to_string(), String::from_utf8_lossy(value.as_bytes()).to_string())).
+            collect();
+        self.header_rules.apply_request(&mut header_pairs);
+
+        let mut rewritten = reqwest::header::HeaderMap::new();
+        for (name, value) in header_pairs.iter() {
+            if let (Ok(name), Ok(value)) = (reqwest::header::HeaderName::from_bytes(name.as_bytes()), reqwest::header::HeaderValue::from_str(value)) {
+                rewritten.append(name, value);
+            }
         }
 
-        headers
+        rewritten
     }
 
     ///
     /// Report proxy request status
     /// 
     pub fn report_proxy_request_status(&self, request_url: &str, status: u16) {
-        self.client.controller.lock().unwrap().report_proxy_request_status(request_url, status, self.pid);
+        self.client.controller.lock().unwrap().report_proxy_request_status(request_url, status, self.pid, self.client.username.as_deref());
     }
     
     ///
     /// Report proxy connection error
     /// 
     pub fn report_connection_error(&self, host: &str, request_url: Option<String>, err: &dyn std::error::Error) {
-        self.client.controller.lock().unwrap().report_connection_error(host, request_url, err, self.pid);
+        self.client.controller.lock().unwrap().report_connection_error(host, request_url, err, self.pid, self.client.username.as_deref());
+    }
+
+    ///
+    /// Report fallback event, `reason` is None when the original resource works
+    /// 
+    pub fn report_fallback(&self, request_url: &str, reason: Option<String>) {
+        self.client.controller.lock().unwrap().report_fallback(request_url, reason, self.pid, self.client.username.as_deref());
+    }
+
+    ///
+    /// Report the network condition actions applied to this request
+    /// 
+    pub fn report_network_condition(&self, request_url: &str, action: String) {
+        self.client.controller.lock().unwrap().report_network_condition(request_url, action, self.pid, self.client.username.as_deref());
+    }
+
+    ///
+    /// Report the result of rewriting the response body
+    /// 
+    pub fn report_body_rewrite(&self, request_url: &str, result: String) {
+        self.client.controller.lock().unwrap().report_body_rewrite(request_url, result, self.pid, self.client.username.as_deref());
+    }
+
+    ///
+    /// Report the offline cache event of this request, like answering a stale copy
+    /// 
+    pub fn report_cache(&self, request_url: &str, event: String) {
+        self.client.controller.lock().unwrap().report_cache(request_url, event, self.pid, self.client.username.as_deref());
+    }
+
+    ///
+    /// Get the stream to write a response to proxy client, the response head is rewritten by the response
+    /// header rules and the stream is throttled by the network condition of this request
+    /// 
+    pub fn response_stream(&mut self) -> HeaderRewriteStream<'_, ThrottledStream<'_, ClientStream>> {
+        HeaderRewriteStream::new(ThrottledStream::new(&mut self.client.stream, self.bandwidth), &self.header_rules.response)
+    }
+
+    ///
+    /// Get the stream to write the body of a response whose head is already sent
+    /// 
+    pub fn response_body_stream(&mut self) -> ThrottledStream<'_, ClientStream> {
+        ThrottledStream::new(&mut self.client.stream, self.bandwidth)
+    }
+
+    ///
+    /// Get the targets of proxy item in the order to try
+    /// 
+    pub fn order_proxy_targets(&self, item: &ProxyItem) -> Vec<ProxyTarget> {
+        self.client.controller.lock().unwrap().order_proxy_targets(item)
+    }
+
+    ///
+    /// Report the health state of proxy target observed by this request
+    /// 
+    pub fn report_target_state(&self, target: &ProxyTarget, healthy: bool, latency: Option<u64>, error: Option<String>) {
+        self.client.controller.lock().unwrap().report_target_state(&target.template, healthy, latency, error);
     }
 }
 
@@ -137,6 +234,10 @@ pub fn get_uri_scheme(url: &str) -> Option<String> {
     Some(url.scheme().to_string())
 }
 
+pub fn is_uri_scheme(url: &str, scheme: &str) -> bool {
+    get_uri_scheme(url).filter(|s: &String| s.eq_ignore_ascii_case(scheme)).is_some()
+}
+
 pub fn get_uri_path(url: &str) -> Option<String> {
     let url = match url::Url::parse(url) {
         Ok(url) => url,
@@ -161,27 +262,43 @@ pub fn get_host_from_url(url: &str) -> Option<String> {
     }
 }
 
-pub fn get_host_from_request(request: &mut httparse::Request) -> Option<String> {
-    let mut host: String = String::new();
+///
+/// Get the absolute url of an origin form request tunneled to `tunnel_host`
+///
+/// # Notes
+/// The `host` header is preferred, the tunnel host may be a bare ip address
+pub fn get_origin_form_request_url(https: bool, tunnel_host: &str, request: &HttpRequestHead) -> String {
+    let authority = match find_header(&request.headers, "host").map(|h: &HttpHeader| h.value_str().trim().to_string()) {
+        Some(host) if !host.is_empty() => host,
+        _ => tunnel_host.to_string()
+    };
 
-    if !request.headers.iter().any(|h| {
-        if !h.name.eq_ignore_ascii_case("host") {
-            return false;
-        }
+    let (scheme, default_port) = if https { ("https", ":443") } else { ("http", ":80") };
+
+    format!("{}://{}{}", scheme, authority.trim_end_matches(default_port), request.path)
+}
 
-        host = match std::str::from_utf8(h.value) {
-            Ok(v) => v.to_string(),
+pub fn get_host_from_request(request: &HttpRequestHead) -> Option<String> {
+    let mut host = match find_header(&request.headers, "host") {
+        Some(h) => match std::str::from_utf8(&h.value) {
+            Ok(v) => v.trim().to_string(),
             Err(_) => {
-                return false;
+                return None;
             }
-        };
+        },
+        None => get_host_from_url(&request.path)?
+    };
 
-        true
-    }) {
-        host = get_host_from_url(request.path?)?;
+    if host.is_empty() {
+        return None;
     }
 
-    if !host.contains(":") {
+    let has_port = match host.rfind(']') {
+        Some(pos) => host[pos..].contains(':'),
+        None => host.contains(':')
+    };
+
+    if !has_port {
         host.push_str(":80");
     }
 
@@ -1,14 +1,3 @@
-pub fn http_version_desc(version: &reqwest::Version) -> &'static str {
-    match *version {
-        reqwest::Version::HTTP_09 => "HTTP/0.9",
-        reqwest::Version::HTTP_10 => "HTTP/1.0",
-        reqwest::Version::HTTP_11 => "HTTP/1.1",
-        reqwest::Version::HTTP_2 => "HTTP/2.0",
-        reqwest::Version::HTTP_3 => "HTTP/3.0",
-        _ => "HTTP/1.1"
-    }
-}
-
 pub fn get_exception_request_status_desc(status: u16) -> Option<String> {
     Some(match reqwest::StatusCode::from_u16(status) {
         Ok(status) => {
@@ -37,100 +26,121 @@ pub fn try_response_status(raw_response_bytes: &[u8]) -> Option<u16> {
     None
 }
 
-pub async fn build_raw_response_bytes(resp: reqwest::Response) -> reqwest::Result<Vec<u8>> {
-    let mut raw_response_bytes: Vec<u8> = vec![];
+///
+/// How the response body is framed to the proxy client
+/// 
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ResponseBodyFraming {
+    Empty,
+    Length,
+    Chunked,
+    UntilClose,
+}
+
+///
+/// Build raw response head
+/// 
+/// # Notes
+/// The body is de-chunked by reqwest, so the framing headers of upstream are dropped and rebuilt :
+/// keep `content-length` when upstream sends it, otherwise use chunked transfer coding for
+/// HTTP/1.1 client and close the connection for HTTP/1.0 client. The status line is always HTTP/1.1
+/// whatever the upstream version is, because the body is framed by lopxy
+pub fn build_raw_response_head(resp: &reqwest::Response, request_method: &str, client_version: u8) -> (Vec<u8>, ResponseBodyFraming) {
+    let mut raw_response_head: Vec<u8> = vec![];
+    let status = resp.status().as_u16();
+
+    let framing = if request_method.eq_ignore_ascii_case("HEAD") || (100..200).contains(&status) || status == 204 || status == 304 {
+        ResponseBodyFraming::Empty
+    } else if resp.headers().contains_key(reqwest::header::CONTENT_LENGTH) && !resp.headers().contains_key(reqwest::header::TRANSFER_ENCODING) {
+        ResponseBodyFraming::Length
+    } else if client_version >= 1 {
+        ResponseBodyFraming::Chunked
+    } else {
+        ResponseBodyFraming::UntilClose
+    };
 
     // response status
-    raw_response_bytes.extend_from_slice(format!("{} {}\r\n", http_version_desc(&resp.version()), resp.status()).as_bytes());
+    raw_response_head.extend_from_slice(format!("HTTP/1.1 {}\r\n", resp.status()).as_bytes());
 
     // response headers
     for (name, value) in resp.headers() {
-        raw_response_bytes.extend_from_slice(name.as_str().as_bytes());
-        raw_response_bytes.extend_from_slice(b": ");
-        raw_response_bytes.extend_from_slice(value.as_bytes());
-        raw_response_bytes.extend_from_slice(b"\r\n");
-    }
-    raw_response_bytes.extend_from_slice(b"\r\n");
+        if name == reqwest::header::TRANSFER_ENCODING ||
+            name == reqwest::header::CONNECTION ||
+            name.as_str().eq_ignore_ascii_case("keep-alive") {
+            continue;
+        }
 
-    // response body
-    raw_response_bytes.extend_from_slice(resp.bytes().await?.as_ref());
+        if name == reqwest::header::CONTENT_LENGTH && framing != ResponseBodyFraming::Length && framing != ResponseBodyFraming::Empty {
+            continue;
+        }
 
-    Ok(raw_response_bytes)
-}
+        raw_response_head.extend_from_slice(name.as_str().as_bytes());
+        raw_response_head.extend_from_slice(b": ");
+        raw_response_head.extend_from_slice(value.as_bytes());
+        raw_response_head.extend_from_slice(b"\r\n");
+    }
 
-pub fn build_404_response() -> Vec<u8> {
-    let mut raw_response_bytes: Vec<u8> = vec![];
+    match framing {
+        ResponseBodyFraming::Chunked => raw_response_head.extend_from_slice(b"transfer-encoding: chunked\r\n"),
+        ResponseBodyFraming::UntilClose => raw_response_head.extend_from_slice(b"connection: close\r\n"),
+        _ => {}
+    }
 
-    raw_response_bytes.extend_from_slice(b"HTTP/1.1 404 Not Found\r\ncontent-type: text/html; charset=utf-8\r\n\r\n");
-    raw_response_bytes.extend_from_slice(b"<html><head><title>404 Not Found</title></head>");
-    raw_response_bytes.extend_from_slice(b"<body style=\"background-color: #e62b60\"><center style=\"margin-top: 10%\"><h1>404 Not Found</h1></center></body></html>");
+    raw_response_head.extend_from_slice(b"\r\n");
 
-    raw_response_bytes
+    (raw_response_head, framing)
 }
 
-pub fn build_502_response() -> Vec<u8> {
-    let mut raw_response_bytes: Vec<u8> = vec![];
-
-    raw_response_bytes.extend_from_slice(b"HTTP/1.1 502 Bad Gateway\r\ncontent-type: text/html; charset=utf-8\r\n\r\n");
-    raw_response_bytes.extend_from_slice(b"<html><head><title>502 Bad Gateway</title></head>");
-    raw_response_bytes.extend_from_slice(b"<body style=\"background-color: #e62b60\"><center style=\"margin-top: 10%\"><h1>502 Bad Gateway</h1></center></body></html>");
-
-    raw_response_bytes
+///
+/// Build lopxy error page response
+/// 
+pub fn build_error_page_response(status: u16, reason: &str) -> Vec<u8> {
+    build_error_page_response_with_headers(status, reason, "")
 }
 
 ///
-/// Build local file response
+/// Build lopxy error page response with extra headers, each line of `headers` ends with CRLF
 /// 
-/// # Notes
-/// Just read the entire file directly
-pub fn build_local_file_response(local_file_uri: &str, content_type: &str) -> Vec<u8> {
-    if local_file_uri.len() == 0 {
-        return build_404_response();
-    }
+pub fn build_error_page_response_with_headers(status: u16, reason: &str, headers: &str) -> Vec<u8> {
+    let body = format!(
+        "<html><head><title>{0} {1}</title></head><body style=\"background-color: #e62b60\"><center style=\"margin-top: 10%\"><h1>{0} {1}</h1></center></body></html>",
+        status,
+        reason
+    );
 
-    //
-    // read entire file
-    //
+    let mut raw_response_bytes: Vec<u8> = vec![];
 
-    let mut file_path = match super::request::get_uri_path(local_file_uri) {
-        Some(fp) => fp,
-        None => {
-            return build_404_response();
-        }
-    };
+    raw_response_bytes.extend_from_slice(
+        format!("HTTP/1.1 {} {}\r\n{}content-type: text/html; charset=utf-8\r\ncontent-length: {}\r\n\r\n", status, reason, headers, body.len()).
+        as_bytes());
+    raw_response_bytes.extend_from_slice(body.as_bytes());
 
-    // decode urlencoding str
-    file_path = match urlencoding::decode(&file_path) {
-        Ok(c) => c.to_string(),
-        Err(_) => {
-            return build_404_response();
-        }
-    };
+    raw_response_bytes
+}
 
-    if cfg!(target_os = "windows") {
-        file_path = file_path[1..].to_string().replace("/", "\\")
-    }
+pub fn build_400_response() -> Vec<u8> {
+    build_error_page_response(400, "Bad Request")
+}
 
-    let file = std::path::Path::new(&file_path);
-    if !file.exists() {
-        return build_404_response();
-    }
+pub fn build_403_response() -> Vec<u8> {
+    build_error_page_response(403, "Forbidden")
+}
 
-    let file_buf = match std::fs::read(file) {
-        Ok(b) => b,
-        Err(_) => {
-            return build_404_response();
-        }
-    };
+pub fn build_404_response() -> Vec<u8> {
+    build_error_page_response(404, "Not Found")
+}
 
-    //
-    // build response
-    //
+///
+/// Build proxy authentication required response, it challenges the client with `Basic` scheme
+///
+pub fn build_407_response(realm: &str) -> Vec<u8> {
+    build_error_page_response_with_headers(407, "Proxy Authentication Required", &format!("proxy-authenticate: Basic realm=\"{}\"\r\n", realm))
+}
 
-    let mut raw_response_bytes: Vec<u8> = vec![];
-    raw_response_bytes.extend_from_slice(
-        format!("HTTP/1.1 202 OK\r\ncontent-length: {}\r\ncontent-type: {}\r\n\r\n", file_buf.len(), content_type).
-        as_bytes());
-    raw_response_bytes.extend_from_slice(file_buf.as_ref());
-    raw_response_bytes
-}
\ No newline at end of file
+pub fn build_431_response() -> Vec<u8> {
+    build_error_page_response(431, "Request Header Fields Too Large")
+}
+
+pub fn build_502_response() -> Vec<u8> {
+    build_error_page_response(502, "Bad Gateway")
+}
@@ -1,41 +1,787 @@
-use async_std::io::ReadExt;
-use async_std::net::TcpStream;
+use async_std::io::{Read, ReadExt, Write, WriteExt};
+
+/// Max size of a message head (request line/status line and all header fields)
+pub const MAX_HEAD_SIZE: usize = 64 * 1024;
+
+/// Max count of header fields in a message head
+pub const MAX_HEADER_COUNT: usize = 128;
+
+/// Max size of a chunk size line or a trailer line
+const MAX_LINE_SIZE: usize = 8 * 1024;
+
+/// Size of a single read from the stream
+const READ_BUFFER_SIZE: usize = 16 * 1024;
+
+#[derive(Debug)]
+pub enum HttpStreamError {
+    Io(std::io::Error),
+    Malformed(String),
+    HeadTooLarge,
+}
+
+impl HttpStreamError {
+    ///
+    /// Get the status code which should be replied to the peer
+    ///
+    pub fn status(&self) -> Option<u16> {
+        match self {
+            HttpStreamError::Io(_) => None,
+            HttpStreamError::Malformed(_) => Some(400),
+            HttpStreamError::HeadTooLarge => Some(431),
+        }
+    }
+}
+
+impl std::fmt::Display for HttpStreamError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            HttpStreamError::Io(err) => write!(f, "{}", err),
+            HttpStreamError::Malformed(desc) => write!(f, "malformed http message : {}", desc),
+            HttpStreamError::HeadTooLarge => write!(f, "http message head too large"),
+        }
+    }
+}
+
+impl std::error::Error for HttpStreamError {}
+
+impl From<std::io::Error> for HttpStreamError {
+    fn from(err: std::io::Error) -> HttpStreamError {
+        HttpStreamError::Io(err)
+    }
+}
+
+impl From<HttpStreamError> for std::io::Error {
+    fn from(err: HttpStreamError) -> std::io::Error {
+        match err {
+            HttpStreamError::Io(err) => err,
+            err => std::io::Error::new(std::io::ErrorKind::InvalidData, err.to_string()),
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct HttpHeader {
+    pub name: String,
+    pub value: Vec<u8>,
+}
+
+impl HttpHeader {
+    pub fn new(name: &str, value: &[u8]) -> HttpHeader {
+        HttpHeader {
+            name: name.to_string(),
+            value: value.to_vec()
+        }
+    }
+
+    pub fn value_str(&self) -> std::borrow::Cow<'_, str> {
+        String::from_utf8_lossy(&self.value)
+    }
+}
 
 ///
-/// collect tcp stream in Vec<u8> buffer until read size < buffer size
-/// 
-pub async fn collect_tcp_stream_buffer(stream: &mut TcpStream) -> std::io::Result<Vec<u8>> {
-    const BUFFER_SIZE: usize = 1024;
+/// Find the value of the first header named `name`
+///
+pub fn find_header<'a>(headers: &'a [HttpHeader], name: &str) -> Option<&'a HttpHeader> {
+    headers.iter().find(|h| h.name.eq_ignore_ascii_case(name))
+}
+
+///
+/// Check whether a comma separated header contains `token`
+///
+pub fn header_has_token(headers: &[HttpHeader], name: &str, token: &str) -> bool {
+    headers.iter().
+        filter(|h| h.name.eq_ignore_ascii_case(name)).
+        any(|h| h.value_str().split(',').any(|t| t.trim().eq_ignore_ascii_case(token)))
+}
+
+#[derive(Debug, Clone)]
+pub struct HttpRequestHead {
+    pub method: String,
+    pub path: String,
+    pub version: u8,
+    pub headers: Vec<HttpHeader>,
+    pub raw: Vec<u8>,
+}
+
+impl HttpRequestHead {
+    ///
+    /// Remove the headers named `name`, the raw head is rebuilt if any is removed
+    ///
+    pub fn remove_header(&mut self, name: &str) {
+        if find_header(&self.headers, name).is_none() {
+            return;
+        }
+
+        self.headers.retain(|h| !h.name.eq_ignore_ascii_case(name));
+
+        let mut raw = format!("{} {} HTTP/1.{}\r\n", self.method, self.path, self.version).into_bytes();
+        for header in &self.headers {
+            raw.extend_from_slice(header.name.as_bytes());
+            raw.extend_from_slice(b": ");
+            raw.extend_from_slice(&header.value);
+            raw.extend_from_slice(b"\r\n");
+        }
+        raw.extend_from_slice(b"\r\n");
+
+        self.raw = raw;
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct HttpResponseHead {
+    pub version: u8,
+    pub status: u16,
+    pub reason: String,
+    pub headers: Vec<HttpHeader>,
+    pub raw: Vec<u8>,
+}
+
+///
+/// How the body of a message is delimited
+///
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BodyLength {
+    Empty,
+    Length(u64),
+    Chunked,
+    UntilClose,
+}
+
+impl BodyLength {
+    ///
+    /// Body length of a request, see RFC 7230 section 3.3.3
+    ///
+    pub fn of_request(head: &HttpRequestHead) -> Result<BodyLength, HttpStreamError> {
+        match BodyLength::from_headers(&head.headers)? {
+            Some(length) => Ok(length),
+            None => Ok(BodyLength::Empty),
+        }
+    }
+
+    ///
+    /// Body length of a response to a request with `request_method`
+    ///
+    pub fn of_response(request_method: &str, head: &HttpResponseHead) -> Result<BodyLength, HttpStreamError> {
+        if request_method.eq_ignore_ascii_case("HEAD") ||
+            (100..200).contains(&head.status) ||
+            head.status == 204 ||
+            head.status == 304 {
+            return Ok(BodyLength::Empty);
+        }
+
+        match BodyLength::from_headers(&head.headers) {
+            Ok(Some(length)) => Ok(length),
+            Ok(None) => Ok(BodyLength::UntilClose),
+            Err(HttpStreamError::Malformed(_)) if find_header(&head.headers, "transfer-encoding").is_some() => Ok(BodyLength::UntilClose),
+            Err(err) => Err(err),
+        }
+    }
+
+    fn from_headers(headers: &[HttpHeader]) -> Result<Option<BodyLength>, HttpStreamError> {
+        // transfer-encoding overrides content-length
+        let mut transfer_encoding: Option<String> = None;
+        for header in headers.iter().filter(|h| h.name.eq_ignore_ascii_case("transfer-encoding")) {
+            for coding in header.value_str().split(',') {
+                let coding = coding.trim();
+                if !coding.is_empty() {
+                    transfer_encoding = Some(coding.to_ascii_lowercase());
+                }
+            }
+        }
+
+        if let Some(coding) = transfer_encoding {
+            return match coding.as_str() {
+                "chunked" => Ok(Some(BodyLength::Chunked)),
+                _ => Err(HttpStreamError::Malformed(format!("unsupported final transfer coding : {}", coding))),
+            };
+        }
+
+        let mut content_length: Option<u64> = None;
+        for header in headers.iter().filter(|h| h.name.eq_ignore_ascii_case("content-length")) {
+            for value in header.value_str().split(',') {
+                let value = value.trim();
+                if value.is_empty() || !value.bytes().all(|b| b.is_ascii_digit()) {
+                    return Err(HttpStreamError::Malformed(format!("invalid content-length : {}", value)));
+                }
+
+                let length: u64 = match value.parse() {
+                    Ok(length) => length,
+                    Err(_) => {
+                        return Err(HttpStreamError::Malformed(format!("invalid content-length : {}", value)));
+                    }
+                };
+
+                if content_length.is_some() && content_length != Some(length) {
+                    return Err(HttpStreamError::Malformed("conflicting content-length".to_string()));
+                }
+
+                content_length = Some(length);
+            }
+        }
+
+        Ok(content_length.map(|length| if length == 0 { BodyLength::Empty } else { BodyLength::Length(length) }))
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum ChunkState {
+    Size,
+    Data(u64),
+    DataEnd,
+    Trailers,
+}
+
+#[derive(Debug)]
+struct BodyDecoder {
+    length: BodyLength,
+    remaining: u64,
+    chunk: ChunkState,
+    done: bool,
+}
+
+///
+/// Incremental HTTP/1.x message reader of a connection
+///
+/// # Notes
+/// Bytes read past the end of a message are kept for the next message,
+/// so pipelined requests on the same connection are parsed in turn
+#[derive(Debug, Default)]
+pub struct HttpMessageReader {
+    buffer: Vec<u8>,
+    body: Option<BodyDecoder>,
+    trailers: Vec<HttpHeader>,
+}
+
+impl HttpMessageReader {
+    pub fn new() -> HttpMessageReader {
+        HttpMessageReader::default()
+    }
+
+    ///
+    /// Bytes already read from the stream but not consumed yet
+    ///
+    pub fn buffered(&self) -> &[u8] {
+        &self.buffer
+    }
+
+    ///
+    /// Take the bytes already read from the stream but not consumed yet
+    ///
+    pub fn take_buffered(&mut self) -> Vec<u8> {
+        std::mem::take(&mut self.buffer)
+    }
+
+    ///
+    /// Trailer fields of the last chunked body
+    ///
+    pub fn trailers(&self) -> &[HttpHeader] {
+        &self.trailers
+    }
+
+    ///
+    /// Read more bytes from the stream into the buffer, 0 means the peer closed the connection
+    ///
+    pub async fn fill<S: Read + Unpin>(&mut self, stream: &mut S) -> std::io::Result<usize> {
+        let mut buf = vec![0; READ_BUFFER_SIZE];
+
+        loop {
+            match stream.read(&mut buf).await {
+                Ok(read_byte_count) => {
+                    self.buffer.extend_from_slice(&buf[..read_byte_count]);
+                    return Ok(read_byte_count);
+                }
+
+                Err(ref err) if err.kind() == std::io::ErrorKind::Interrupted => {
+                    continue;
+                }
+
+                Err(err) => {
+                    return Err(err);
+                }
+            }
+        }
+    }
 
-    let mut full_buffer: Vec<u8> = vec![];
+    ///
+    /// Read the next request head, `None` means the peer closed the connection between messages
+    ///
+    pub async fn read_request_head<S: Read + Unpin>(&mut self, stream: &mut S) -> Result<Option<HttpRequestHead>, HttpStreamError> {
+        self.skip_body(stream).await?;
 
-    loop {
-        let mut buf = vec![0; BUFFER_SIZE];
+        loop {
+            // tolerate empty lines between pipelined requests
+            let leading = self.buffer.iter().take_while(|b| **b == b'\r' || **b == b'\n').count();
+            self.buffer.drain(..leading);
 
-        match stream.read(&mut buf).await {
-            Ok(0) => {
-                break;
+            if !self.buffer.is_empty() {
+                if let Some(head) = parse_request_head(&self.buffer)? {
+                    self.buffer.drain(..head.raw.len());
+                    self.body = Some(BodyDecoder::new(BodyLength::of_request(&head)?));
+                    return Ok(Some(head));
+                }
             }
 
-            Ok(read_byte_count) => {
-                full_buffer.extend_from_slice(&mut buf[..read_byte_count]);
+            if self.buffer.len() > MAX_HEAD_SIZE {
+                return Err(HttpStreamError::HeadTooLarge);
+            }
 
-                if read_byte_count < BUFFER_SIZE {
-                    break;
+            if self.fill(stream).await? == 0 {
+                if self.buffer.is_empty() {
+                    return Ok(None);
                 }
 
-                continue;
+                return Err(HttpStreamError::Io(std::io::Error::from(std::io::ErrorKind::UnexpectedEof)));
+            }
+        }
+    }
+
+    ///
+    /// Read the next response head of a response to a request with `request_method`
+    ///
+    pub async fn read_response_head<S: Read + Unpin>(&mut self, stream: &mut S, request_method: &str) -> Result<HttpResponseHead, HttpStreamError> {
+        self.skip_body(stream).await?;
+
+        loop {
+            if !self.buffer.is_empty() {
+                if let Some(head) = parse_response_head(&self.buffer)? {
+                    self.buffer.drain(..head.raw.len());
+                    self.body = Some(BodyDecoder::new(BodyLength::of_response(request_method, &head)?));
+                    return Ok(head);
+                }
             }
 
-            Err(ref err) if err.kind() == std::io::ErrorKind::Interrupted => {
-                continue;
+            if self.buffer.len() > MAX_HEAD_SIZE {
+                return Err(HttpStreamError::HeadTooLarge);
             }
 
-            Err(err) => {
-                return Err(err);
+            if self.fill(stream).await? == 0 {
+                return Err(HttpStreamError::Io(std::io::Error::from(std::io::ErrorKind::UnexpectedEof)));
             }
         }
     }
 
-    Ok(full_buffer)
-}
\ No newline at end of file
+    ///
+    /// Body length of the current message
+    ///
+    pub fn body_length(&self) -> BodyLength {
+        match &self.body {
+            Some(body) => body.length,
+            None => BodyLength::Empty,
+        }
+    }
+
+    ///
+    /// Check whether the body of the current message is entirely read
+    ///
+    pub fn is_body_done(&self) -> bool {
+        match &self.body {
+            Some(body) => body.done,
+            None => true,
+        }
+    }
+
+    ///
+    /// Read the next decoded piece of the current message body, `None` means the body is finished
+    ///
+    pub async fn read_body_chunk<S: Read + Unpin>(&mut self, stream: &mut S) -> Result<Option<Vec<u8>>, HttpStreamError> {
+        let mut body = match self.body.take() {
+            Some(body) => body,
+            None => return Ok(None),
+        };
+
+        let result = self.decode_body_chunk(stream, &mut body).await;
+        self.body = Some(body);
+        result
+    }
+
+    ///
+    /// Read the entire current message body
+    ///
+    pub async fn read_body<S: Read + Unpin>(&mut self, stream: &mut S) -> Result<Vec<u8>, HttpStreamError> {
+        let mut full_body: Vec<u8> = vec![];
+
+        while let Some(chunk) = self.read_body_chunk(stream).await? {
+            full_body.extend_from_slice(&chunk);
+        }
+
+        Ok(full_body)
+    }
+
+    ///
+    /// Discard the unread part of the current message body
+    ///
+    pub async fn skip_body<S: Read + Unpin>(&mut self, stream: &mut S) -> Result<(), HttpStreamError> {
+        while self.read_body_chunk(stream).await?.is_some() {}
+        self.body = None;
+        Ok(())
+    }
+
+    async fn decode_body_chunk<S: Read + Unpin>(&mut self, stream: &mut S, body: &mut BodyDecoder) -> Result<Option<Vec<u8>>, HttpStreamError> {
+        if body.done {
+            return Ok(None);
+        }
+
+        match body.length {
+            BodyLength::Empty => {
+                body.done = true;
+                Ok(None)
+            }
+
+            BodyLength::Length(_) => {
+                if self.buffer.is_empty() && self.fill(stream).await? == 0 {
+                    return Err(HttpStreamError::Io(std::io::Error::from(std::io::ErrorKind::UnexpectedEof)));
+                }
+
+                let take = std::cmp::min(body.remaining, self.buffer.len() as u64) as usize;
+                let chunk: Vec<u8> = self.buffer.drain(..take).collect();
+                body.remaining -= take as u64;
+                body.done = body.remaining == 0;
+
+                Ok(Some(chunk))
+            }
+
+            BodyLength::UntilClose => {
+                if self.buffer.is_empty() && self.fill(stream).await? == 0 {
+                    body.done = true;
+                    return Ok(None);
+                }
+
+                Ok(Some(std::mem::take(&mut self.buffer)))
+            }
+
+            BodyLength::Chunked => {
+                self.decode_chunked(stream, body).await
+            }
+        }
+    }
+
+    async fn decode_chunked<S: Read + Unpin>(&mut self, stream: &mut S, body: &mut BodyDecoder) -> Result<Option<Vec<u8>>, HttpStreamError> {
+        loop {
+            match body.chunk {
+                ChunkState::Size => {
+                    let line = self.read_line(stream).await?;
+                    let size = String::from_utf8_lossy(&line);
+                    let size = size.split(';').next().unwrap_or("").trim();
+                    let size = match u64::from_str_radix(size, 16) {
+                        Ok(size) => size,
+                        Err(_) => {
+                            return Err(HttpStreamError::Malformed(format!("invalid chunk size : {}", size)));
+                        }
+                    };
+
+                    body.chunk = if size == 0 {
+                        self.trailers.clear();
+                        ChunkState::Trailers
+                    } else {
+                        ChunkState::Data(size)
+                    };
+                }
+
+                ChunkState::Data(remaining) => {
+                    if self.buffer.is_empty() && self.fill(stream).await? == 0 {
+                        return Err(HttpStreamError::Io(std::io::Error::from(std::io::ErrorKind::UnexpectedEof)));
+                    }
+
+                    let take = std::cmp::min(remaining, self.buffer.len() as u64) as usize;
+                    let chunk: Vec<u8> = self.buffer.drain(..take).collect();
+                    let remaining = remaining - take as u64;
+
+                    body.chunk = if remaining == 0 { ChunkState::DataEnd } else { ChunkState::Data(remaining) };
+
+                    return Ok(Some(chunk));
+                }
+
+                ChunkState::DataEnd => {
+                    if !self.read_line(stream).await?.is_empty() {
+                        return Err(HttpStreamError::Malformed("missing chunk data terminator".to_string()));
+                    }
+
+                    body.chunk = ChunkState::Size;
+                }
+
+                ChunkState::Trailers => {
+                    let line = self.read_line(stream).await?;
+                    if line.is_empty() {
+                        body.done = true;
+                        return Ok(None);
+                    }
+
+                    if self.trailers.len() >= MAX_HEADER_COUNT {
+                        return Err(HttpStreamError::HeadTooLarge);
+                    }
+
+                    let line = String::from_utf8_lossy(&line).to_string();
+                    match line.split_once(':') {
+                        Some((name, value)) => {
+                            self.trailers.push(HttpHeader::new(name.trim(), value.trim().as_bytes()));
+                        }
+                        None => {
+                            return Err(HttpStreamError::Malformed(format!("invalid trailer field : {}", line)));
+                        }
+                    }
+                }
+            }
+        }
+    }
+
+    ///
+    /// Read a CRLF(or bare LF) terminated line, the terminator is not included
+    ///
+    async fn read_line<S: Read + Unpin>(&mut self, stream: &mut S) -> Result<Vec<u8>, HttpStreamError> {
+        loop {
+            if let Some(pos) = self.buffer.iter().position(|b| *b == b'\n') {
+                let mut line: Vec<u8> = self.buffer.drain(..=pos).collect();
+                line.pop();
+                if line.last() == Some(&b'\r') {
+                    line.pop();
+                }
+                return Ok(line);
+            }
+
+            if self.buffer.len() > MAX_LINE_SIZE {
+                return Err(HttpStreamError::HeadTooLarge);
+            }
+
+            if self.fill(stream).await? == 0 {
+                return Err(HttpStreamError::Io(std::io::Error::from(std::io::ErrorKind::UnexpectedEof)));
+            }
+        }
+    }
+}
+
+impl BodyDecoder {
+    fn new(length: BodyLength) -> BodyDecoder {
+        BodyDecoder {
+            length,
+            remaining: match length {
+                BodyLength::Length(length) => length,
+                _ => 0,
+            },
+            chunk: ChunkState::Size,
+            done: length == BodyLength::Empty,
+        }
+    }
+}
+
+fn collect_headers(headers: &[httparse::Header]) -> Vec<HttpHeader> {
+    headers.iter().map(|h| HttpHeader::new(h.name, h.value)).collect()
+}
+
+///
+/// Check whether `buffer` starts like an http request line, `None` means more bytes are needed
+///
+pub fn sniff_http_request(buffer: &[u8]) -> Option<bool> {
+    const METHODS: [&[u8]; 9] = [b"GET", b"HEAD", b"POST", b"PUT", b"DELETE", b"CONNECT", b"OPTIONS", b"TRACE", b"PATCH"];
+
+    for (i, byte) in buffer.iter().enumerate() {
+        if *byte == b' ' {
+            return Some(METHODS.contains(&&buffer[..i]));
+        }
+
+        if !byte.is_ascii_uppercase() || i >= b"OPTIONS".len() {
+            return Some(false);
+        }
+    }
+
+    None
+}
+
+///
+/// Try to parse a request head from the beginning of `buffer`, `None` means the head is partial
+///
+pub fn parse_request_head(buffer: &[u8]) -> Result<Option<HttpRequestHead>, HttpStreamError> {
+    let mut headers = vec![httparse::EMPTY_HEADER; MAX_HEADER_COUNT];
+    let mut req = httparse::Request::new(&mut headers);
+
+    match req.parse(buffer) {
+        Ok(httparse::Status::Complete(head_size)) => {
+            if head_size > MAX_HEAD_SIZE {
+                return Err(HttpStreamError::HeadTooLarge);
+            }
+
+            Ok(Some(HttpRequestHead {
+                method: req.method.unwrap_or("").to_string(),
+                path: req.path.unwrap_or("").to_string(),
+                version: req.version.unwrap_or(1),
+                headers: collect_headers(req.headers),
+                raw: buffer[..head_size].to_vec(),
+            }))
+        }
+
+        Ok(httparse::Status::Partial) => Ok(None),
+
+        Err(httparse::Error::TooManyHeaders) => Err(HttpStreamError::HeadTooLarge),
+
+        Err(err) => Err(HttpStreamError::Malformed(err.to_string())),
+    }
+}
+
+///
+/// Try to parse a response head from the beginning of `buffer`, `None` means the head is partial
+///
+pub fn parse_response_head(buffer: &[u8]) -> Result<Option<HttpResponseHead>, HttpStreamError> {
+    let mut headers = vec![httparse::EMPTY_HEADER; MAX_HEADER_COUNT];
+    let mut resp = httparse::Response::new(&mut headers);
+
+    match resp.parse(buffer) {
+        Ok(httparse::Status::Complete(head_size)) => {
+            if head_size > MAX_HEAD_SIZE {
+                return Err(HttpStreamError::HeadTooLarge);
+            }
+
+            Ok(Some(HttpResponseHead {
+                version: resp.version.unwrap_or(1),
+                status: resp.code.unwrap_or(0),
+                reason: resp.reason.unwrap_or("").to_string(),
+                headers: collect_headers(resp.headers),
+                raw: buffer[..head_size].to_vec(),
+            }))
+        }
+
+        Ok(httparse::Status::Partial) => Ok(None),
+
+        Err(httparse::Error::TooManyHeaders) => Err(HttpStreamError::HeadTooLarge),
+
+        Err(err) => Err(HttpStreamError::Malformed(err.to_string())),
+    }
+}
+
+///
+/// Encode a piece of body data in chunked transfer coding
+///
+pub fn encode_chunk(data: &[u8]) -> Vec<u8> {
+    let mut chunk: Vec<u8> = format!("{:x}\r\n", data.len()).into_bytes();
+    chunk.extend_from_slice(data);
+    chunk.extend_from_slice(b"\r\n");
+    chunk
+}
+
+///
+/// Encode the last chunk with trailer fields in chunked transfer coding
+///
+pub fn encode_last_chunk(trailers: &[HttpHeader]) -> Vec<u8> {
+    let mut chunk: Vec<u8> = b"0\r\n".to_vec();
+    for trailer in trailers {
+        chunk.extend_from_slice(trailer.name.as_bytes());
+        chunk.extend_from_slice(b": ");
+        chunk.extend_from_slice(&trailer.value);
+        chunk.extend_from_slice(b"\r\n");
+    }
+    chunk.extend_from_slice(b"\r\n");
+    chunk
+}
+
+///
+/// Relay the current message body of `reader` from `from` to `to`
+/// 
+/// # Notes
+/// Chunked body is decoded and encoded again, so that only complete messages are relayed
+pub async fn relay_body<R: Read + Unpin, W: Write + Unpin>(reader: &mut HttpMessageReader, from: &mut R, to: &mut W) -> Result<(), HttpStreamError> {
+    let chunked = reader.body_length() == BodyLength::Chunked;
+
+    while let Some(chunk) = reader.read_body_chunk(from).await? {
+        if chunk.is_empty() {
+            continue;
+        }
+
+        if chunked {
+            to.write_all(&encode_chunk(&chunk)).await?;
+        } else {
+            to.write_all(&chunk).await?;
+        }
+    }
+
+    if chunked {
+        to.write_all(&encode_last_chunk(reader.trailers())).await?;
+    }
+
+    to.flush().await?;
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn read_all_requests(raw: &[u8]) -> Vec<(HttpRequestHead, Vec<u8>)> {
+        async_std::task::block_on(async {
+            let mut stream = async_std::io::Cursor::new(raw.to_vec());
+            let mut reader = HttpMessageReader::new();
+            let mut requests = vec![];
+
+            while let Some(head) = reader.read_request_head(&mut stream).await.unwrap() {
+                let body = reader.read_body(&mut stream).await.unwrap();
+                requests.push((head, body));
+            }
+
+            requests
+        })
+    }
+
+    #[test]
+    fn parse_pipelined_requests() {
+        let requests = read_all_requests(
+            b"POST http://a.com/x HTTP/1.1\r\nHost: a.com\r\nContent-Length: 5\r\n\r\nhelloGET http://a.com/y HTTP/1.1\r\nHost: a.com\r\n\r\n"
+        );
+
+        assert_eq!(requests.len(), 2);
+        assert_eq!(requests[0].0.path, "http://a.com/x");
+        assert_eq!(requests[0].1, b"hello");
+        assert_eq!(requests[1].0.method, "GET");
+        assert!(requests[1].1.is_empty());
+    }
+
+    #[test]
+    fn remove_request_header() {
+        let mut head = parse_request_head(b"GET http://a.com/ HTTP/1.1\r\nHost: a.com\r\nProxy-Authorization: Basic YTpi\r\n\r\n").unwrap().unwrap();
+        head.remove_header("proxy-authorization");
+
+        assert!(find_header(&head.headers, "proxy-authorization").is_none());
+        assert_eq!(head.raw, b"GET http://a.com/ HTTP/1.1\r\nHost: a.com\r\n\r\n".to_vec());
+    }
+
+    #[test]
+    fn parse_chunked_body_with_trailers() {
+        let requests = read_all_requests(
+            b"POST / HTTP/1.1\r\nTransfer-Encoding: chunked\r\n\r\n5;ext=1\r\nhello\r\n6\r\n world\r\n0\r\nChecksum: abc\r\n\r\nGET / HTTP/1.1\r\n\r\n"
+        );
+
+        assert_eq!(requests.len(), 2);
+        assert_eq!(requests[0].1, b"hello world");
+        assert_eq!(requests[1].0.method, "GET");
+    }
+
+    #[test]
+    fn sniff_http_request_lines() {
+        assert_eq!(sniff_http_request(b"GET / HTTP/1.1\r\n"), Some(true));
+        assert_eq!(sniff_http_request(b"OPTIONS * HTTP/1.1\r\n"), Some(true));
+        assert_eq!(sniff_http_request(b"POS"), None);
+        assert_eq!(sniff_http_request(b"HELLO / HTTP/1.1"), Some(false));
+        assert_eq!(sniff_http_request(b"SSH-2.0-OpenSSH"), Some(false));
+        assert_eq!(sniff_http_request(b"\x16\x03\x01\x02\x00"), Some(false));
+        assert_eq!(sniff_http_request(b"CONNECTED"), Some(false));
+    }
+
+    #[test]
+    fn reject_malformed_and_oversized_heads() {
+        let status = |raw: Vec<u8>| async_std::task::block_on(async {
+            let mut stream = async_std::io::Cursor::new(raw);
+            HttpMessageReader::new().read_request_head(&mut stream).await.err().and_then(|err| err.status())
+        });
+
+        assert_eq!(status(b"GET / HTTP/1.1\r\nContent-Length: 1, 2\r\n\r\n".to_vec()), Some(400));
+        assert_eq!(status(b"GET\x01 / HTTP/1.1\r\n\r\n".to_vec()), Some(400));
+
+        let mut huge = b"GET / HTTP/1.1\r\n".to_vec();
+        huge.extend(std::iter::repeat_n(b'a', MAX_HEAD_SIZE + 1));
+        assert_eq!(status(huge), Some(431));
+
+        let mut many = b"GET / HTTP/1.1\r\n".to_vec();
+        for i in 0..MAX_HEADER_COUNT + 1 {
+            many.extend_from_slice(format!("x-{}: 1\r\n", i).as_bytes());
+        }
+        many.extend_from_slice(b"\r\n");
+        assert_eq!(status(many), Some(431));
+    }
+}
@@ -0,0 +1,81 @@
+use std::pin::Pin;
+use std::sync::Arc;
+use std::task::{Context, Poll};
+
+use async_std::io::{Read, Write};
+use async_std::net::TcpStream;
+
+///
+/// Stream of proxy client, the stream of an intercepted https connection is decrypted by lopxy
+///
+pub enum ClientStream {
+    Plain(TcpStream),
+    Tls(Box<futures_rustls::server::TlsStream<TcpStream>>),
+}
+
+impl ClientStream {
+    ///
+    /// Get the underlying tcp stream
+    ///
+    pub fn tcp<'a>(&'a self) -> &'a TcpStream {
+        match self {
+            ClientStream::Plain(stream) => stream,
+            ClientStream::Tls(stream) => stream.get_ref().0
+        }
+    }
+
+    pub fn is_tls(&self) -> bool {
+        matches!(self, ClientStream::Tls(_))
+    }
+
+    ///
+    /// Accept TLS handshake of proxy client with the certificate in `server_config`
+    ///
+    /// # Notes
+    /// The stream keeps plain if the handshake fails, but the connection is not usable any more
+    pub async fn accept_tls(&mut self, server_config: Arc<rustls::ServerConfig>) -> std::io::Result<()> {
+        let stream = match self {
+            ClientStream::Plain(stream) => stream.clone(),
+            ClientStream::Tls(_) => {
+                return Ok(());
+            }
+        };
+
+        let tls_stream = futures_rustls::TlsAcceptor::from(server_config).accept(stream).await?;
+        *self = ClientStream::Tls(Box::new(tls_stream));
+
+        Ok(())
+    }
+}
+
+impl Read for ClientStream {
+    fn poll_read(self: Pin<&mut Self>, cx: &mut Context<'_>, buf: &mut [u8]) -> Poll<std::io::Result<usize>> {
+        match self.get_mut() {
+            ClientStream::Plain(stream) => Pin::new(stream).poll_read(cx, buf),
+            ClientStream::Tls(stream) => Pin::new(stream.as_mut()).poll_read(cx, buf)
+        }
+    }
+}
+
+impl Write for ClientStream {
+    fn poll_write(self: Pin<&mut Self>, cx: &mut Context<'_>, buf: &[u8]) -> Poll<std::io::Result<usize>> {
+        match self.get_mut() {
+            ClientStream::Plain(stream) => Pin::new(stream).poll_write(cx, buf),
+            ClientStream::Tls(stream) => Pin::new(stream.as_mut()).poll_write(cx, buf)
+        }
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<std::io::Result<()>> {
+        match self.get_mut() {
+            ClientStream::Plain(stream) => Pin::new(stream).poll_flush(cx),
+            ClientStream::Tls(stream) => Pin::new(stream.as_mut()).poll_flush(cx)
+        }
+    }
+
+    fn poll_close(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<std::io::Result<()>> {
+        match self.get_mut() {
+            ClientStream::Plain(stream) => Pin::new(stream).poll_close(cx),
+            ClientStream::Tls(stream) => Pin::new(stream.as_mut()).poll_close(cx)
+        }
+    }
+}
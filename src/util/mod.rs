@@ -1,4 +1,5 @@
 pub mod config;
 pub mod async_helper;
 pub mod daemon;
-pub mod netstat;
\ No newline at end of file
+pub mod netstat;
+pub mod network;
\ No newline at end of file
@@ -0,0 +1,117 @@
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr};
+
+///
+/// Ip network like `10.0.0.0/8` or `fd00::/8`, a bare ip address is a network of itself
+///
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct IpNetwork {
+    address: IpAddr,
+    prefix_length: u8,
+}
+
+impl IpNetwork {
+    pub fn new(address: IpAddr, prefix_length: u8) -> Option<IpNetwork> {
+        match prefix_length <= IpNetwork::max_prefix_length(&address) {
+            true => Some(IpNetwork { address, prefix_length }),
+            false => None
+        }
+    }
+
+    pub fn parse(network: &str) -> Option<IpNetwork> {
+        let network = network.trim();
+        let (address, prefix_length) = match network.split_once('/') {
+            Some((address, prefix_length)) => (address, Some(prefix_length.parse::<u8>().ok()?)),
+            None => (network, None)
+        };
+
+        let address = address.trim_start_matches('[').trim_end_matches(']').parse::<IpAddr>().ok()?;
+        IpNetwork::new(address, prefix_length.unwrap_or(IpNetwork::max_prefix_length(&address)))
+    }
+
+    fn max_prefix_length(address: &IpAddr) -> u8 {
+        match address {
+            IpAddr::V4(_) => 32,
+            IpAddr::V6(_) => 128
+        }
+    }
+
+    ///
+    /// Check whether `address` is in the network, ipv4-mapped ipv6 addresses match ipv4 networks
+    ///
+    pub fn contains(&self, address: &IpAddr) -> bool {
+        let (network, address, bits) = match (&self.address, canonical_ip(address)) {
+            (IpAddr::V4(network), IpAddr::V4(address)) => (u32::from(*network) as u128, u32::from(address) as u128, 32),
+            (IpAddr::V6(network), IpAddr::V6(address)) => (u128::from(*network), u128::from(address), 128),
+            _ => {
+                return false;
+            }
+        };
+
+        if self.prefix_length == 0 {
+            return true;
+        }
+
+        let shift = bits - self.prefix_length as u32;
+        (network >> shift) == (address >> shift)
+    }
+}
+
+///
+/// Convert ipv4-mapped ipv6 address like `::ffff:192.168.1.2` to ipv4 address
+///
+pub fn canonical_ip(address: &IpAddr) -> IpAddr {
+    match address {
+        IpAddr::V6(v6) => match v6.octets() {
+            [0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0xff, 0xff, a, b, c, d] => IpAddr::V4(Ipv4Addr::new(a, b, c, d)),
+            _ => *address
+        },
+        IpAddr::V4(_) => *address
+    }
+}
+
+///
+/// Get the address which local clients use to connect a server listening on `listen_addresses`
+///
+/// # Notes
+/// Loopback address is preferred if the server listens on it or on an unspecified address
+pub fn local_connect_address(listen_addresses: &[IpAddr], port: u32) -> String {
+    let address = if listen_addresses.iter().any(|address: &IpAddr| (address.is_unspecified() && address.is_ipv4()) || *address == IpAddr::V4(Ipv4Addr::LOCALHOST)) {
+        IpAddr::V4(Ipv4Addr::LOCALHOST)
+    } else if listen_addresses.iter().any(|address: &IpAddr| address.is_unspecified() || address.is_loopback()) {
+        IpAddr::V6(Ipv6Addr::LOCALHOST)
+    } else {
+        listen_addresses.first().cloned().unwrap_or(IpAddr::V4(Ipv4Addr::LOCALHOST))
+    };
+
+    SocketAddr::new(address, port as u16).to_string()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn match_ip_networks() {
+        let network = IpNetwork::parse("192.168.1.0/24").unwrap();
+        assert!(network.contains(&"192.168.1.20".parse().unwrap()));
+        assert!(network.contains(&"::ffff:192.168.1.20".parse().unwrap()));
+        assert!(!network.contains(&"192.168.2.1".parse().unwrap()));
+
+        let network = IpNetwork::parse("[fd00::]/8").unwrap();
+        assert!(network.contains(&"fd12::1".parse().unwrap()));
+        assert!(!network.contains(&"10.0.0.1".parse().unwrap()));
+
+        assert!(IpNetwork::parse("0.0.0.0/0").unwrap().contains(&"8.8.8.8".parse().unwrap()));
+        assert!(IpNetwork::parse("10.0.0.1").unwrap().contains(&"10.0.0.1".parse().unwrap()));
+        assert_eq!(IpNetwork::parse("10.0.0.0/33"), None);
+        assert_eq!(IpNetwork::parse("example.com"), None);
+    }
+
+    #[test]
+    fn pick_local_connect_address() {
+        assert_eq!(local_connect_address(&["0.0.0.0".parse().unwrap()], 8283), "127.0.0.1:8283");
+        assert_eq!(local_connect_address(&["::".parse().unwrap()], 8283), "[::1]:8283");
+        assert_eq!(local_connect_address(&["192.168.1.2".parse().unwrap(), "::1".parse().unwrap()], 8283), "[::1]:8283");
+        assert_eq!(local_connect_address(&["192.168.1.2".parse().unwrap()], 8283), "192.168.1.2:8283");
+    }
+}
@@ -4,7 +4,12 @@
 
 const lopxy = (function() {
 
-    function lopxy_init_failed() {
+    function lopxy_init_failed(xhr) {
+        if (xhr && xhr.status == 401) {
+            $("#lopxy-init-status").text("Unauthorized, Please Open by `lopxy manager`");
+            return;
+        }
+
         $("#lopxy-init-status").text("Lopxy Init Failed");
     }
 
@@ -48,12 +53,24 @@ const lopxy = (function() {
             let newItemInfo = {
                 resource_url: $("#lopxy-add-proxy-item-resource-url").val(),
                 proxy_resource_url: $("#lopxy-add-proxy-item-proxy-resource-url").val(),
-                content_type: $("#lopxy-add-proxy-item-content-type").val()
+                content_type: $("#lopxy-add-proxy-item-content-type").val(),
+                match_kind: $("#lopxy-add-proxy-item-match-kind").val(),
+                mode: $("#lopxy-add-proxy-item-mode").val(),
+                mirror_urls: split_mirror_urls($("#lopxy-add-proxy-item-mirrors").val()),
+                mirror_strategy: $("#lopxy-add-proxy-item-mirror-strategy").val(),
+                upstream_proxy: $("#lopxy-add-proxy-item-upstream-proxy").val().trim(),
+                rule_set: $("#lopxy-add-proxy-item-rule-set").val().trim()
             };
 
             $("#lopxy-add-proxy-item-resource-url").val('');
             $("#lopxy-add-proxy-item-proxy-resource-url").val('');
-            $("#lopxy-add-proxy-item-content-type").val('application/octet-stream');
+            $("#lopxy-add-proxy-item-content-type").val('auto');
+            $("#lopxy-add-proxy-item-match-kind").val('exact');
+            $("#lopxy-add-proxy-item-mode").val('replace');
+            $("#lopxy-add-proxy-item-mirrors").val('');
+            $("#lopxy-add-proxy-item-mirror-strategy").val('ordered');
+            $("#lopxy-add-proxy-item-upstream-proxy").val('');
+            $("#lopxy-add-proxy-item-rule-set").val('');
 
             if (!newItemInfo.resource_url.length || !newItemInfo.proxy_resource_url.length || !newItemInfo.content_type.length) {
                 return;
@@ -68,10 +85,22 @@ const lopxy = (function() {
             let resourceUrl = $(children[0]).text();
             let proxyResourceUrl = $(children[1]).text();
             let contentType = $(children[2]).text();
+            let matchKind = $(children[3]).text();
+            let mode = $(children[4]).text();
+            let mirrors = $(children[5]).text();
+            let mirrorStrategy = $(children[6]).text();
+            let upstreamProxy = $(children[7]).text();
+            let ruleSet = $(children[8]).text();
 
             $("#lopxy-modify-proxy-item-resource-url").text(resourceUrl);
             $("#lopxy-modify-proxy-item-proxy-resource-url").val(proxyResourceUrl);
             $("#lopxy-modify-proxy-item-content-type").val(contentType);
+            $("#lopxy-modify-proxy-item-match-kind").val(matchKind);
+            $("#lopxy-modify-proxy-item-mode").val(mode);
+            $("#lopxy-modify-proxy-item-mirrors").val(mirrors);
+            $("#lopxy-modify-proxy-item-mirror-strategy").val(mirrorStrategy);
+            $("#lopxy-modify-proxy-item-upstream-proxy").val(upstreamProxy);
+            $("#lopxy-modify-proxy-item-rule-set").val(ruleSet);
 
             $("#lopxy-modify-proxy-item-dialog").attr("target-proxy-item-name", resourceUrl);
             $("#lopxy-modify-proxy-item-dialog").modal('show');
@@ -83,7 +112,13 @@ const lopxy = (function() {
             let newItemInfo = {
                 resource_url: $("#lopxy-modify-proxy-item-resource-url").text(),
                 proxy_resource_url: $("#lopxy-modify-proxy-item-proxy-resource-url").val(),
-                content_type: $("#lopxy-modify-proxy-item-content-type").val()
+                content_type: $("#lopxy-modify-proxy-item-content-type").val(),
+                match_kind: $("#lopxy-modify-proxy-item-match-kind").val(),
+                mode: $("#lopxy-modify-proxy-item-mode").val(),
+                mirror_urls: split_mirror_urls($("#lopxy-modify-proxy-item-mirrors").val()),
+                mirror_strategy: $("#lopxy-modify-proxy-item-mirror-strategy").val(),
+                upstream_proxy: $("#lopxy-modify-proxy-item-upstream-proxy").val().trim(),
+                rule_set: $("#lopxy-modify-proxy-item-rule-set").val().trim()
             };
 
             let itemTag = get_proxy_item(newItemInfo.resource_url);
@@ -93,7 +128,9 @@ const lopxy = (function() {
                 return;
             }
 
-            if (newItemInfo.proxy_resource_url == itemInfo.proxy_resource_url && newItemInfo.content_type == itemInfo.content_type) {
+            if (newItemInfo.proxy_resource_url == itemInfo.proxy_resource_url && newItemInfo.content_type == itemInfo.content_type && newItemInfo.match_kind == itemInfo.match_kind && newItemInfo.mode == itemInfo.mode &&
+                newItemInfo.mirror_urls.join('\n') == itemInfo.mirror_urls.join('\n') && newItemInfo.mirror_strategy == itemInfo.mirror_strategy &&
+                newItemInfo.upstream_proxy == itemInfo.upstream_proxy && newItemInfo.rule_set == itemInfo.rule_set) {
                 return;
             }
 
@@ -112,6 +149,14 @@ const lopxy = (function() {
             self.remove_lopxy_proxy_item(targetProxyItemName);
         });
 
+        //
+        // bypass list
+        //
+
+        $('#lopxy-save-bypass-list-btn').on('click', function() {
+            self.save_lopxy_bypass_list(split_mirror_urls($("#lopxy-bypass-list").val()));
+        });
+
         //
         // request status
         //
@@ -141,6 +186,10 @@ const lopxy = (function() {
         });
     }
 
+    function split_mirror_urls(mirrors) {
+        return mirrors.split('\n').map((url) => url.trim()).filter((url) => url.length);
+    }
+
     function create_request_status_log_item(item) {
         function getLocalTime(ms) {  
             return new Date(parseInt(ms)).toLocaleString().replace(/:\d{1,2}$/,' ');  
@@ -149,6 +198,7 @@ const lopxy = (function() {
     <td>${getLocalTime(item.timestamp)}</td>
     <td>${item.bin_name}</td>
     <td>${item.pid}</td>
+    <td>${item.username || ''}</td>
     <td>${item.path}</td>
     <td>${item.status}</td>
 </tr>`;
@@ -175,6 +225,12 @@ const lopxy = (function() {
     <td>${item.resource_url}</td>
     <td>${item.proxy_resource_url}</td>
     <td>${item.content_type}</td>
+    <td>${item.match_kind}</td>
+    <td>${item.mode}</td>
+    <td style="white-space: pre-line;">${item.mirror_urls.join('\n')}</td>
+    <td>${item.mirror_strategy}</td>
+    <td>${item.upstream_proxy || ''}</td>
+    <td>${item.rule_set || ''}</td>
     <td>
         <button type="button" class="btn btn-info btn-sm lopxy-modify-proxy-item-btn">修改</button>
         <button type="button" class="btn btn-danger btn-sm lopxy-remove-proxy-item-btn">删除</button>
@@ -188,6 +244,12 @@ const lopxy = (function() {
         $(children[0]).text(item['resource_url']);
         $(children[1]).text(item['proxy_resource_url']);
         $(children[2]).text(item['content_type']);
+        $(children[3]).text(item['match_kind']);
+        $(children[4]).text(item['mode']);
+        $(children[5]).text(item['mirror_urls'].join('\n'));
+        $(children[6]).text(item['mirror_strategy']);
+        $(children[7]).text(item['upstream_proxy'] || '');
+        $(children[8]).text(item['rule_set'] || '');
     }
 
     function get_proxy_item(itemName) {
@@ -208,7 +270,13 @@ const lopxy = (function() {
         return {
             resource_url: $(children[0]).text(),
             proxy_resource_url: $(children[1]).text(),
-            content_type: $(children[2]).text()
+            content_type: $(children[2]).text(),
+            match_kind: $(children[3]).text(),
+            mode: $(children[4]).text(),
+            mirror_urls: split_mirror_urls($(children[5]).text()),
+            mirror_strategy: $(children[6]).text(),
+            upstream_proxy: $(children[7]).text(),
+            rule_set: $(children[8]).text()
         };
     }
 
@@ -230,6 +298,28 @@ const lopxy = (function() {
         }
     }
 
+    function update_target_states(targetStates) {
+        function getLocalTime(ms) {
+            return new Date(parseInt(ms)).toLocaleString().replace(/:\d{1,2}$/,' ');
+        }
+
+        let tbody = $("#lopxy-target-states-table tbody");
+        tbody.empty();
+
+        for (let state of targetStates) {
+            let row = $(`<tr>
+    <td></td>
+    <td>${state.healthy ? "正常" : "异常"}</td>
+    <td>${state.latency == null ? "-" : state.latency + "ms"}</td>
+    <td>${getLocalTime(state.checked_timestamp)}</td>
+    <td></td>
+</tr>`);
+            $(row.children()[0]).text(state.target);
+            $(row.children()[4]).text(state.error || "");
+            tbody.append(row);
+        }
+    }
+
     function update_proxy_enabled_button_status(enabled) {
         if (enabled) {
             $("#lopxy-proxy-enabled").text("启用");
@@ -254,6 +344,9 @@ const lopxy = (function() {
 
         $("#lopxy-web-manager-port").text(this.env.webManagerPort);
         $("#lopxy-proxy-server-port").text(this.env.proxyPort);
+        $("#lopxy-pac-url").text(`${window.location.origin}/proxy.pac`);
+
+        update_target_states(lopxy_status['target_states']);
 
         if (lopxy_status['updated']) {
             if (this.env.statusUpdateTimestamp != lopxy_status['status_log_timestamp']) {
@@ -267,6 +360,7 @@ const lopxy = (function() {
                 this.env.configTimestamp = lopxy_status['config_timestamp'];
                 this.env.proxyItems = lopxy_status['proxy_items'];
                 update_proxy_item_list(this.env.proxyItems);
+                request_refresh_bypass_list();
             }
         }
 
@@ -292,6 +386,22 @@ const lopxy = (function() {
         });
     }
 
+    function request_refresh_bypass_list() {
+        // keep the entries being edited
+        if ($("#lopxy-bypass-list").is(":focus")) {
+            return;
+        }
+
+        $.ajax({
+            type: 'get',
+            url: '/bypass',
+            success: (entries) => {
+                $("#lopxy-bypass-list").removeClass("is-invalid");
+                $("#lopxy-bypass-list").val(entries.join('\n'));
+            }
+        });
+    }
+
     function request_set_lopxy_proxy_enabled(enabled) {
         $.ajax({
             type: 'post',
@@ -343,8 +453,8 @@ const lopxy = (function() {
                     into_lopxy_web_manager_page.bind(this)();
                     this.start_lopxy_status_monitor();
                 },
-                error: () => {
-                    lopxy_init_failed();
+                error: (xhr) => {
+                    lopxy_init_failed(xhr);
                 }
             })
         },        
@@ -352,7 +462,7 @@ const lopxy = (function() {
             this.stop_lopxy_status_monitor();
             $("#lopxy-shutdown-dialog-trigger").attr("disabled", true);
             $.ajax({
-                type: 'get',
+                type: 'post',
                 url: '/shutdown',
                 success: (lopxy_status) => {
                     into_lopxy_shutdown_page();
@@ -395,7 +505,13 @@ const lopxy = (function() {
                 data: {
                     resource: encodeURI(newItem.resource_url),
                     resource_proxy: encodeURI(newItem.proxy_resource_url),
-                    resource_content_type: newItem.content_type
+                    resource_content_type: newItem.content_type,
+                    match_kind: newItem.match_kind,
+                    mode: newItem.mode,
+                    mirrors: encodeURI(newItem.mirror_urls.join('\n')),
+                    mirror_strategy: newItem.mirror_strategy,
+                    upstream_proxy: encodeURI(newItem.upstream_proxy),
+                    rule_set: encodeURI(newItem.rule_set)
                 },
                 success: (status) => {
                     if (!status.result) {
@@ -413,7 +529,13 @@ const lopxy = (function() {
                 data: {
                     resource: encodeURI(newItemInfo.resource_url),
                     resource_proxy: encodeURI(newItemInfo.proxy_resource_url),
-                    resource_content_type: newItemInfo.content_type
+                    resource_content_type: newItemInfo.content_type,
+                    match_kind: newItemInfo.match_kind,
+                    mode: newItemInfo.mode,
+                    mirrors: encodeURI(newItemInfo.mirror_urls.join('\n')),
+                    mirror_strategy: newItemInfo.mirror_strategy,
+                    upstream_proxy: encodeURI(newItemInfo.upstream_proxy),
+                    rule_set: encodeURI(newItemInfo.rule_set)
                 },
                 success: (status) => {
                     if (!status.result) {
@@ -424,6 +546,24 @@ const lopxy = (function() {
                 }
             });
         },
+        save_lopxy_bypass_list: function(entries) {
+            $.ajax({
+                type: 'post',
+                url: '/bypass',
+                data: {
+                    entries: encodeURI(entries.join('\n'))
+                },
+                success: (status) => {
+                    if (!status.result) {
+                        $("#lopxy-bypass-list").addClass("is-invalid");
+                        return;
+                    }
+
+                    $("#lopxy-bypass-list").removeClass("is-invalid");
+                    $("#lopxy-bypass-list").val(entries.join('\n'));
+                }
+            });
+        },
         remove_lopxy_proxy_item: function(targetProxyItemName) {
             let targetProxyItem = $(`#lopxy-proxy-items-table tbody tr[proxy-item='${targetProxyItemName}']`);
             if (targetProxyItem.length == 0) {